@@ -210,25 +210,24 @@ pub mod host {
         /// [`auth::server_bus`] bindings.
         pub mod server_bus {
             use externref::Resource;
-            use tq_db::realm::Realm;
+            use tq_db::realm::{Realm, RealmStatus};
 
             /// [`auth::server_bus::check`] bindings.
             #[cfg(target_arch = "wasm32")]
-            pub fn check(realm: &Realm) -> Result<(), tq_network::Error> {
+            pub fn check(realm: &Realm) -> RealmStatus {
                 let archived = rkyv::to_bytes::<_, 64>(realm).unwrap();
                 let res = unsafe {
                     let archived = crate::encode_ptr_len(archived.as_ptr() as *mut u8, archived.len());
                     crate::auth_server_bus_check(archived)
                 };
-                if res == 0 {
-                    Ok(())
-                } else {
-                    Err(tq_network::Error::Other(String::from("Server Down")))
-                }
+                u8::try_from(res)
+                    .ok()
+                    .and_then(RealmStatus::from_u8)
+                    .unwrap_or_default()
             }
 
             #[cfg(not(target_arch = "wasm32"))]
-            pub fn check(_realm: &Realm) -> Result<(), tq_network::Error> {
+            pub fn check(_realm: &Realm) -> RealmStatus {
                 unimplemented!("Not implemented on non-wasm32")
             }
 
@@ -0,0 +1,167 @@
+use crate::seal::{self, Seal};
+use crate::{ids, Error, Request, Secret, HANDSHAKE_TIMEOUT};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::StreamExt;
+use tq_codec::{TQCodec, TQDecoder, TQEncoder};
+use tq_crypto::NopCipher;
+use tq_network::{PacketDecode, PacketEncode};
+
+type Pending = Arc<Mutex<HashMap<u32, oneshot::Sender<Result<(u16, Bytes), Error>>>>>;
+
+/// How many requests could wait to be written.
+const QUEUE_SIZE: usize = 64;
+
+/// A connection to a bus server, cheap to clone, every clone shares the same
+/// connection.
+///
+/// The requests are written in order, but the responses come back as soon as
+/// they are ready, so a slow request does not hold the others.
+#[derive(Debug, Clone)]
+pub struct Client {
+    tx: mpsc::Sender<(u16, u32, Bytes)>,
+    pending: Pending,
+    next_request_id: Arc<AtomicU32>,
+    closed: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Waits that long for any response by default.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Authenticates with the server on the other end of `stream`, gives up
+    /// after [`HANDSHAKE_TIMEOUT`].
+    pub async fn connect<S>(stream: S, secret: &Secret) -> Result<Self, Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (mut encoder, mut decoder) = TQCodec::new(stream, NopCipher).split();
+        let (sealer, opener) =
+            tokio::time::timeout(HANDSHAKE_TIMEOUT, seal::connect(&mut encoder, &mut decoder, secret))
+                .await
+                .map_err(|_| Error::TimedOut)??;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let pending = Pending::default();
+        let closed = Arc::new(AtomicBool::new(false));
+        tokio::spawn(write_requests(encoder, sealer, rx));
+        tokio::spawn(read_responses(decoder, opener, pending.clone(), closed.clone()));
+        Ok(Self {
+            tx,
+            pending,
+            next_request_id: Arc::new(AtomicU32::new(1)),
+            closed,
+            timeout: Self::DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Waits that long for any response, instead of
+    /// [`Client::DEFAULT_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether the connection is gone, a new one has to be made.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire) || self.tx.is_closed()
+    }
+
+    /// Sends `request` and waits for its response.
+    pub async fn request<R: Request>(&self, request: &R) -> Result<R::Response, Error> {
+        let (id, payload) = request.encode()?;
+        let (id, payload) = self.call(id, payload).await?;
+        if id != <R::Response as tq_network::PacketID>::PACKET_ID {
+            return Err(Error::UnexpectedResponse(id));
+        }
+        Ok(<R::Response as PacketDecode>::decode(&payload)?)
+    }
+
+    /// Sends a raw request and waits for its response.
+    pub async fn call(&self, packet_id: u16, payload: Bytes) -> Result<(u16, Bytes), Error> {
+        if self.is_closed() {
+            return Err(Error::Closed);
+        }
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().expect("not poisoned").insert(request_id, tx);
+        // The responses reader could have just stopped, after the check above.
+        if self.closed.load(Ordering::Acquire) {
+            self.pending.lock().expect("not poisoned").remove(&request_id);
+            return Err(Error::Closed);
+        }
+        if self.tx.send((packet_id, request_id, payload)).await.is_err() {
+            self.pending.lock().expect("not poisoned").remove(&request_id);
+            return Err(Error::Closed);
+        }
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(response)) => response,
+            // The connection is gone along with the pending requests.
+            Ok(Err(_)) => Err(Error::Closed),
+            Err(_) => {
+                self.pending.lock().expect("not poisoned").remove(&request_id);
+                Err(Error::TimedOut)
+            },
+        }
+    }
+}
+
+async fn write_requests<S>(
+    mut encoder: TQEncoder<S, NopCipher>,
+    mut sealer: Seal,
+    mut rx: mpsc::Receiver<(u16, u32, Bytes)>,
+) where
+    S: AsyncRead + AsyncWrite,
+{
+    while let Some((packet_id, request_id, payload)) = rx.recv().await {
+        let body = sealer.seal(packet_id, request_id, &payload);
+        if let Err(e) = encoder.send((packet_id, body)).await {
+            tracing::warn!(error = ?e, "Failed to send bus request");
+            return;
+        }
+    }
+    // Every client is gone.
+    let _ = encoder.close().await;
+}
+
+async fn read_responses<S>(
+    mut decoder: TQDecoder<S, NopCipher>,
+    mut opener: Seal,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(packet) = decoder.next().await {
+        let (packet_id, body) = match packet {
+            Ok(packet) => packet,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Failed to read bus response");
+                break;
+            },
+        };
+        let (request_id, payload) = match opener.open(packet_id, body) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(error = %e, packet_id, "Bad bus response, closing the connection");
+                break;
+            },
+        };
+        let response = match packet_id {
+            ids::ERROR => Err(Error::Remote(String::from_utf8_lossy(&payload).into_owned())),
+            _ => Ok((packet_id, payload)),
+        };
+        // Nobody is waiting if it timed out.
+        if let Some(tx) = pending.lock().expect("not poisoned").remove(&request_id) {
+            let _ = tx.send(response);
+        }
+    }
+    closed.store(true, Ordering::Release);
+    // Fails every request still waiting.
+    pending.lock().expect("not poisoned").clear();
+}
@@ -0,0 +1,49 @@
+#[derive(Debug)]
+pub enum Error {
+    IO(std::io::Error),
+    TQNetwork(tq_network::Error),
+    /// The other side does not know the secret, or the handshake went wrong.
+    Unauthenticated,
+    /// A packet failed its seal, it was changed or is out of order.
+    BadSeal,
+    /// A packet is too short to be a bus packet.
+    BadFrame,
+    /// The connection is closed.
+    Closed,
+    /// No response arrived in time.
+    TimedOut,
+    /// The other side failed to handle the request.
+    Remote(String),
+    /// The response is not the one the request expects.
+    UnexpectedResponse(u16),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "IO Error: {}", e),
+            Self::TQNetwork(e) => write!(f, "TQNetwork Error: {}", e),
+            Self::Unauthenticated => write!(f, "Bus authentication failed"),
+            Self::BadSeal => write!(f, "Bad bus packet seal"),
+            Self::BadFrame => write!(f, "Bad bus packet"),
+            Self::Closed => write!(f, "Bus connection closed"),
+            Self::TimedOut => write!(f, "Bus request timed out"),
+            Self::Remote(e) => write!(f, "Bus request failed: {}", e),
+            Self::UnexpectedResponse(id) => write!(f, "Unexpected bus response #{}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<tq_network::Error> for Error {
+    fn from(e: tq_network::Error) -> Self {
+        Self::TQNetwork(e)
+    }
+}
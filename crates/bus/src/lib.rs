@@ -0,0 +1,68 @@
+//! The inter-server bus, how the servers talk to each other away from the
+//! game clients.
+//!
+//! Every server that answers requests (like a game server handing out login
+//! tokens) listens on its own bus port, see [`serve`], and the others keep a
+//! long lived [`Client`] connected to it, sending as many requests as they
+//! like over it at the same time.
+//!
+//! The packets are framed like the game ones (see [`tq_codec`]), without any
+//! encryption. Before anything else, both sides prove they know the shared
+//! [`Secret`] without sending it:
+//!
+//! ```text
+//! Client -> Server: Hello     | client nonce (16) |
+//! Server -> Client: Challenge | server nonce (16) | HMAC(secret, "server" + nonces) (32) |
+//! Client -> Server: Proof     | HMAC(secret, "client" + nonces) (32) |
+//! ```
+//!
+//! After that, every packet body carries the request id it belongs to and is
+//! sealed with a session key derived from the nonces:
+//!
+//! ```text
+//! | request id (4) | payload | HMAC(key, direction + sequence + packet id + request id + payload) (32) |
+//! ```
+//!
+//! The sequence number counts the packets sent in that direction, so a packet
+//! that is changed, dropped, replayed or reordered closes the connection.
+//!
+//! The bus is authenticated, not encrypted, keep it on a private network.
+
+mod error;
+pub use error::Error;
+
+mod secret;
+pub use secret::Secret;
+
+mod seal;
+
+mod client;
+pub use client::Client;
+
+mod server;
+pub use server::{serve, serve_stream, Handler};
+
+pub mod messages;
+
+use core::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tq_network::PacketID;
+
+/// Both sides give up on a peer that does not finish the handshake by then.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The packet ids the bus itself uses, the [`messages`] start after them.
+pub(crate) mod ids {
+    pub const HELLO: u16 = 1;
+    pub const CHALLENGE: u16 = 2;
+    pub const PROOF: u16 = 3;
+    /// The [`Handler`](crate::Handler) failed, the body is the error message.
+    pub const ERROR: u16 = 4;
+}
+
+/// A request sent with [`Client::request`], and what the other side answers
+/// with.
+pub trait Request: Serialize + PacketID + Send + Sync {
+    type Response: DeserializeOwned + PacketID;
+}
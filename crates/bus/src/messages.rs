@@ -0,0 +1,89 @@
+//! The requests and responses on the bus.
+
+use crate::Request;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+use tq_network::{PacketID, PacketInfo};
+
+/// Every message on the bus, their ids come after the ones of the bus itself.
+pub const MESSAGES: &[PacketInfo] = tq_network::packets![
+    Ping,
+    Pong,
+    LoginTokenRequest,
+    LoginToken,
+    RealmHeartbeat,
+    RealmHeartbeatAck,
+];
+
+/// Checks that the other side is up and answering.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 10)]
+pub struct Ping {
+    pub nonce: u32,
+}
+
+/// The answer to [`Ping`], with the same nonce.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 11)]
+pub struct Pong {
+    pub nonce: u32,
+}
+
+impl Request for Ping {
+    type Response = Pong;
+}
+
+/// Asks a game server for a one time login token, the account server sends
+/// it to the game client once it logs in.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 20)]
+pub struct LoginTokenRequest {
+    pub account_id: u32,
+    pub realm_id: u32,
+    /// The address of the game client, only that address could use the
+    /// token, anyone if empty.
+    pub client_ip: String,
+}
+
+/// The answer to [`LoginTokenRequest`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 21)]
+pub struct LoginToken {
+    pub token: u64,
+}
+
+impl Request for LoginTokenRequest {
+    type Response = LoginToken;
+}
+
+/// Sent by every game server to the account server, the first one registers
+/// the realm, and it is gone once they stop.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 30)]
+pub struct RealmHeartbeat {
+    pub realm_id: u32,
+    /// See `tq_db::realm::RealmStatus`.
+    pub status: u8,
+    /// The players in game.
+    pub population: u32,
+    /// How many players it takes.
+    pub capacity: u32,
+}
+
+impl RealmHeartbeat {
+    /// How often the game servers send it.
+    pub const INTERVAL: Duration = Duration::from_secs(5);
+    /// A realm that did not send any for that long is offline.
+    pub const TIMEOUT: Duration = Duration::from_secs(15);
+}
+
+/// The answer to [`RealmHeartbeat`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PacketID)]
+#[packet(id = 31)]
+pub struct RealmHeartbeatAck {
+    pub realm_id: u32,
+}
+
+impl Request for RealmHeartbeat {
+    type Response = RealmHeartbeatAck;
+}
@@ -0,0 +1,188 @@
+//! The bus handshake, and the seal of every packet after it, see the crate
+//! docs for how they look.
+
+use crate::{ids, Error, Secret};
+use bytes::{BufMut, Bytes, BytesMut};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::StreamExt;
+use tq_codec::{TQDecoder, TQEncoder};
+use tq_crypto::NopCipher;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+/// The request id before the payload.
+const HEAD_LEN: usize = 4;
+
+/// The direction of the packets a [`Seal`] is for.
+const CLIENT_TO_SERVER: u8 = b'c';
+const SERVER_TO_CLIENT: u8 = b's';
+
+fn mac(key: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+fn tag(key: &[u8], parts: &[&[u8]]) -> [u8; TAG_LEN] {
+    mac(key, parts).finalize().into_bytes().into()
+}
+
+/// Compares in constant time.
+fn verify(key: &[u8], parts: &[&[u8]], tag: &[u8]) -> bool {
+    mac(key, parts).verify_slice(tag).is_ok()
+}
+
+async fn recv<S: AsyncRead + AsyncWrite + Unpin>(decoder: &mut TQDecoder<S, NopCipher>) -> Result<(u16, Bytes), Error> {
+    match decoder.next().await {
+        Some(packet) => Ok(packet?),
+        None => Err(Error::Closed),
+    }
+}
+
+/// Seals the packets going one way, or opens them on the other side.
+#[derive(Debug)]
+pub(crate) struct Seal {
+    key: [u8; TAG_LEN],
+    direction: u8,
+    sequence: u64,
+}
+
+impl Seal {
+    fn new(key: [u8; TAG_LEN], direction: u8) -> Self {
+        Self {
+            key,
+            direction,
+            sequence: 0,
+        }
+    }
+
+    pub fn seal(&mut self, packet_id: u16, request_id: u32, payload: &[u8]) -> Bytes {
+        let sequence = self.sequence.to_le_bytes();
+        let tag = tag(
+            &self.key,
+            &[
+                &[self.direction],
+                &sequence,
+                &packet_id.to_le_bytes(),
+                &request_id.to_le_bytes(),
+                payload,
+            ],
+        );
+        self.sequence += 1;
+        let mut body = BytesMut::with_capacity(HEAD_LEN + payload.len() + TAG_LEN);
+        body.put_u32_le(request_id);
+        body.put_slice(payload);
+        body.put_slice(&tag);
+        body.freeze()
+    }
+
+    /// Checks the seal of a packet body, returns its request id and payload.
+    pub fn open(&mut self, packet_id: u16, mut body: Bytes) -> Result<(u32, Bytes), Error> {
+        if body.len() < HEAD_LEN + TAG_LEN {
+            return Err(Error::BadFrame);
+        }
+        let tag = body.split_off(body.len() - TAG_LEN);
+        let payload = body.split_off(HEAD_LEN);
+        let sequence = self.sequence.to_le_bytes();
+        let parts: [&[u8]; 5] = [&[self.direction], &sequence, &packet_id.to_le_bytes(), &body, &payload];
+        if !verify(&self.key, &parts, &tag) {
+            return Err(Error::BadSeal);
+        }
+        self.sequence += 1;
+        let request_id = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+        Ok((request_id, payload))
+    }
+}
+
+/// The client side of the handshake, returns the seals of the packets to
+/// send and of the ones to receive.
+pub(crate) async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    encoder: &mut TQEncoder<S, NopCipher>,
+    decoder: &mut TQDecoder<S, NopCipher>,
+    secret: &Secret,
+) -> Result<(Seal, Seal), Error> {
+    let secret = secret.as_bytes();
+    let client_nonce: [u8; NONCE_LEN] = rand::random();
+    encoder
+        .send((ids::HELLO, Bytes::copy_from_slice(&client_nonce)))
+        .await?;
+    let (id, body) = recv(decoder).await?;
+    if id != ids::CHALLENGE || body.len() != NONCE_LEN + TAG_LEN {
+        return Err(Error::Unauthenticated);
+    }
+    let (server_nonce, proof) = body.split_at(NONCE_LEN);
+    if !verify(secret, &[b"server", &client_nonce, server_nonce], proof) {
+        return Err(Error::Unauthenticated);
+    }
+    let proof = tag(secret, &[b"client", &client_nonce, server_nonce]);
+    encoder.send((ids::PROOF, Bytes::copy_from_slice(&proof))).await?;
+    let key = tag(secret, &[b"session", &client_nonce, server_nonce]);
+    Ok((Seal::new(key, CLIENT_TO_SERVER), Seal::new(key, SERVER_TO_CLIENT)))
+}
+
+/// The server side of the handshake, returns the seals of the packets to
+/// send and of the ones to receive.
+pub(crate) async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    encoder: &mut TQEncoder<S, NopCipher>,
+    decoder: &mut TQDecoder<S, NopCipher>,
+    secret: &Secret,
+) -> Result<(Seal, Seal), Error> {
+    let secret = secret.as_bytes();
+    let (id, client_nonce) = recv(decoder).await?;
+    if id != ids::HELLO || client_nonce.len() != NONCE_LEN {
+        return Err(Error::Unauthenticated);
+    }
+    let server_nonce: [u8; NONCE_LEN] = rand::random();
+    let mut challenge = BytesMut::with_capacity(NONCE_LEN + TAG_LEN);
+    challenge.put_slice(&server_nonce);
+    challenge.put_slice(&tag(secret, &[b"server", &client_nonce, &server_nonce]));
+    encoder.send((ids::CHALLENGE, challenge.freeze())).await?;
+    let (id, proof) = recv(decoder).await?;
+    if id != ids::PROOF || !verify(secret, &[b"client", &client_nonce, &server_nonce], &proof) {
+        return Err(Error::Unauthenticated);
+    }
+    let key = tag(secret, &[b"session", &client_nonce, &server_nonce]);
+    Ok((Seal::new(key, SERVER_TO_CLIENT), Seal::new(key, CLIENT_TO_SERVER)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_or_replayed_packets_are_rejected() {
+        let key = [7; TAG_LEN];
+        let mut sealer = Seal::new(key, CLIENT_TO_SERVER);
+        let mut opener = Seal::new(key, CLIENT_TO_SERVER);
+        let first = sealer.seal(10, 1, b"hello");
+        let second = sealer.seal(10, 2, b"world");
+
+        assert_eq!(
+            opener.open(10, first.clone()).unwrap(),
+            (1, Bytes::from_static(b"hello"))
+        );
+        // Replayed, the sequence moved on.
+        assert!(matches!(opener.open(10, first), Err(Error::BadSeal)));
+        // Another packet id.
+        assert!(matches!(opener.open(11, second.clone()), Err(Error::BadSeal)));
+        let mut changed = second.to_vec();
+        changed[4] ^= 1;
+        assert!(matches!(opener.open(10, changed.into()), Err(Error::BadSeal)));
+        assert!(matches!(
+            opener.open(10, Bytes::from_static(b"short")),
+            Err(Error::BadFrame)
+        ));
+        assert_eq!(opener.open(10, second).unwrap(), (2, Bytes::from_static(b"world")));
+
+        // The other direction never opens.
+        let mut reflected = Seal::new(key, SERVER_TO_CLIENT);
+        let packet = Seal::new(key, CLIENT_TO_SERVER).seal(10, 1, b"hello");
+        assert!(matches!(reflected.open(10, packet), Err(Error::BadSeal)));
+    }
+}
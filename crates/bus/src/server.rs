@@ -0,0 +1,213 @@
+use crate::seal::{self, Seal};
+use crate::{ids, Error, Secret, HANDSHAKE_TIMEOUT};
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::fmt;
+use core::future::Future;
+use futures::stream::FuturesUnordered;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::StreamExt;
+use tq_codec::{TQCodec, TQEncoder};
+use tq_crypto::NopCipher;
+use tq_server::admission::AcceptBackoff;
+
+/// How many responses could wait to be written.
+const QUEUE_SIZE: usize = 64;
+
+/// Answers the requests of a bus server, every request is handled in its own
+/// task.
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    type Error: fmt::Display + Send;
+
+    /// Handles a single request, returns the response packet. An error is
+    /// sent back as [`Error::Remote`] to the client.
+    async fn handle(&self, packet: (u16, Bytes)) -> Result<(u16, Bytes), Self::Error>;
+}
+
+/// Accepts the bus clients on `listener`, until `shutdown` completes.
+///
+/// A failed accept (like running out of file descriptors) is logged and
+/// retried after a [`AcceptBackoff`] delay, it never stops the bus. On
+/// shutdown, every connection is stopped, and gone along with the requests
+/// it was handling once this returns, so nothing uses the handler anymore.
+pub async fn serve<H, F>(listener: TcpListener, secret: Secret, handler: H, shutdown: F) -> Result<(), Error>
+where
+    H: Handler,
+    F: Future<Output = ()> + Send,
+{
+    let handler = Arc::new(handler);
+    let backoff = AcceptBackoff::default();
+    let mut accept_delay = Duration::ZERO;
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            // Forget about the connections that are done.
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = &mut shutdown => break,
+        };
+        let (stream, addr) = match accepted {
+            Ok(accepted) => {
+                accept_delay = Duration::ZERO;
+                accepted
+            },
+            Err(e) => {
+                accept_delay = backoff.next(accept_delay);
+                tracing::error!(error = ?e, retry_in = ?accept_delay, "Failed to accept bus connection");
+                tokio::time::sleep(accept_delay).await;
+                continue;
+            },
+        };
+        // Only a bit slower without it.
+        if let Err(e) = stream.set_nodelay(true) {
+            tracing::debug!(%addr, error = ?e, "Failed to set TCP_NODELAY on bus connection");
+        }
+        let secret = secret.clone();
+        let handler = handler.clone();
+        connections.spawn(async move {
+            tracing::debug!(%addr, "Accepted bus connection");
+            match serve_stream(stream, &secret, handler).await {
+                Ok(()) => tracing::debug!(%addr, "Bus connection closed"),
+                Err(e) => tracing::warn!(%addr, error = %e, "Bus connection failed"),
+            }
+        });
+    }
+    connections.shutdown().await;
+    Ok(())
+}
+
+/// Serves a single bus client, until it disconnects.
+pub async fn serve_stream<S, H>(stream: S, secret: &Secret, handler: Arc<H>) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    H: Handler,
+{
+    let (mut encoder, mut decoder) = TQCodec::new(stream, NopCipher).split();
+    let (sealer, mut opener) =
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, seal::accept(&mut encoder, &mut decoder, secret))
+            .await
+            .map_err(|_| Error::Unauthenticated)??;
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let writer = tokio::spawn(write_responses(encoder, sealer, rx));
+    // The requests are handled at the same time, but within this future, so
+    // they are gone with it.
+    let mut requests = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            packet = decoder.next() => {
+                let Some(packet) = packet else { break };
+                let (packet_id, body) = packet?;
+                let (request_id, payload) = opener.open(packet_id, body)?;
+                let handler = handler.clone();
+                requests.push(async move {
+                    let response = match handler.handle((packet_id, payload)).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            tracing::warn!(packet_id, error = %e, "Failed to handle bus request");
+                            (ids::ERROR, Bytes::from(e.to_string()))
+                        },
+                    };
+                    (response.0, request_id, response.1)
+                });
+            },
+            Some(response) = requests.next(), if !requests.is_empty() => {
+                // The connection is gone if this fails.
+                let _ = tx.send(response).await;
+            },
+        }
+    }
+    // Answer the requests in flight, then the writer stops.
+    while let Some(response) = requests.next().await {
+        let _ = tx.send(response).await;
+    }
+    drop(tx);
+    let _ = writer.await;
+    Ok(())
+}
+
+async fn write_responses<S>(
+    mut encoder: TQEncoder<S, NopCipher>,
+    mut sealer: Seal,
+    mut rx: mpsc::Receiver<(u16, u32, Bytes)>,
+) where
+    S: AsyncRead + AsyncWrite,
+{
+    while let Some((packet_id, request_id, payload)) = rx.recv().await {
+        let body = sealer.seal(packet_id, request_id, &payload);
+        if let Err(e) = encoder.send((packet_id, body)).await {
+            tracing::warn!(error = ?e, "Failed to send bus response");
+            return;
+        }
+    }
+    let _ = encoder.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{Ping, Pong};
+    use crate::Client;
+    use tq_network::{PacketDecode, PacketEncode, PacketID};
+
+    #[derive(Default)]
+    struct Echo(tokio::sync::Notify);
+
+    #[async_trait]
+    impl Handler for Echo {
+        type Error = String;
+
+        async fn handle(&self, (id, body): (u16, Bytes)) -> Result<(u16, Bytes), Self::Error> {
+            if id != Ping::PACKET_ID {
+                return Err(format!("unknown request #{id}"));
+            }
+            let ping = Ping::decode(&body).map_err(|e| e.to_string())?;
+            // The first one waits for the second, it never gets answered
+            // unless they are handled at the same time.
+            match ping.nonce {
+                1 => self.0.notified().await,
+                _ => self.0.notify_one(),
+            }
+            let pong = Pong { nonce: ping.nonce };
+            pong.encode().map_err(|e| e.to_string())
+        }
+    }
+
+    /// Serves `Echo` with `secret`, returns the client end.
+    fn serve(secret: &str) -> (tokio::io::DuplexStream, tokio::task::JoinHandle<Result<(), Error>>) {
+        let (client, server) = tokio::io::duplex(1024);
+        let secret = Secret::new(secret);
+        let task = tokio::spawn(async move { serve_stream(server, &secret, Arc::new(Echo::default())).await });
+        (client, task)
+    }
+
+    #[tokio::test]
+    async fn requests_are_multiplexed() {
+        let (stream, _) = serve("a very secret secret");
+        let client = Client::connect(stream, &Secret::new("a very secret secret"))
+            .await
+            .unwrap();
+        let slow = client.request(&Ping { nonce: 1 });
+        let fast = client.request(&Ping { nonce: 2 });
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert_eq!(slow.unwrap().nonce, 1);
+        assert_eq!(fast.unwrap().nonce, 2);
+
+        let res = client.call(42, Bytes::new()).await;
+        assert!(matches!(res, Err(Error::Remote(e)) if e == "unknown request #42"));
+    }
+
+    #[tokio::test]
+    async fn both_sides_need_the_secret() {
+        let (stream, server) = serve("a very secret secret");
+        let res = Client::connect(stream, &Secret::new("not the same secret")).await;
+        assert!(matches!(res, Err(Error::Unauthenticated)));
+        assert!(matches!(server.await.unwrap(), Err(Error::Closed)));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::Error;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_stream::StreamExt;
+use tq_codec::{handshake, TQCodec, TQDecoder, TQEncoder};
+use tq_crypto::Cipher;
+use tq_network::{PacketDecode, PacketEncode, PacketID, ProtocolCipher, ProtocolVersion, VersionedCipher};
+
+/// A raw connection to a server, speaking `(u16, Bytes)` packets.
+///
+/// `cipher` is the client side one, [`tq_crypto::CQCipher`] for both the
+/// account and the game servers, or a [`ProtocolCipher`] for game servers
+/// speaking a later [`ProtocolVersion`], see [`Connection::negotiate`].
+pub struct Connection<C: Cipher, S: AsyncRead + AsyncWrite = TcpStream> {
+    cipher: C,
+    encoder: TQEncoder<S, C>,
+    decoder: TQDecoder<S, C>,
+}
+
+impl<C: Cipher> Connection<C> {
+    /// Connects to a server over TCP.
+    pub async fn connect(addr: impl ToSocketAddrs, cipher: C) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream, cipher))
+    }
+}
+
+impl Connection<ProtocolCipher> {
+    /// Connects to a game server over TCP speaking `version`.
+    pub async fn connect_versioned(addr: impl ToSocketAddrs, version: ProtocolVersion) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Self::negotiate(stream, version).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<ProtocolCipher, S> {
+    /// Wraps an already connected stream speaking `version`, running the key
+    /// exchange first if that version has one.
+    pub async fn negotiate(mut stream: S, version: ProtocolVersion) -> Result<Self, Error> {
+        let cipher = ProtocolCipher::client(version);
+        if let Some(blowfish) = cipher.key_exchange() {
+            handshake::connect(&mut stream, blowfish).await?;
+        }
+        let codec = TQCodec::new(stream, cipher.clone()).with_seals(version.server_seal(), version.client_seal());
+        Ok(Self::from_codec(codec, cipher))
+    }
+}
+
+impl<C: Cipher, S: AsyncRead + AsyncWrite + Unpin> Connection<C, S> {
+    /// Wraps an already connected stream.
+    pub fn new(stream: S, cipher: C) -> Self {
+        Self::from_codec(TQCodec::new(stream, cipher.clone()), cipher)
+    }
+
+    fn from_codec(codec: TQCodec<S, C>, cipher: C) -> Self {
+        let (encoder, decoder) = codec.split();
+        Self {
+            cipher,
+            encoder,
+            decoder,
+        }
+    }
+
+    /// Switches to the new keys, this is what the game client does right
+    /// after sending `MsgConnect` with the login token as the seed.
+    ///
+    /// Only the packets sent after this call use the new keys.
+    pub fn generate_keys(&self, seed: u64) {
+        self.cipher.generate_keys(seed);
+    }
+
+    pub async fn send<P>(&mut self, packet: &P) -> Result<(), Error>
+    where
+        P: PacketEncode,
+        Error: From<P::Error>,
+    {
+        let packet = packet.encode()?;
+        self.encoder.send(packet).await?;
+        Ok(())
+    }
+
+    /// Receives the next packet, `None` once the server closed the
+    /// connection.
+    pub async fn recv(&mut self) -> Result<Option<(u16, Bytes)>, Error> {
+        match self.decoder.next().await {
+            Some(packet) => Ok(Some(packet?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`Connection::recv`] but fails with [`Error::TimedOut`] if
+    /// nothing arrives in time.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<(u16, Bytes)>, Error> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .map_err(|_| Error::TimedOut)?
+    }
+
+    /// Receives the next packet and decodes it as `P`, any other packet is an
+    /// [`Error::UnexpectedPacket`].
+    pub async fn recv_packet<P>(&mut self) -> Result<P, Error>
+    where
+        P: PacketID + PacketDecode<Packet = P>,
+        Error: From<P::Error>,
+    {
+        match self.recv().await? {
+            Some((id, bytes)) if id == P::PACKET_ID => Ok(P::decode(&bytes)?),
+            Some((id, bytes)) => Err(Error::UnexpectedPacket(id, bytes)),
+            None => Err(Error::Closed),
+        }
+    }
+
+    /// Closes the connection from our side.
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.encoder.close().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use bytes::Bytes;
+
+#[derive(Debug)]
+pub enum Error {
+    TQNetwork(tq_network::Error),
+    IO(std::io::Error),
+    /// Talking to the game server over the bus failed.
+    Bus(tq_bus::Error),
+    /// The key exchange failed.
+    Handshake(tq_codec::handshake::HandshakeError),
+    /// The server closed the connection.
+    Closed,
+    /// Nothing arrived from the server in time.
+    TimedOut,
+    /// Got a packet we did not expect at this point.
+    UnexpectedPacket(u16, Bytes),
+    /// The game server refused the login token.
+    LoginRejected(String),
+    /// The game server refused to create the character.
+    RegisterRejected(String),
+    /// The character is not in the game yet.
+    NotInGame,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TQNetwork(e) => write!(f, "TQNetwork Error: {}", e),
+            Self::IO(e) => write!(f, "IO Error: {}", e),
+            Self::Bus(e) => write!(f, "Bus Error: {}", e),
+            Self::Handshake(e) => write!(f, "Handshake Error: {}", e),
+            Self::Closed => write!(f, "Connection closed"),
+            Self::TimedOut => write!(f, "Timed out"),
+            Self::UnexpectedPacket(id, _) => write!(f, "Unexpected packet #{}", id),
+            Self::LoginRejected(msg) => write!(f, "Login rejected: {}", msg),
+            Self::RegisterRejected(msg) => write!(f, "Register rejected: {}", msg),
+            Self::NotInGame => write!(f, "Not in game"),
+        }
+    }
+}
+
+impl From<tq_network::Error> for Error {
+    fn from(e: tq_network::Error) -> Self {
+        Self::TQNetwork(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<tq_bus::Error> for Error {
+    fn from(e: tq_bus::Error) -> Self {
+        Self::Bus(e)
+    }
+}
+
+impl From<tq_codec::handshake::HandshakeError> for Error {
+    fn from(e: tq_codec::handshake::HandshakeError) -> Self {
+        Self::Handshake(e)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,25 @@
+//! A client for our own servers, for bots, tests and tools.
+//!
+//! [`Connection`] is the raw connection, sending and receiving `(u16, Bytes)`
+//! packets with the client cipher. The [`transfer`] module is the account
+//! server side of the login handoff, and with the `game` feature (off by
+//! default) [`session::GameClient`] plays a character in the game server:
+//!
+//! ```ignore
+//! let token = transfer::request_token(&bus, account_id, realm_id, Some(client_ip)).await?;
+//! let (mut client, login) = GameClient::connect(addr, token).await?;
+//! while let Some(event) = client.next_event().await? {
+//!     // ...
+//! }
+//! ```
+
+mod error;
+pub use error::Error;
+
+mod connection;
+pub use connection::Connection;
+
+pub mod transfer;
+
+#[cfg(feature = "game")]
+pub mod session;
@@ -0,0 +1,385 @@
+//! A game session, what the game client does after the account server handed
+//! it a login token.
+//!
+//! Every packet the server sends is turned into an [`Event`], and the session
+//! keeps track of the bits it needs to act on behalf of the character (its id,
+//! map and location).
+
+use crate::{Connection, Error};
+use bytes::Bytes;
+use game::constants::{ALL_USERS, ANSWER_OK, NEW_ROLE, WALK_XCOORDS, WALK_YCOORDS};
+use game::packets::*;
+use game::utils::LoHi;
+use std::collections::VecDeque;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tq_crypto::{CQCipher, Cipher};
+use tq_network::{PacketDecode, PacketID, ProtocolCipher, ProtocolVersion};
+
+/// A packet from the game server.
+#[derive(Debug)]
+pub enum Event {
+    Talk(MsgTalk),
+    Action(MsgAction),
+    Walk(MsgWalk),
+    UserInfo(MsgUserInfo),
+    Player(MsgPlayer),
+    Item(MsgItem),
+    Data(MsgData),
+    TaskDialog(MsgTaskDialog),
+    /// Any other packet, as is.
+    Unknown(u16, Bytes),
+}
+
+impl Event {
+    pub fn decode(id: u16, bytes: Bytes) -> Result<Self, Error> {
+        let event = match id {
+            MsgTalk::PACKET_ID => Self::Talk(MsgTalk::decode(&bytes)?),
+            MsgAction::PACKET_ID => Self::Action(MsgAction::decode(&bytes)?),
+            MsgWalk::PACKET_ID => Self::Walk(MsgWalk::decode(&bytes)?),
+            MsgUserInfo::PACKET_ID => Self::UserInfo(MsgUserInfo::decode(&bytes)?),
+            MsgPlayer::PACKET_ID => Self::Player(MsgPlayer::decode(&bytes)?),
+            MsgItem::PACKET_ID => Self::Item(MsgItem::decode(&bytes)?),
+            MsgData::PACKET_ID => Self::Data(MsgData::decode(&bytes)?),
+            MsgTaskDialog::PACKET_ID => Self::TaskDialog(MsgTaskDialog::decode(&bytes)?),
+            _ => Self::Unknown(id, bytes),
+        };
+        Ok(event)
+    }
+}
+
+/// The game server's answer to the login token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Login {
+    /// The character is in the game.
+    Ready,
+    /// The account has no character yet, see [`GameClient::register`].
+    NewRole,
+}
+
+/// Where the character is, as far as the server told us.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Me {
+    pub character_id: u32,
+    pub name: String,
+    pub map_id: u32,
+    pub x: u16,
+    pub y: u16,
+}
+
+pub struct GameClient<S: AsyncRead + AsyncWrite = TcpStream, C: Cipher = CQCipher> {
+    conn: Connection<C, S>,
+    token: u64,
+    me: Option<Me>,
+    /// Events that arrived while we waited for something else.
+    pending: VecDeque<Event>,
+}
+
+impl GameClient {
+    /// Connects to the game server over TCP and logs in with `token`.
+    pub async fn connect(addr: impl ToSocketAddrs, token: u64) -> Result<(Self, Login), Error> {
+        let conn = Connection::connect(addr, CQCipher::new()).await?;
+        Self::login(conn, token).await
+    }
+}
+
+impl GameClient<TcpStream, ProtocolCipher> {
+    /// Like [`GameClient::connect`], for a game server speaking `version`.
+    pub async fn connect_versioned(
+        addr: impl ToSocketAddrs,
+        token: u64,
+        version: ProtocolVersion,
+    ) -> Result<(Self, Login), Error> {
+        let conn = Connection::connect_versioned(addr, version).await?;
+        Self::login(conn, token).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, C: Cipher> GameClient<S, C> {
+    /// Sends `MsgConnect` with the login `token` and waits for the answer.
+    ///
+    /// The connection switches to the keys derived from the token right after
+    /// `MsgConnect` is sent, everything after it uses the new keys.
+    pub async fn login(mut conn: Connection<C, S>, token: u64) -> Result<(Self, Login), Error> {
+        let msg = MsgConnect {
+            token,
+            language: String::from("En").into(),
+            ..Default::default()
+        };
+        conn.send(&msg).await?;
+        conn.generate_keys(token);
+        let mut client = Self {
+            conn,
+            token,
+            me: None,
+            pending: VecDeque::new(),
+        };
+        let answer = client.wait_answer(TalkChannel::Login).await?;
+        match answer.as_str() {
+            ANSWER_OK => Ok((client, Login::Ready)),
+            NEW_ROLE => Ok((client, Login::NewRole)),
+            _ => Err(Error::LoginRejected(answer)),
+        }
+    }
+
+    /// Creates the account's character, after a [`Login::NewRole`].
+    ///
+    /// Like the game client, log in again with a new token to play it.
+    pub async fn register(&mut self, name: &str, class: BaseClass, body: BodyType) -> Result<(), Error> {
+        let msg = MsgRegister {
+            character_name: name.to_owned().into(),
+            class: class.into(),
+            mesh: body.into(),
+            token: self.token as u32,
+            ..Default::default()
+        };
+        self.conn.send(&msg).await?;
+        let answer = self.wait_answer(TalkChannel::Register).await?;
+        match answer.as_str() {
+            ANSWER_OK => Ok(()),
+            _ => Err(Error::RegisterRejected(answer)),
+        }
+    }
+
+    /// The character, once the server sent its info.
+    pub fn me(&self) -> Option<&Me> {
+        self.me.as_ref()
+    }
+
+    /// Waits for the next event, `None` once the server closed the
+    /// connection.
+    pub async fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+        match self.conn.recv().await? {
+            Some((id, bytes)) => {
+                let event = Event::decode(id, bytes)?;
+                self.track(&event);
+                Ok(Some(event))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Asks the server where the character is, answered by an
+    /// [`ActionType::SendLocation`] action.
+    pub async fn send_location(&mut self) -> Result<(), Error> {
+        let me = self.me.as_ref().ok_or(Error::NotInGame)?;
+        let msg = MsgAction::new(me.character_id, 0, 0, 0, ActionType::SendLocation);
+        self.conn.send(&msg).await
+    }
+
+    /// Walks one step in `direction` (0 to 7).
+    pub async fn walk(&mut self, direction: u8, movement: MovementType) -> Result<(), Error> {
+        let me = self.me.as_ref().ok_or(Error::NotInGame)?;
+        let msg = MsgWalk::new(me.character_id, direction, movement);
+        self.conn.send(&msg).await
+    }
+
+    /// Jumps from the current location to `(x, y)`.
+    pub async fn jump(&mut self, x: u16, y: u16) -> Result<(), Error> {
+        let me = self.me.as_ref().ok_or(Error::NotInGame)?;
+        let msg = MsgAction::new(
+            me.character_id,
+            u32::constract(y, x),
+            u32::constract(me.y, me.x),
+            0,
+            ActionType::Jump,
+        );
+        self.conn.send(&msg).await
+    }
+
+    /// Says `message` to `recipient`, use [`ALL_USERS`] to talk to everyone
+    /// around.
+    pub async fn talk(&mut self, recipient: &str, message: impl Into<String>) -> Result<(), Error> {
+        let me = self.me.as_ref().ok_or(Error::NotInGame)?;
+        let msg = MsgTalk {
+            color: 0x00FF_FFFF,
+            channel: TalkChannel::Talk.into(),
+            style: TalkStyle::Normal.into(),
+            character_id: me.character_id,
+            recipient_mesh: 0,
+            sender_mesh: 0,
+            list_count: 4,
+            sender_name: me.name.clone(),
+            recipient_name: recipient.to_owned(),
+            suffix: String::new(),
+            message: message.into(),
+        };
+        self.conn.send(&msg).await
+    }
+
+    /// Talks to everyone around, see [`GameClient::talk`].
+    pub async fn say(&mut self, message: impl Into<String>) -> Result<(), Error> {
+        self.talk(ALL_USERS, message).await
+    }
+
+    /// Starts interacting with an NPC, the server answers with
+    /// [`Event::TaskDialog`]s.
+    pub async fn interact(&mut self, npc_id: u32) -> Result<(), Error> {
+        self.me.as_ref().ok_or(Error::NotInGame)?;
+        let msg = MsgNpc::new(npc_id, NpcActionKind::Activate);
+        self.conn.send(&msg).await
+    }
+
+    /// Closes the connection.
+    pub async fn close(self) -> Result<(), Error> {
+        self.conn.close().await
+    }
+
+    /// Waits for the server's answer on `channel`, queuing everything else.
+    async fn wait_answer(&mut self, channel: TalkChannel) -> Result<String, Error> {
+        loop {
+            let (id, bytes) = self.conn.recv().await?.ok_or(Error::Closed)?;
+            let event = Event::decode(id, bytes)?;
+            match event {
+                Event::Talk(msg) if msg.channel == u16::from(channel) => return Ok(msg.message),
+                event => {
+                    self.track(&event);
+                    self.pending.push_back(event);
+                },
+            }
+        }
+    }
+
+    fn track(&mut self, event: &Event) {
+        match event {
+            Event::UserInfo(msg) => {
+                let me = self.me.get_or_insert_with(Default::default);
+                me.character_id = msg.character_id;
+                me.name = msg.character_name.clone();
+            },
+            Event::Action(msg) => {
+                let Some(me) = self.me.as_mut().filter(|me| me.character_id == msg.character_id) else {
+                    return;
+                };
+                match ActionType::from(msg.action_type) {
+                    ActionType::SendLocation | ActionType::Teleport => {
+                        me.map_id = msg.data1;
+                        (me.x, me.y) = (msg.data2.lo(), msg.data2.hi());
+                    },
+                    ActionType::Jump => {
+                        (me.x, me.y) = (msg.data1.lo(), msg.data1.hi());
+                    },
+                    _ => {},
+                }
+            },
+            Event::Walk(msg) => {
+                let Some(me) = self.me.as_mut().filter(|me| me.character_id == msg.character_id()) else {
+                    return;
+                };
+                let direction = (msg.direction() % 8) as usize;
+                me.x = me.x.wrapping_add(WALK_XCOORDS[direction] as u16);
+                me.y = me.y.wrapping_add(WALK_YCOORDS[direction] as u16);
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer;
+    use game::GameServer;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tq_server::harness::Harness;
+
+    /// Where the harness clients connect from.
+    const LOCALHOST: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    /// A bus to the game server, like the account server has.
+    async fn bus(state: &'static game::State) -> tq_bus::Client {
+        let (stream, server) = tokio::io::duplex(1024);
+        let secret = tq_bus::Secret::new("a very secret secret");
+        let handler = std::sync::Arc::new(game::bus::BusHandler::new(state));
+        let server_secret = secret.clone();
+        tokio::spawn(async move { tq_bus::serve_stream(server, &server_secret, handler).await });
+        tq_bus::Client::connect(stream, &secret).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn login_with_a_transferred_token() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+        let state = game::State::with_pool(pool, concat!(env!("CARGO_MANIFEST_DIR"), "/../../data"))
+            .await
+            .unwrap();
+        let state = Box::leak(Box::new(state));
+        let harness = Harness::<GameServer>::new(state);
+        let token = transfer::request_token(&bus(state).await, 1, 1, Some(LOCALHOST))
+            .await
+            .unwrap();
+
+        let (stream, _) = harness.open();
+        let conn = Connection::new(stream, CQCipher::new());
+        let (mut client, login) = GameClient::login(conn, token).await.unwrap();
+        assert_eq!(login, Login::NewRole);
+        assert!(client.me().is_none());
+        assert!(matches!(client.interact(1).await, Err(Error::NotInGame)));
+
+        // The token is good for one login only.
+        let (stream, _) = harness.open();
+        let conn = Connection::new(stream, CQCipher::new());
+        let res = GameClient::login(conn, token).await;
+        assert!(matches!(res, Err(Error::LoginRejected(_))));
+    }
+
+    #[tokio::test]
+    async fn login_after_the_key_exchange() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+        let state = game::State::with_pool(pool, concat!(env!("CARGO_MANIFEST_DIR"), "/../../data"))
+            .await
+            .unwrap();
+        let config = tq_server::Config {
+            protocols: vec![ProtocolVersion::V5018],
+            ..Default::default()
+        };
+        let state = Box::leak(Box::new(state));
+        let harness = Harness::<GameServer>::with_config(state, config);
+        let token = transfer::request_token(&bus(state).await, 1, 1, Some(LOCALHOST))
+            .await
+            .unwrap();
+
+        let (stream, _) = harness.open();
+        let conn = Connection::negotiate(stream, ProtocolVersion::V5018).await.unwrap();
+        let (_, login) = GameClient::login(conn, token).await.unwrap();
+        assert_eq!(login, Login::NewRole);
+
+        // A client that skips the key exchange gets nowhere.
+        let (stream, _) = harness.open();
+        let conn = Connection::new(stream, CQCipher::new());
+        let res = GameClient::login(conn, token).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn one_server_for_both_patches() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+        let state = game::State::with_pool(pool, concat!(env!("CARGO_MANIFEST_DIR"), "/../../data"))
+            .await
+            .unwrap();
+        let config = tq_server::Config {
+            protocols: vec![ProtocolVersion::V5017, ProtocolVersion::V5018],
+            ..Default::default()
+        };
+        let state = Box::leak(Box::new(state));
+        let harness = Harness::<GameServer>::with_config(state, config);
+        let bus = bus(state).await;
+
+        let token = transfer::request_token(&bus, 1, 1, Some(LOCALHOST)).await.unwrap();
+        let (stream, _) = harness.open();
+        let conn = Connection::new(stream, CQCipher::new());
+        let (_, login) = GameClient::login(conn, token).await.unwrap();
+        assert_eq!(login, Login::NewRole);
+
+        let token = transfer::request_token(&bus, 2, 1, Some(LOCALHOST)).await.unwrap();
+        let (stream, _) = harness.open();
+        let conn = Connection::negotiate(stream, ProtocolVersion::V5018).await.unwrap();
+        let (_, login) = GameClient::login(conn, token).await.unwrap();
+        assert_eq!(login, Login::NewRole);
+    }
+}
@@ -0,0 +1,27 @@
+//! The account server side of the login handoff.
+//!
+//! The account server asks the game server for a one time login token over
+//! the bus (see [`tq_bus`]), sends it to the game client in `MsgConnectEx`,
+//! and the client then presents it in the game server's `MsgConnect`.
+
+use crate::Error;
+use std::net::IpAddr;
+use tq_bus::messages::LoginTokenRequest;
+
+/// Asks the game server on the other end of `bus` for a login token for
+/// `account_id` on `realm_id`, only the game client at `client_ip` could use
+/// it (anyone if `None`).
+pub async fn request_token(
+    bus: &tq_bus::Client,
+    account_id: u32,
+    realm_id: u32,
+    client_ip: Option<IpAddr>,
+) -> Result<u64, Error> {
+    let req = LoginTokenRequest {
+        account_id,
+        realm_id,
+        client_ip: client_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+    };
+    let res = bus.request(&req).await?;
+    Ok(res.token)
+}
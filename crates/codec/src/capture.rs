@@ -0,0 +1,498 @@
+//! Session capture, records the decrypted packets going through a
+//! [`TQCodec`](crate::TQCodec) into a capture file.
+//!
+//! A [`CaptureFile`] is shared between all the connections, each connection
+//! gets its own [`Recorder`] (with its own session number) that could be
+//! switched on and off at any time. Recording only queues the packet, the
+//! file itself is written by a dedicated thread.
+//!
+//! ## File Format (Version 1)
+//!
+//! All integers are little-endian.
+//!
+//! The file starts with a 6 bytes header:
+//!
+//! | Offset | Size | Description                    |
+//! |--------|------|--------------------------------|
+//! | 0      | 4    | Magic, always `TQCP`           |
+//! | 4      | 2    | Format version, currently `1`  |
+//!
+//! Followed by zero or more records until the end of the file:
+//!
+//! | Offset | Size | Description                                          |
+//! |--------|------|------------------------------------------------------|
+//! | 0      | 1    | Direction, `0` Client -> Server, `1` Server -> Client |
+//! | 1      | 4    | Session, unique per connection in the same file      |
+//! | 5      | 4    | Actor ID at the time of the record (`0` before login) |
+//! | 9      | 8    | Microseconds since the capture started (monotonic)   |
+//! | 17     | 2    | Packet ID                                            |
+//! | 19     | 2    | Body length (`n`)                                    |
+//! | 21     | n    | Decrypted packet body, without the 4 bytes head      |
+//!
+//! Any change to the layout bumps the version, readers must refuse versions
+//! they don't know about.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The first 4 bytes of every capture file.
+pub const MAGIC: [u8; 4] = *b"TQCP";
+/// The current version of the capture format.
+pub const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 6;
+const RECORD_HEAD_LEN: usize = 21;
+
+/// Which way the packet was going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Direction {
+    ClientToServer = 0,
+    ServerToClient = 1,
+}
+
+impl TryFrom<u8> for Direction {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::ClientToServer),
+            1 => Ok(Self::ServerToClient),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid Capture Direction {v}"),
+            )),
+        }
+    }
+}
+
+/// A single captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    pub session: u32,
+    pub actor_id: u32,
+    /// Time since the capture started.
+    pub timestamp: Duration,
+    pub packet_id: u16,
+    pub body: Bytes,
+}
+
+impl CaptureRecord {
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(RECORD_HEAD_LEN + self.body.len());
+        buf.put_u8(self.direction as u8);
+        buf.put_u32_le(self.session);
+        buf.put_u32_le(self.actor_id);
+        buf.put_u64_le(self.timestamp.as_micros() as u64);
+        buf.put_u16_le(self.packet_id);
+        buf.put_u16_le(self.body.len() as u16);
+        buf.extend_from_slice(&self.body);
+    }
+}
+
+/// Writes a capture file, see the [module docs](self) for the format.
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+    buf: BytesMut,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Creates a new writer and writes the file header.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&VERSION.to_le_bytes());
+        inner.write_all(&header)?;
+        Ok(Self {
+            inner,
+            buf: BytesMut::with_capacity(64),
+        })
+    }
+
+    pub fn write_record(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        self.buf.clear();
+        record.encode(&mut self.buf);
+        self.inner.write_all(&self.buf)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads a capture file, see the [module docs](self) for the format.
+///
+/// Records are returned in the same order they were written.
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    inner: R,
+    version: u16,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Creates a new reader, reading and validating the file header.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a Capture File"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported Capture Version {version}"),
+            ));
+        }
+        Ok(Self { inner, version })
+    }
+
+    /// The format version of this file.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Reads the next record, returns `None` at the end of the file.
+    pub fn read_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        let mut head = [0u8; RECORD_HEAD_LEN];
+        // A clean end of file is only allowed between records.
+        match self.inner.read(&mut head[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut head[1..])?,
+        }
+        let mut head = &head[..];
+        let direction = Direction::try_from(head.get_u8())?;
+        let session = head.get_u32_le();
+        let actor_id = head.get_u32_le();
+        let timestamp = Duration::from_micros(head.get_u64_le());
+        let packet_id = head.get_u16_le();
+        let len = head.get_u16_le() as usize;
+        let mut body = vec![0u8; len];
+        self.inner.read_exact(&mut body)?;
+        Ok(Some(CaptureRecord {
+            direction,
+            session,
+            actor_id,
+            timestamp,
+            packet_id,
+            body: body.into(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// How many records could be waiting for the writer thread, any more than
+/// that are dropped instead of slowing the connections down.
+pub const QUEUE_SIZE: usize = 4096;
+/// How long the writer thread waits for new records before flushing what it
+/// got so far.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Command {
+    Record(CaptureRecord),
+    Flush,
+    Close,
+}
+
+struct Shared {
+    tx: SyncSender<Command>,
+    start: Instant,
+    next_session: AtomicU32,
+    /// Records dropped because the queue was full, since the writer last
+    /// reported them.
+    dropped: Arc<AtomicU64>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Wait for everything queued so far to be written.
+        let _ = self.tx.send(Command::Close);
+        if let Some(writer) = self.writer.get_mut().ok().and_then(Option::take) {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// A capture file shared between many connections.
+///
+/// The records are written by a dedicated thread, so recording a packet
+/// never blocks on the file. If the writer can't keep up, the records over
+/// [`QUEUE_SIZE`] are dropped (and reported) instead.
+#[derive(Clone)]
+pub struct CaptureFile {
+    shared: Arc<Shared>,
+}
+
+impl core::fmt::Debug for CaptureFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CaptureFile")
+            .field("start", &self.shared.start)
+            .field("next_session", &self.shared.next_session)
+            .finish()
+    }
+}
+
+impl CaptureFile {
+    /// Creates (or truncates) the capture file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file))
+    }
+
+    /// Captures into any writer.
+    pub fn new(writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let writer = CaptureWriter::new(writer)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let handle = std::thread::Builder::new().name("Capture Writer".into()).spawn({
+            let dropped = dropped.clone();
+            move || write_records(writer, rx, &dropped)
+        })?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                tx,
+                start: Instant::now(),
+                next_session: AtomicU32::new(1),
+                dropped,
+                writer: Mutex::new(Some(handle)),
+            }),
+        })
+    }
+
+    /// Creates a recorder for a new connection.
+    pub fn recorder(&self, enabled: bool) -> Recorder {
+        let session = self.shared.next_session.fetch_add(1, Ordering::Relaxed);
+        Recorder {
+            inner: Arc::new(RecorderInner {
+                file: self.clone(),
+                session,
+                actor_id: AtomicU32::new(0),
+                enabled: AtomicBool::new(enabled),
+            }),
+        }
+    }
+
+    /// Queues a record for the writer thread.
+    ///
+    /// Fails only if the writer is gone, a full queue drops the record.
+    fn write(&self, record: CaptureRecord) -> io::Result<()> {
+        match self.shared.tx.try_send(Command::Record(record)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            },
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::other("Capture Writer Stopped")),
+        }
+    }
+
+    /// Asks the writer thread to flush the buffered records to the
+    /// underlying writer, without waiting for it.
+    pub fn flush(&self) {
+        let _ = self.shared.tx.try_send(Command::Flush);
+    }
+}
+
+/// The writer thread, runs until [`Command::Close`] or the first error.
+fn write_records<W: Write>(mut writer: CaptureWriter<W>, rx: Receiver<Command>, dropped: &AtomicU64) {
+    let result = loop {
+        let command = match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => Command::Flush,
+            Err(RecvTimeoutError::Disconnected) => Command::Close,
+        };
+        let written = match command {
+            Command::Record(record) => writer.write_record(&record),
+            Command::Flush => writer.flush(),
+            Command::Close => break writer.flush(),
+        };
+        if let Err(e) = written {
+            break Err(e);
+        }
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            tracing::warn!(records = lost, "Capture writer could not keep up, dropped some records");
+        }
+    };
+    if let Err(e) = result {
+        // Dropping the receiver switches every recorder off.
+        tracing::error!(error = %e, "Failed to write the capture file, stop recording");
+    }
+}
+
+#[derive(Debug)]
+struct RecorderInner {
+    file: CaptureFile,
+    session: u32,
+    actor_id: AtomicU32,
+    enabled: AtomicBool,
+}
+
+/// Records the packets of a single connection into a [`CaptureFile`].
+///
+/// Cheap to clone, all the clones share the same state, so switching it on
+/// or off affects both sides of the connection.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    inner: Arc<RecorderInner>,
+}
+
+impl Recorder {
+    pub fn session(&self) -> u32 {
+        self.inner.session
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            // Make sure what we got so far ends up on disk.
+            self.inner.file.flush();
+        }
+    }
+
+    /// Sets the actor id used for the next records.
+    pub fn set_actor_id(&self, id: u32) {
+        self.inner.actor_id.store(id, Ordering::Relaxed);
+    }
+
+    /// Records a packet, if enabled.
+    ///
+    /// Capturing is best effort, this only queues the record for the writer
+    /// thread. If the writer stopped, the recorder switches itself off
+    /// instead of failing the connection.
+    pub fn record(&self, direction: Direction, packet_id: u16, body: &Bytes) {
+        if !self.is_enabled() {
+            return;
+        }
+        let record = CaptureRecord {
+            direction,
+            session: self.inner.session,
+            actor_id: self.inner.actor_id.load(Ordering::Relaxed),
+            timestamp: self.inner.file.shared.start.elapsed(),
+            packet_id,
+            body: body.clone(),
+        };
+        if let Err(e) = self.inner.file.write(record) {
+            tracing::error!(error = %e, session = %self.inner.session, "Failed to capture packet, stop recording");
+            self.inner.enabled.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for RecorderInner {
+    fn drop(&mut self) {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_then_read() {
+        let records = [
+            CaptureRecord {
+                direction: Direction::ClientToServer,
+                session: 1,
+                actor_id: 0,
+                timestamp: Duration::from_micros(10),
+                packet_id: 1052,
+                body: Bytes::from_static(&[1, 2, 3, 4]),
+            },
+            CaptureRecord {
+                direction: Direction::ServerToClient,
+                session: 1,
+                actor_id: 1_000_001,
+                timestamp: Duration::from_millis(5),
+                packet_id: 1004,
+                body: Bytes::new(),
+            },
+        ];
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write_record(record).unwrap();
+        }
+        let file = writer.inner;
+        assert_eq!(&file[..4], b"TQCP");
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        let read: Vec<_> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, records);
+        // A truncated record is an error, not the end of the file.
+        let mut reader = CaptureReader::new(&file[..file.len() - 1]).unwrap();
+        assert!(reader.read_record().unwrap().is_some());
+        assert!(reader.read_record().is_err());
+    }
+
+    /// A writer the test could read back after the capture is done.
+    #[derive(Clone, Default)]
+    struct SharedVec(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedVec {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_are_written_by_the_writer_thread() {
+        let out = SharedVec::default();
+        let file = CaptureFile::new(out.clone()).unwrap();
+        let recorder = file.recorder(true);
+        let muted = file.recorder(false);
+        recorder.set_actor_id(1_000_001);
+        recorder.record(Direction::ClientToServer, 1052, &Bytes::from_static(&[1, 2]));
+        muted.record(Direction::ClientToServer, 1052, &Bytes::new());
+        recorder.record(Direction::ServerToClient, 1004, &Bytes::new());
+        // Everything queued is written once the last handle is gone.
+        drop((file, recorder, muted));
+        let out = out.0.lock().unwrap();
+        let read: Vec<_> = CaptureReader::new(&out[..])
+            .unwrap()
+            .map(|r| r.map(|r| (r.direction, r.session, r.actor_id, r.packet_id)))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            read,
+            [
+                (Direction::ClientToServer, 1, 1_000_001, 1052),
+                (Direction::ServerToClient, 1, 1_000_001, 1004),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut file = Vec::from(MAGIC);
+        file.extend_from_slice(&2u16.to_le_bytes());
+        assert!(CaptureReader::new(&file[..]).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Sans-IO framing, the packet framing and encryption without any IO.
+//!
+//! [`FrameDecoder`] takes the bytes as they come from the client (in any
+//! chunk size) and returns the decrypted `(u16, Bytes)` frames, while
+//! [`FrameEncoder`] encrypts frames into a buffer that could be written to
+//! anything.
+//!
+//! They don't know about sockets, runtimes or tasks, so they work the same
+//! over TCP, in memory, in a fuzzer or inside a WASM guest.
+//! [`TQDecoder`](crate::TQDecoder) and [`TQEncoder`](crate::TQEncoder) are
+//! thin adapters on top of them for tokio streams.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use core::fmt;
+use pretty_hex::{HexConfig, PrettyHex};
+use tq_crypto::Cipher;
+
+/// The size of the frame head, 2 bytes for the length and 2 bytes for the
+/// packet id.
+pub const HEAD_LEN: usize = 4;
+/// The biggest frame a client is allowed to send, unless the decoder is told
+/// otherwise with [`FrameDecoder::with_max_size`].
+pub const MAX_FRAME_SIZE: u16 = 2 * 1024;
+
+/// Errors while framing packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame length is bigger than the decoder allows, see
+    /// [`MAX_FRAME_SIZE`].
+    TooBig { len: u16, packet_id: u16 },
+    /// The frame length is smaller than its own head.
+    TooSmall { len: u16, packet_id: u16 },
+    /// The packet body does not fit in a single frame.
+    BodyTooBig { len: usize, packet_id: u16 },
+    /// The frame does not end with the expected seal.
+    BadSeal { packet_id: u16 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooBig { len, packet_id } => write!(f, "Frame Too Big ({len} bytes, Packet {packet_id})"),
+            Self::TooSmall { len, packet_id } => write!(f, "Frame Too Small ({len} bytes, Packet {packet_id})"),
+            Self::BodyTooBig { len, packet_id } => write!(f, "Packet Body Too Big ({len} bytes, Packet {packet_id})"),
+            Self::BadSeal { packet_id } => write!(f, "Bad Frame Seal (Packet {packet_id})"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+#[cfg(feature = "std")]
+impl From<FrameError> for std::io::Error {
+    fn from(e: FrameError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A simple State Machine for Decoding the Stream.
+#[derive(Debug, Clone, Copy)]
+enum DecodeState {
+    /// Decoding the Head of the Packet (4 Bytes for the Size + PacketID)
+    Head,
+    /// Decoding the Packet Bytes (usize => Body Size, u16 => PacketID)
+    Data((usize, u16)),
+}
+
+/// Ends every frame (and key exchange message) the servers since patch 5018
+/// send.
+pub const SERVER_SEAL: &[u8; 8] = b"TQServer";
+/// Ends every frame (and key exchange message) the clients since patch 5018
+/// send.
+pub const CLIENT_SEAL: &[u8; 8] = b"TQClient";
+
+/// Decrypts and splits the incoming bytes into frames.
+#[derive(Debug)]
+pub struct FrameDecoder<C: Cipher> {
+    /// Current Decode State
+    state: DecodeState,
+    /// Cipher Used to Decrypt Packets
+    cipher: C,
+    /// Bytes received, but not decoded yet.
+    buf: BytesMut,
+    /// Expected at the end of every frame, not counted in its length.
+    seal: &'static [u8],
+    /// The biggest frame we accept.
+    max_size: u16,
+}
+
+impl<C: Cipher> FrameDecoder<C> {
+    pub fn new(cipher: C) -> Self {
+        Self {
+            state: DecodeState::Head,
+            cipher,
+            buf: BytesMut::with_capacity(64),
+            seal: &[],
+            max_size: MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Expects every frame to end with `seal`, like [`CLIENT_SEAL`].
+    pub fn with_seal(mut self, seal: &'static [u8]) -> Self {
+        self.seal = seal;
+        self
+    }
+
+    /// Rejects the frames bigger than `max_size` instead of
+    /// [`MAX_FRAME_SIZE`].
+    pub fn with_max_size(mut self, max_size: u16) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Adds the received bytes to the decoder.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The buffer holding the received bytes, reading directly into it is the
+    /// same as calling [`FrameDecoder::feed`], without the extra copy.
+    pub fn read_buf(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+
+    /// Number of bytes received and not decoded yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Decodes the next frame, returns `None` if we need more bytes.
+    ///
+    /// After an error, the decoder is out of sync with the client and should
+    /// not be used anymore.
+    pub fn decode(&mut self) -> Result<Option<(u16, Bytes)>, FrameError> {
+        let (n, packet_id) = match self.state {
+            DecodeState::Head => match self.decode_head()? {
+                Some(head) => {
+                    self.state = DecodeState::Data(head);
+                    head
+                },
+                None => return Ok(None),
+            },
+            DecodeState::Data(head) => head,
+        };
+        if self.buf.len() < n + self.seal.len() {
+            tracing::trace!(data_len = %n, buf_len = %self.buf.len(), "Not enough data");
+            return Ok(None);
+        }
+        let mut data = self.buf.split_to(n);
+        self.cipher.decrypt(&mut data);
+        if !self.seal.is_empty() {
+            let mut seal = self.buf.split_to(self.seal.len());
+            self.cipher.decrypt(&mut seal);
+            if seal != self.seal {
+                return Err(FrameError::BadSeal { packet_id });
+            }
+        }
+        let config = HexConfig {
+            title: false,
+            ..Default::default()
+        };
+        let packet_len = n + HEAD_LEN;
+        tracing::trace!(
+            "\nClient -> Server ID({packet_id}) Length({packet_len})\n{:?}",
+            data.as_ref().hex_conf(config)
+        );
+        self.state = DecodeState::Head;
+        Ok(Some((packet_id, data.freeze())))
+    }
+
+    fn decode_head(&mut self) -> Result<Option<(usize, u16)>, FrameError> {
+        if self.buf.len() < HEAD_LEN {
+            return Ok(None);
+        }
+        let mut len = self.buf.split_to(2);
+        let mut ty = self.buf.split_to(2);
+        // Get the decrypted head len.
+        self.cipher.decrypt(&mut len);
+        // Get the decrypted head packet type.
+        self.cipher.decrypt(&mut ty);
+        let len = len.as_ref().get_u16_le();
+        let packet_id = ty.as_ref().get_u16_le();
+        tracing::trace!(%len, %packet_id, "decoded head");
+        if len > self.max_size {
+            tracing::warn!(%len, %packet_id, "Frame too big!");
+            return Err(FrameError::TooBig { len, packet_id });
+        }
+        let Some(n) = (len as usize).checked_sub(HEAD_LEN) else {
+            return Err(FrameError::TooSmall { len, packet_id });
+        };
+        // Make room for the body.
+        self.buf.reserve(n + self.seal.len());
+        Ok(Some((n, packet_id)))
+    }
+}
+
+/// Encrypts frames into a buffer, ready to be written.
+#[derive(Debug)]
+pub struct FrameEncoder<C: Cipher> {
+    /// Cipher Used to Encrypt Packets
+    cipher: C,
+    /// Encrypted frames, not written yet.
+    buf: BytesMut,
+    /// Added at the end of every frame, not counted in its length.
+    seal: &'static [u8],
+}
+
+impl<C: Cipher> FrameEncoder<C> {
+    pub fn new(cipher: C) -> Self {
+        Self {
+            cipher,
+            buf: BytesMut::with_capacity(64),
+            seal: &[],
+        }
+    }
+
+    /// Ends every frame with `seal`, like [`SERVER_SEAL`].
+    pub fn with_seal(mut self, seal: &'static [u8]) -> Self {
+        self.seal = seal;
+        self
+    }
+
+    /// Encodes and encrypts a frame at the end of the buffer.
+    pub fn encode(&mut self, packet_id: u16, body: &[u8]) -> Result<(), FrameError> {
+        let n = body.len() + HEAD_LEN;
+        if n > u16::MAX as usize {
+            return Err(FrameError::BodyTooBig {
+                len: body.len(),
+                packet_id,
+            });
+        }
+        let config = HexConfig {
+            title: false,
+            ..Default::default()
+        };
+        tracing::trace!(
+            "\nServer -> Client ID({packet_id}) Length({n})\n{:?}",
+            body.hex_conf(config)
+        );
+        let start = self.buf.len();
+        self.buf.reserve(n + self.seal.len());
+        self.buf.put_u16_le(n as u16); // packet length (0) -> (2)
+        self.buf.put_u16_le(packet_id); // packet type (2) -> (4)
+        self.buf.extend_from_slice(body); // packet_body (4) -> (packet_length)
+        self.buf.extend_from_slice(self.seal); // seal, if any
+        self.cipher.encrypt(&mut self.buf[start..]);
+        Ok(())
+    }
+
+    /// Takes everything encoded so far, leaving the buffer empty.
+    pub fn take(&mut self) -> Bytes {
+        self.buf.split().freeze()
+    }
+
+    /// The encoded frames, writing from it (and advancing it) is the same as
+    /// calling [`FrameEncoder::take`], without giving up the buffer.
+    pub fn write_buf(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+
+    /// Number of bytes encoded and not taken yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tq_crypto::{CQCipher, TQCipher};
+
+    #[test]
+    fn frames_split_at_any_point() {
+        // Client encrypts, server decrypts.
+        let mut encoder = FrameEncoder::new(CQCipher::new());
+        let packets = [(1052, &b"hello world"[..]), (1004, &[][..]), (1005, &[7; 300][..])];
+        for (id, body) in packets {
+            encoder.encode(id, body).unwrap();
+        }
+        let bytes = encoder.take();
+        assert_eq!(encoder.buffered(), 0);
+        let mut decoder = FrameDecoder::new(TQCipher::new());
+        let mut decoded = Vec::new();
+        // One byte at a time, the worst case.
+        for b in bytes.chunks(1) {
+            decoder.feed(b);
+            while let Some(frame) = decoder.decode().unwrap() {
+                decoded.push(frame);
+            }
+        }
+        assert_eq!(decoder.buffered(), 0);
+        let expected: Vec<_> = packets
+            .iter()
+            .map(|(id, body)| (*id, Bytes::copy_from_slice(body)))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let mut decoder = FrameDecoder::new(tq_crypto::NopCipher);
+        decoder.feed(&[2, 0, 0x1c, 0x04]);
+        assert_eq!(
+            decoder.decode(),
+            Err(FrameError::TooSmall {
+                len: 2,
+                packet_id: 1052
+            })
+        );
+        let mut decoder = FrameDecoder::new(tq_crypto::NopCipher);
+        decoder.feed(&(MAX_FRAME_SIZE + 1).to_le_bytes());
+        decoder.feed(&1052u16.to_le_bytes());
+        assert!(matches!(decoder.decode(), Err(FrameError::TooBig { .. })));
+        let mut decoder = FrameDecoder::new(tq_crypto::NopCipher).with_max_size(64);
+        decoder.feed(&65u16.to_le_bytes());
+        decoder.feed(&1052u16.to_le_bytes());
+        assert_eq!(
+            decoder.decode(),
+            Err(FrameError::TooBig {
+                len: 65,
+                packet_id: 1052
+            })
+        );
+    }
+
+    #[test]
+    fn sealed_frames() {
+        let mut encoder = FrameEncoder::new(CQCipher::new()).with_seal(CLIENT_SEAL);
+        encoder.encode(1052, b"hello").unwrap();
+        let bytes = encoder.take();
+        // The seal is not part of the length.
+        assert_eq!(bytes.len(), 4 + 5 + 8);
+
+        let mut decoder = FrameDecoder::new(TQCipher::new()).with_seal(CLIENT_SEAL);
+        // Not there until the seal is.
+        decoder.feed(&bytes[..bytes.len() - 1]);
+        assert_eq!(decoder.decode(), Ok(None));
+        decoder.feed(&bytes[bytes.len() - 1..]);
+        assert_eq!(decoder.decode(), Ok(Some((1052, Bytes::from_static(b"hello")))));
+
+        let mut decoder = FrameDecoder::new(TQCipher::new()).with_seal(SERVER_SEAL);
+        decoder.feed(&bytes);
+        assert_eq!(decoder.decode(), Err(FrameError::BadSeal { packet_id: 1052 }));
+    }
+}
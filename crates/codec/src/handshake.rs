@@ -0,0 +1,369 @@
+//! The key exchange the game server and client do since patch 5018, before
+//! the first game packet.
+//!
+//! Right after the client connects, the server sends its half of a
+//! Diffie-Hellman exchange along with the Blowfish IVs, the client answers
+//! with its own public key, and both sides switch their
+//! [`BlowfishCipher`](tq_crypto::BlowfishCipher) to the shared secret. Both
+//! messages are encrypted with the Blowfish default key, and they are not
+//! framed like packets:
+//!
+//! ```text
+//! ServerKeyExchange:
+//! | padding (11) | size (4) | junk len (4) | junk | encrypt iv len (4) | encrypt iv |
+//! | decrypt iv len (4) | decrypt iv | p len (4) | p | g len (4) | g |
+//! | public key len (4) | public key | "TQServer" |
+//!
+//! ClientKeyExchange:
+//! | padding (7) | size (4) | junk len (4) | junk | public key len (4) | public key |
+//! | "TQClient" |
+//! ```
+//!
+//! `size` counts everything after the padding, the numbers are little endian
+//! and `p`, `g` and the public keys are upper case hex strings.
+
+use crate::frame::{CLIENT_SEAL, SERVER_SEAL};
+use bytes::{Buf, BufMut, BytesMut};
+use core::fmt;
+
+/// The biggest key exchange message we accept, a lot more than the keys need.
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+
+const IV_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    IO(std::io::Error),
+    /// The size field is out of range.
+    BadSize(usize),
+    /// A field does not fit in the message, or has the wrong size.
+    BadField,
+    /// The message does not end with the expected seal.
+    BadSeal,
+    /// A key is not valid UTF-8.
+    InvalidString,
+    #[cfg(feature = "handshake")]
+    KeyExchange(tq_crypto::dh::DhError),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "IO Error: {e}"),
+            Self::BadSize(size) => write!(f, "Bad Key Exchange Size ({size} bytes)"),
+            Self::BadField => write!(f, "Bad Key Exchange Field"),
+            Self::BadSeal => write!(f, "Bad Key Exchange Seal"),
+            Self::InvalidString => write!(f, "Invalid Key Exchange String"),
+            #[cfg(feature = "handshake")]
+            Self::KeyExchange(e) => write!(f, "Key Exchange Error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<std::io::Error> for HandshakeError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+#[cfg(feature = "handshake")]
+impl From<tq_crypto::dh::DhError> for HandshakeError {
+    fn from(e: tq_crypto::dh::DhError) -> Self {
+        Self::KeyExchange(e)
+    }
+}
+
+impl From<HandshakeError> for std::io::Error {
+    fn from(e: HandshakeError) -> Self {
+        match e {
+            HandshakeError::IO(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// The server's half of the key exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerKeyExchange {
+    /// The IV the server encrypts with, and the client decrypts with.
+    pub encrypt_iv: [u8; IV_SIZE],
+    /// The IV the server decrypts with, and the client encrypts with.
+    pub decrypt_iv: [u8; IV_SIZE],
+    pub p: String,
+    pub g: String,
+    pub public_key: String,
+}
+
+impl ServerKeyExchange {
+    pub const PADDING: usize = 11;
+
+    pub fn encode(&self, junk: &[u8]) -> BytesMut {
+        let mut buf = begin(Self::PADDING, junk);
+        put_field(&mut buf, &self.encrypt_iv);
+        put_field(&mut buf, &self.decrypt_iv);
+        put_field(&mut buf, self.p.as_bytes());
+        put_field(&mut buf, self.g.as_bytes());
+        put_field(&mut buf, self.public_key.as_bytes());
+        finish(buf, Self::PADDING, SERVER_SEAL)
+    }
+
+    pub fn decode(msg: &[u8]) -> Result<Self, HandshakeError> {
+        let mut fields = Fields::new(msg, Self::PADDING, SERVER_SEAL)?;
+        Ok(Self {
+            encrypt_iv: fields.iv()?,
+            decrypt_iv: fields.iv()?,
+            p: fields.string()?,
+            g: fields.string()?,
+            public_key: fields.string()?,
+        })
+    }
+}
+
+/// The client's half of the key exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientKeyExchange {
+    pub public_key: String,
+}
+
+impl ClientKeyExchange {
+    pub const PADDING: usize = 7;
+
+    pub fn encode(&self, junk: &[u8]) -> BytesMut {
+        let mut buf = begin(Self::PADDING, junk);
+        put_field(&mut buf, self.public_key.as_bytes());
+        finish(buf, Self::PADDING, CLIENT_SEAL)
+    }
+
+    pub fn decode(msg: &[u8]) -> Result<Self, HandshakeError> {
+        let mut fields = Fields::new(msg, Self::PADDING, CLIENT_SEAL)?;
+        Ok(Self {
+            public_key: fields.string()?,
+        })
+    }
+}
+
+/// The length of the whole message, from its first `padding + 4` bytes.
+pub fn message_len(padding: usize, head: &[u8]) -> Result<usize, HandshakeError> {
+    let mut size = head.get(padding..padding + 4).ok_or(HandshakeError::BadField)?;
+    let size = size.get_u32_le() as usize;
+    // At least the size, the junk length and the seal.
+    if !(4 + 4 + 8..=MAX_MESSAGE_SIZE).contains(&size) {
+        return Err(HandshakeError::BadSize(size));
+    }
+    Ok(padding + size)
+}
+
+fn begin(padding: usize, junk: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(256);
+    buf.put_bytes(0, padding);
+    buf.put_u32_le(0); // the size, once we know it.
+    put_field(&mut buf, junk);
+    buf
+}
+
+fn finish(mut buf: BytesMut, padding: usize, seal: &[u8]) -> BytesMut {
+    buf.extend_from_slice(seal);
+    let size = (buf.len() - padding) as u32;
+    buf[padding..padding + 4].copy_from_slice(&size.to_le_bytes());
+    buf
+}
+
+fn put_field(buf: &mut BytesMut, field: &[u8]) {
+    buf.put_u32_le(field.len() as u32);
+    buf.extend_from_slice(field);
+}
+
+/// The length prefixed fields of a message, after the junk.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn new(msg: &'a [u8], padding: usize, seal: &[u8]) -> Result<Self, HandshakeError> {
+        if message_len(padding, msg)? != msg.len() {
+            return Err(HandshakeError::BadSize(msg.len()));
+        }
+        let body = msg[padding + 4..].strip_suffix(seal).ok_or(HandshakeError::BadSeal)?;
+        let mut fields = Self(body);
+        // The junk is there to be skipped.
+        fields.next()?;
+        Ok(fields)
+    }
+
+    fn next(&mut self) -> Result<&'a [u8], HandshakeError> {
+        if self.0.len() < 4 {
+            return Err(HandshakeError::BadField);
+        }
+        let len = self.0.get_u32_le() as usize;
+        if self.0.len() < len {
+            return Err(HandshakeError::BadField);
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn iv(&mut self) -> Result<[u8; IV_SIZE], HandshakeError> {
+        self.next()?.try_into().map_err(|_| HandshakeError::BadField)
+    }
+
+    fn string(&mut self) -> Result<String, HandshakeError> {
+        let field = self.next()?;
+        String::from_utf8(field.to_vec()).map_err(|_| HandshakeError::InvalidString)
+    }
+}
+
+#[cfg(feature = "handshake")]
+pub use self::io::{accept, connect};
+
+#[cfg(feature = "handshake")]
+mod io {
+    use super::*;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tq_crypto::dh::{DiffieHellman, DEFAULT_G, DEFAULT_P};
+    use tq_crypto::{BlowfishCipher, Cipher};
+
+    /// Most junk the clients send is about this long.
+    const MAX_JUNK_SIZE: usize = 32;
+
+    /// The server side, run right after the client connects.
+    ///
+    /// `cipher` must still have the default key, it is switched to the shared
+    /// secret once the client answered.
+    pub async fn accept<S>(stream: &mut S, cipher: &BlowfishCipher) -> Result<(), HandshakeError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let dh = DiffieHellman::new(DEFAULT_P, DEFAULT_G)?;
+        let msg = ServerKeyExchange {
+            encrypt_iv: rand::random(),
+            decrypt_iv: rand::random(),
+            p: dh.p(),
+            g: dh.g(),
+            public_key: dh.public_key(),
+        };
+        write_message(stream, cipher, msg.encode(&junk())).await?;
+        let res = read_message(stream, cipher, ClientKeyExchange::PADDING).await?;
+        let res = ClientKeyExchange::decode(&res)?;
+        let secret = dh.compute_key(&res.public_key)?;
+        cipher.set_key(secret.as_bytes());
+        cipher.set_ivs(msg.encrypt_iv, msg.decrypt_iv);
+        Ok(())
+    }
+
+    /// The client side, mostly useful for bots and tests.
+    pub async fn connect<S>(stream: &mut S, cipher: &BlowfishCipher) -> Result<(), HandshakeError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let msg = read_message(stream, cipher, ServerKeyExchange::PADDING).await?;
+        let msg = ServerKeyExchange::decode(&msg)?;
+        let dh = DiffieHellman::new(&msg.p, &msg.g)?;
+        let res = ClientKeyExchange {
+            public_key: dh.public_key(),
+        };
+        write_message(stream, cipher, res.encode(&junk())).await?;
+        let secret = dh.compute_key(&msg.public_key)?;
+        cipher.set_key(secret.as_bytes());
+        // The other way around.
+        cipher.set_ivs(msg.decrypt_iv, msg.encrypt_iv);
+        Ok(())
+    }
+
+    fn junk() -> Vec<u8> {
+        let len = rand::random::<usize>() % MAX_JUNK_SIZE;
+        (0..len).map(|_| rand::random()).collect()
+    }
+
+    async fn write_message<S>(stream: &mut S, cipher: &BlowfishCipher, mut msg: BytesMut) -> Result<(), HandshakeError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        cipher.encrypt(&mut msg);
+        stream.write_all(&msg).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn read_message<S>(
+        stream: &mut S,
+        cipher: &BlowfishCipher,
+        padding: usize,
+    ) -> Result<BytesMut, HandshakeError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut msg = BytesMut::zeroed(padding + 4);
+        stream.read_exact(&mut msg).await?;
+        cipher.decrypt(&mut msg);
+        let len = message_len(padding, &msg)?;
+        let head = msg.len();
+        msg.resize(len, 0);
+        stream.read_exact(&mut msg[head..]).await?;
+        cipher.decrypt(&mut msg[head..]);
+        Ok(msg)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn both_sides_agree() {
+            let (mut client, mut server) = tokio::io::duplex(1024);
+            let client_cipher = BlowfishCipher::default();
+            let server_cipher = BlowfishCipher::default();
+            let (a, b) = tokio::join!(
+                connect(&mut client, &client_cipher),
+                accept(&mut server, &server_cipher)
+            );
+            a.unwrap();
+            b.unwrap();
+
+            let mut data = *b"hello world";
+            server_cipher.encrypt(&mut data);
+            client_cipher.decrypt(&mut data);
+            assert_eq!(&data, b"hello world");
+            client_cipher.encrypt(&mut data);
+            server_cipher.decrypt(&mut data);
+            assert_eq!(&data, b"hello world");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let msg = ServerKeyExchange {
+            encrypt_iv: [1; 8],
+            decrypt_iv: [2; 8],
+            p: String::from("17"),
+            g: String::from("05"),
+            public_key: String::from("8"),
+        };
+        let bytes = msg.encode(b"junk");
+        assert_eq!(
+            message_len(ServerKeyExchange::PADDING, &bytes[..15]).unwrap(),
+            bytes.len()
+        );
+        assert!(bytes.ends_with(SERVER_SEAL));
+        assert_eq!(ServerKeyExchange::decode(&bytes).unwrap(), msg);
+
+        let res = ClientKeyExchange {
+            public_key: String::from("13"),
+        };
+        let mut bytes = res.encode(&[]);
+        // padding, size, junk, key and seal.
+        assert_eq!(bytes.len(), 7 + 4 + 4 + 4 + 2 + 8);
+        assert_eq!(ClientKeyExchange::decode(&bytes).unwrap(), res);
+        let last = bytes.len() - 1;
+        bytes[last] = b'x';
+        assert!(matches!(
+            ClientKeyExchange::decode(&bytes),
+            Err(HandshakeError::BadSeal)
+        ));
+    }
+}
@@ -1,5 +1,6 @@
-//! crate that contians a [`TQCodec`] that wraps any [`AsyncRead`] +
-//! [`AsyncWrite`] and Outputs a Stream-like [`TQDecoder`] of `(u16, Bytes)`
+//! crate that contians a [`TQCodec`] that wraps any
+//! [`AsyncRead`](tokio::io::AsyncRead) + [`AsyncWrite`](tokio::io::AsyncWrite)
+//! and Outputs a Stream-like [`TQDecoder`] of `(u16, Bytes)`
 //! where the `u16`s the are the PacketID and Bytes is the Body of the Packet.
 //! It also implements Sink-like
 //! [`TQEncoder`] where you could write `(u16, Bytes)` to it.
@@ -18,296 +19,282 @@
 //! the first 2 | bytes are the length
 //!             the next 2 bytes are the packet id.
 //! ```
+//!
+//! The framing itself lives in the [`frame`] module, without any IO, the
+//! tokio types here (behind the `tokio` feature) only move the bytes between
+//! it and the socket. The key exchange later patches do before the first
+//! frame lives in the [`handshake`] module.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
 #[cfg(not(feature = "std"))]
 extern crate alloc;
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use core::future::Future;
-use core::pin::Pin;
-use core::task::{Context, Poll};
-use pretty_hex::{HexConfig, PrettyHex};
-use tokio::io::{self, split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
-use tokio_stream::Stream;
-use tq_crypto::Cipher;
-
-const KB: usize = 1024;
-const MB: usize = 1024 * KB;
-const MAX_PACKET_SIZE: u16 = 2 * KB as u16;
-const MAX_CAPACITY: usize = 10 * MB;
+#[cfg(feature = "std")]
+pub mod capture;
 
-/// A simple State Machine for Decoding the Stream.
-#[derive(Debug, Clone, Copy)]
-enum DecodeState {
-    /// Decoding the Head of the Packet (4 Bytes for the Size + PacketID)
-    Head,
-    /// Decoding the Packet Bytes (usize => Packet Size, u16 => PacketID)
-    Data((usize, u16)),
-}
+pub mod frame;
 
-#[derive(Debug)]
-pub struct TQDecoder<S: AsyncRead + AsyncWrite, C: Cipher> {
-    /// Current Decode State
-    state: DecodeState,
-    /// Cipher Used to Decrypt Packets
-    cipher: C,
-    /// Buffer used when reading from the stream. Data is not returned from
-    /// this buffer until an entire packet has been read.
-    buf: BytesMut,
-    /// The Underlaying Read Half of Socket
-    rdr: ReadHalf<S>,
-}
+#[cfg(feature = "std")]
+pub mod handshake;
 
-impl<S: AsyncRead + AsyncWrite, C: Cipher> TQDecoder<S, C> {
-    /// Read data from the socket.
-    ///
-    /// This only returns `Ready` when the socket has closed.
-    fn fill_read_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        loop {
-            // Ensure the read buffer has capacity.
-            //
-            // This might result in an internal allocation.
-            self.buf.reserve(64);
+#[cfg(feature = "tokio")]
+pub use self::io_codec::{TQCodec, TQDecoder, TQEncoder};
 
-            // Read data into the buffer.
-            let n: usize = {
-                let p = self.rdr.read_buf(&mut self.buf);
-                tokio::pin!(p);
-                match p.poll(cx) {
-                    Poll::Pending => return Poll::Pending,
-                    Poll::Ready(v) => v?,
-                }
-            };
+#[cfg(feature = "tokio")]
+mod io_codec {
+    #[cfg(feature = "std")]
+    use crate::capture;
+    use crate::frame::{FrameDecoder, FrameEncoder, MAX_FRAME_SIZE};
+    use bytes::{Buf, Bytes};
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use tokio::io::{self, split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+    use tokio_stream::Stream;
+    use tq_crypto::Cipher;
 
-            if n == 0 {
-                return Poll::Ready(Ok(()));
-            }
-        }
+    /// Stream of the decoded packets, an adapter over a [`FrameDecoder`].
+    #[derive(Debug)]
+    pub struct TQDecoder<S: AsyncRead + AsyncWrite, C: Cipher> {
+        frames: FrameDecoder<C>,
+        /// The Underlaying Read Half of Socket
+        rdr: ReadHalf<S>,
+        /// Records the decoded packets, if any.
+        #[cfg(feature = "std")]
+        recorder: Option<capture::Recorder>,
     }
 
-    #[tracing::instrument(skip(self))]
-    fn decode_head(&mut self) -> io::Result<Option<(usize, u16)>> {
-        tracing::trace!(buf_len = %self.buf.len(), "buffer bytes");
-        if self.buf.len() < 4 {
-            // Not enough data
-            tracing::trace!("no enough data");
-            return Ok(None);
+    impl<S: AsyncRead + AsyncWrite, C: Cipher> TQDecoder<S, C> {
+        /// The recorder attached to this connection, if any.
+        #[cfg(feature = "std")]
+        pub fn recorder(&self) -> Option<&capture::Recorder> {
+            self.recorder.as_ref()
         }
-        let (n, packet_type) = {
-            let mut len = self.buf.split_to(2);
-            let mut ty = self.buf.split_to(2);
-            // Get the decrypted head len.
-            self.cipher.decrypt(&mut len);
-            // Get the decrypted head packet type.
-            self.cipher.decrypt(&mut ty);
-            // get length
-            let n = len.as_ref().get_u16_le();
-            // get type
-            let packet_id = ty.as_ref().get_u16_le();
-            tracing::trace!(%n, %packet_id, "decoded head");
-            if n > MAX_PACKET_SIZE {
-                tracing::warn!(%n, %packet_id, "Frame too big!");
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame Too Big"));
+
+        /// Read data from the socket.
+        ///
+        /// This only returns `Ready` when the socket has closed.
+        fn fill_read_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+            loop {
+                let buf = self.frames.read_buf();
+                // Ensure the read buffer has capacity.
+                //
+                // This might result in an internal allocation.
+                buf.reserve(64);
+
+                // Read data into the buffer.
+                let n: usize = {
+                    let p = self.rdr.read_buf(buf);
+                    tokio::pin!(p);
+                    match p.poll(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(v) => v?,
+                    }
+                };
+
+                if n == 0 {
+                    return Poll::Ready(Ok(()));
+                }
             }
-            (n as usize, packet_id)
-        };
-        // Ensure that the buffer has enough space to read the incoming
-        // payload
-        self.buf.reserve(n - 4);
+        }
+    }
 
-        Ok(Some((n - 4, packet_type)))
+    /// Sink of packets, an adapter over a [`FrameEncoder`].
+    pub struct TQEncoder<S: AsyncRead + AsyncWrite, C: Cipher> {
+        frames: FrameEncoder<C>,
+        /// The Underlaying Write Half of Socket
+        wrt: WriteHalf<S>,
+        /// Records the encoded packets, if any.
+        #[cfg(feature = "std")]
+        recorder: Option<capture::Recorder>,
     }
 
-    #[tracing::instrument(skip(self))]
-    fn decode_data(&mut self, n: usize) -> io::Result<Option<BytesMut>> {
-        tracing::trace!(
-            data_len = %n,
-            current_buf_len = %self.buf.len(),
-            "decoding data"
-        );
+    impl<S: AsyncRead + AsyncWrite, C: Cipher> TQEncoder<S, C> {
+        /// The recorder attached to this connection, if any.
+        #[cfg(feature = "std")]
+        pub fn recorder(&self) -> Option<&capture::Recorder> {
+            self.recorder.as_ref()
+        }
 
-        // At this point, the buffer has already had the required capacity
-        // reserved. All there is to do is read.
+        /// Send Item to the Underlaying Socket.
+        ///
+        /// Items (u16, Bytes) got Encoded and Encrypted and sent to socket.
+        #[tracing::instrument(skip(self, item))]
+        pub async fn send(&mut self, item: (u16, Bytes)) -> Result<(), io::Error> {
+            self.feed(item)?;
+            self.flush().await?;
+            Ok(())
+        }
 
-        if self.buf.len() < n {
-            tracing::trace!("Buffer too small, skipping");
-            return Ok(None);
+        /// Encode, Encrypt and buffer the item without writing it to the
+        /// socket, call [`TQEncoder::flush`] to write everything buffered so
+        /// far at once.
+        #[tracing::instrument(skip(self, item))]
+        pub fn feed(&mut self, item: (u16, Bytes)) -> Result<(), io::Error> {
+            #[cfg(feature = "std")]
+            if let Some(recorder) = &self.recorder {
+                recorder.record(capture::Direction::ServerToClient, item.0, &item.1);
+            }
+            self.frames.encode(item.0, &item.1)?;
+            Ok(())
         }
-        let mut buf = self.buf.split_to(n);
-        self.cipher.decrypt(&mut buf);
-        Ok(Some(buf))
-    }
-}
 
-pub struct TQEncoder<S: AsyncRead + AsyncWrite, C: Cipher> {
-    /// Cipher Used to Encrypt Packets
-    cipher: C,
-    /// Buffer used to stage data before writing it to the socket.
-    buf: BytesMut,
-    /// The Underlaying Write Half of Socket
-    wrt: WriteHalf<S>,
-}
+        /// Number of bytes buffered and not yet written to the socket.
+        pub fn buffered(&self) -> usize {
+            self.frames.buffered()
+        }
 
-impl<S: AsyncRead + AsyncWrite, C: Cipher> TQEncoder<S, C> {
-    /// Send Item to the Underlaying Socket.
-    ///
-    /// Items (u16, Bytes) got Encoded and Encrypted and sent to socket.
-    #[tracing::instrument(skip(self, item))]
-    pub async fn send(&mut self, item: (u16, Bytes)) -> Result<(), io::Error> {
-        let buf = self.encode_data(item.0, item.1)?;
-        self.buffer(&buf);
-        self.flush().await?;
-        Ok(())
-    }
+        /// Close The Socket .. No More IO.
+        #[tracing::instrument(skip(self))]
+        pub async fn close(&mut self) -> Result<(), io::Error> {
+            tracing::trace!("Sutting down socket");
+            self.wrt.shutdown().await?;
+            Ok(())
+        }
 
-    /// Close The Socket .. No More IO.
-    #[tracing::instrument(skip(self))]
-    pub async fn close(&mut self) -> Result<(), io::Error> {
-        tracing::trace!("Sutting down socket");
-        self.wrt.shutdown().await?;
-        Ok(())
+        /// Flush the write buffer to the socket
+        #[tracing::instrument(skip(self))]
+        pub async fn flush(&mut self) -> Result<(), io::Error> {
+            tracing::trace!("flushing data into stream");
+            let buf = self.frames.write_buf();
+            // As long as there is buffered data to write, try to write it.
+            while buf.has_remaining() {
+                let n = self.wrt.write_buf(buf).await?;
+                tracing::trace!("written {} bytes", n);
+            }
+            self.wrt.flush().await?;
+            Ok(())
+        }
     }
 
-    #[tracing::instrument(skip(self, body))]
-    fn encode_data(&self, packet_id: u16, body: Bytes) -> io::Result<Bytes> {
-        tracing::trace!(%packet_id, "encoding packet");
-        let n = body.len() + 4;
-        let mut result = BytesMut::with_capacity(n);
-        result.put_u16_le(n as u16); // packet length (0) -> (2)
-        result.put_u16_le(packet_id); // packet type (2) -> (4)
-        result.extend_from_slice(&body); // packet_body (4) -> (packet_length)
-        let mut full_packet = result;
-        let config = HexConfig {
-            title: false,
-            ..Default::default()
-        };
-        tracing::trace!(
-            "\nServer -> Client ID({packet_id}) Length({n})\n{:?}",
-            body.as_ref().hex_conf(config)
-        );
-        // encrypt data
-        self.cipher.encrypt(&mut full_packet);
-        Ok(full_packet.freeze())
+    #[derive(Debug)]
+    pub struct TQCodec<S: AsyncRead + AsyncWrite, C: Cipher + Clone> {
+        stream: S,
+        cipher: C,
+        /// Expected at the end of every frame read.
+        read_seal: &'static [u8],
+        /// Added at the end of every frame written.
+        write_seal: &'static [u8],
+        /// The biggest frame we read.
+        max_frame_size: u16,
+        #[cfg(feature = "std")]
+        recorder: Option<capture::Recorder>,
     }
 
-    /// Buffer a packet.
-    ///
-    /// This writes the packet to an internal buffer. Calls to `flush` will
-    /// attempt to flush this buffer to the socket.
-    #[tracing::instrument(skip(self, buf))]
-    fn buffer(&mut self, buf: &[u8]) {
-        // Ensure the buffer has capacity. Ideally this would not be unbounded,
-        // but to keep the example simple, we will not limit this.
-        if self.buf.capacity() <= buf.len() && self.buf.capacity() < MAX_CAPACITY {
-            self.buf.reserve(buf.len());
+    impl<S: AsyncRead + AsyncWrite, C: Cipher + Clone> TQCodec<S, C> {
+        pub fn new(stream: S, cipher: C) -> Self {
+            Self {
+                stream,
+                cipher,
+                read_seal: &[],
+                write_seal: &[],
+                max_frame_size: MAX_FRAME_SIZE,
+                #[cfg(feature = "std")]
+                recorder: None,
+            }
         }
 
-        // Push the packet onto the end of the write buffer.
-        //
-        // The `put` function is from the `BufMut` trait.
-        self.buf.put(buf);
-        tracing::trace!(
-            buf_len = %buf.len(),
-            "bytes got buffered and ready to be sent",
-        );
-    }
+        /// Seals the frames in both directions, see
+        /// [`FrameDecoder::with_seal`] and [`FrameEncoder::with_seal`].
+        pub fn with_seals(mut self, read: &'static [u8], write: &'static [u8]) -> Self {
+            self.read_seal = read;
+            self.write_seal = write;
+            self
+        }
 
-    /// Flush the write buffer to the socket
-    #[tracing::instrument(skip(self))]
-    async fn flush(&mut self) -> Result<(), io::Error> {
-        tracing::trace!("flushing data into stream");
-        // As long as there is buffered data to write, try to write it.
-        while self.buf.has_remaining() {
-            let n = self.wrt.write_buf(&mut self.buf).await?;
-            tracing::trace!("written {} bytes", n);
+        /// Rejects the frames bigger than `max_size`, see
+        /// [`FrameDecoder::with_max_size`].
+        pub fn with_max_frame_size(mut self, max_size: u16) -> Self {
+            self.max_frame_size = max_size;
+            self
         }
-        self.wrt.flush().await?;
-        Ok(())
-    }
-}
 
-#[derive(Debug)]
-pub struct TQCodec<S: AsyncRead + AsyncWrite, C: Cipher + Clone> {
-    stream: S,
-    cipher: C,
-}
+        /// Records every decrypted packet going in and out using this
+        /// [`capture::Recorder`].
+        #[cfg(feature = "std")]
+        pub fn with_recorder(mut self, recorder: capture::Recorder) -> Self {
+            self.recorder = Some(recorder);
+            self
+        }
 
-impl<S: AsyncRead + AsyncWrite, C: Cipher + Clone> TQCodec<S, C> {
-    pub fn new(stream: S, cipher: C) -> Self {
-        Self { stream, cipher }
+        pub fn split(self) -> (TQEncoder<S, C>, TQDecoder<S, C>) {
+            let (rdr, wrt) = split(self.stream);
+            let encoder = TQEncoder {
+                frames: FrameEncoder::new(self.cipher.clone()).with_seal(self.write_seal),
+                wrt,
+                #[cfg(feature = "std")]
+                recorder: self.recorder.clone(),
+            };
+            let decoder = TQDecoder {
+                frames: FrameDecoder::new(self.cipher)
+                    .with_seal(self.read_seal)
+                    .with_max_size(self.max_frame_size),
+                rdr,
+                #[cfg(feature = "std")]
+                recorder: self.recorder,
+            };
+            (encoder, decoder)
+        }
     }
 
-    pub fn split(self) -> (TQEncoder<S, C>, TQDecoder<S, C>) {
-        let (rdr, wrt) = split(self.stream);
-        let encoder = TQEncoder {
-            buf: BytesMut::with_capacity(64),
-            cipher: self.cipher.clone(),
-            wrt,
-        };
-        let decoder = TQDecoder {
-            state: DecodeState::Head,
-            buf: BytesMut::with_capacity(64),
-            cipher: self.cipher,
-            rdr,
-        };
-        (encoder, decoder)
-    }
-}
+    impl<S, C> Stream for TQDecoder<S, C>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        C: Cipher + Unpin,
+    {
+        type Item = io::Result<(u16, Bytes)>;
 
-impl<S, C> Stream for TQDecoder<S, C>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-    C: Cipher + Unpin,
-{
-    type Item = io::Result<(u16, Bytes)>;
+        #[tracing::instrument(skip(self, cx))]
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            // First, read any new data that might have been received off the
+            // socket
+            let sock_closed = self.fill_read_buf(cx)?.is_ready();
+            tracing::trace!("Socket Close? {}", sock_closed);
+            if let Some((packet_id, data)) = self.frames.decode()? {
+                #[cfg(feature = "std")]
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(capture::Direction::ClientToServer, packet_id, &data);
+                }
+                return Poll::Ready(Some(Ok((packet_id, data))));
+            }
 
-    #[tracing::instrument(skip(self, cx))]
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // First, read any new data that might have been received off the socket
-        let sock_closed = self.fill_read_buf(cx)?.is_ready();
-        tracing::trace!("Socket Close? {}", sock_closed);
-        let (n, packet_id) = match self.state {
-            DecodeState::Head => match self.decode_head()? {
-                Some((n, packet_id)) => {
-                    self.state = DecodeState::Data((n, packet_id));
-                    (n, packet_id)
-                },
-                None => {
-                    if sock_closed {
-                        // we know we will get here when we read zero from the
-                        // socket so we need to stop
-                        // looping and just free all resources
-                        return Poll::Ready(None);
-                    } else {
-                        return Poll::Pending;
-                    }
-                },
-            },
-            DecodeState::Data((n, packet_id)) => (n, packet_id),
-        };
-        if let Some(data) = self.decode_data(n)? {
-            let config = HexConfig {
-                title: false,
-                ..Default::default()
-            };
-            let packet_len = n + 4;
-            tracing::trace!(
-                "\nClient -> Server ID({packet_id}) Length({packet_len})\n{:?}",
-                data.as_ref().hex_conf(config)
-            );
-            // Update the decode state
-            self.state = DecodeState::Head;
-            let data = Ok((packet_id, data.freeze()));
-            return Poll::Ready(Some(data));
+            if sock_closed {
+                // we know we will get here when we read zero from the socket
+                // so we need to stop looping and just free all resources
+                tracing::trace!("Socket Closed, end of stream!");
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
         }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tokio_stream::StreamExt;
+        use tq_crypto::NopCipher;
 
-        if sock_closed {
-            tracing::warn!("Socket Closed, end of stream!");
-            Poll::Ready(None)
-        } else {
-            Poll::Pending
+        #[tokio::test]
+        async fn feed_then_flush_once() {
+            let (client, server) = io::duplex(1024);
+            let (mut encoder, _) = TQCodec::new(server, NopCipher).split();
+            let (_, mut decoder) = TQCodec::new(client, NopCipher).split();
+            let packets = [
+                (1004, Bytes::from_static(b"hello")),
+                (1005, Bytes::new()),
+                (1010, Bytes::from_static(&[1, 2, 3, 4])),
+            ];
+            for packet in packets.iter().cloned() {
+                encoder.feed(packet).unwrap();
+            }
+            assert_eq!(encoder.buffered(), 3 * 4 + 5 + 4);
+            encoder.flush().await.unwrap();
+            assert_eq!(encoder.buffered(), 0);
+            for packet in packets {
+                assert_eq!(decoder.next().await.unwrap().unwrap(), packet);
+            }
         }
     }
 }
@@ -0,0 +1,709 @@
+//! The CoEmu configuration, a single TOML file shared by the servers and the
+//! tools.
+//!
+//! Every setting has a default, so the file (and any of its sections) is
+//! optional. The settings are read in this order, the later wins:
+//!
+//! 1. The defaults.
+//! 2. The file given with `--config`, or `coemu.toml` in the working directory
+//!    if there is one.
+//! 3. The environment variables (and `.env`), see [`Config::apply_vars`] for
+//!    the names.
+//!
+//! See `coemu.example.toml` at the root of the repository for every setting.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tq_network::{ProtocolVersion, ViolationKind};
+use tq_server::violation::ViolationAction;
+
+/// The command line flags every CoEmu program understands.
+#[derive(argh::FromArgs, Debug)]
+pub struct Args {
+    /// the configuration file, `coemu.toml` by default
+    #[argh(option)]
+    pub config: Option<PathBuf>,
+    /// validate the configuration and exit
+    #[argh(switch)]
+    pub check_config: bool,
+}
+
+impl Args {
+    /// Parses the command line, exits on `--help` or bad flags.
+    pub fn from_env() -> Self {
+        argh::from_env()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+    /// An environment variable could not be parsed.
+    Env {
+        var: &'static str,
+        reason: String,
+    },
+    /// A setting has an invalid value.
+    Invalid {
+        setting: &'static str,
+        reason: String,
+    },
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::IO(path, e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Self::Toml(path, e) => write!(f, "Invalid configuration in {}: {}", path.display(), e),
+            Self::Env { var, reason } => write!(f, "Invalid environment variable {var}: {reason}"),
+            Self::Invalid { setting, reason } => write!(f, "Invalid setting {setting}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    fn invalid(setting: &'static str, reason: impl Into<String>) -> Self {
+        Self::Invalid {
+            setting,
+            reason: reason.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The game data, maps and such.
+    pub data_dir: PathBuf,
+    pub log: LogConfig,
+    pub database: DatabaseConfig,
+    pub bus: BusConfig,
+    pub auth: AuthConfig,
+    pub game: GameConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("./data"),
+            log: LogConfig::default(),
+            database: DatabaseConfig::default(),
+            bus: BusConfig::default(),
+            auth: AuthConfig::default(),
+            game: GameConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Used when there is no `--config`, if it exists.
+    pub const DEFAULT_PATH: &'static str = "coemu.toml";
+
+    /// Loads the configuration from `path` (or [`Config::DEFAULT_PATH`]),
+    /// then the environment, and validates it.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(Self::DEFAULT_PATH).exists() => Self::from_file(Path::new(Self::DEFAULT_PATH))?,
+            None => Self::default(),
+        };
+        config.apply_vars(|var| std::env::var(var).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let toml = std::fs::read_to_string(path).map_err(|e| Error::IO(path.to_owned(), e))?;
+        toml::from_str(&toml).map_err(|e| Error::Toml(path.to_owned(), e))
+    }
+
+    /// Overrides the settings with the variables found by `vars`, for the
+    /// deployments that only have environment variables:
+    ///
+    /// | Variable | Setting |
+    /// |----------|---------|
+    /// | `DATA_LOCATION` | `data_dir` |
+    /// | `LOG_VERBOSITY` | `log.verbosity` |
+    /// | `DATABASE_URL` | `database.url` |
+    /// | `BUS_SECRET` | `bus.secret` |
+    /// | `AUTH_PORT` | `auth.port` |
+    /// | `AUTH_BUS_PORT` | `auth.bus_port` |
+    /// | `AUTH_METRICS_ADDR` | `auth.metrics_addr` |
+    /// | `AUTH_TRUSTED_PROXIES` | `auth.server.trusted_proxies`, comma separated |
+    /// | `GAME_REALM` | `game.realm` |
+    /// | `GAME_AUTH_BUS` | `game.auth_bus` |
+    /// | `GAME_CAPACITY` | `game.capacity` |
+    /// | `GAME_PATCH` | `game.server.patch` |
+    /// | `GAME_PATCHES` | `game.server.patches`, comma separated |
+    /// | `GAME_METRICS_ADDR` | `game.metrics_addr` |
+    /// | `GAME_TRUSTED_PROXIES` | `game.server.trusted_proxies`, comma separated |
+    /// | `CAPTURE_FILE` | `game.capture.file` |
+    /// | `CAPTURE_ALL` | `game.capture.record_all` |
+    pub fn apply_vars<F>(&mut self, vars: F) -> Result<(), Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let vars = Vars(vars);
+        vars.set("DATA_LOCATION", &mut self.data_dir)?;
+        vars.set("LOG_VERBOSITY", &mut self.log.verbosity)?;
+        vars.set_some("DATABASE_URL", &mut self.database.url)?;
+        vars.set_some("BUS_SECRET", &mut self.bus.secret)?;
+        vars.set("AUTH_PORT", &mut self.auth.port)?;
+        vars.set("AUTH_BUS_PORT", &mut self.auth.bus_port)?;
+        vars.set_some("AUTH_METRICS_ADDR", &mut self.auth.metrics_addr)?;
+        vars.set_list("AUTH_TRUSTED_PROXIES", &mut self.auth.server.trusted_proxies);
+        vars.set("GAME_REALM", &mut self.game.realm)?;
+        vars.set("GAME_AUTH_BUS", &mut self.game.auth_bus)?;
+        vars.set("GAME_CAPACITY", &mut self.game.capacity)?;
+        vars.set_some("GAME_PATCH", &mut self.game.server.patch)?;
+        vars.set_all("GAME_PATCHES", &mut self.game.server.patches)?;
+        vars.set_some("GAME_METRICS_ADDR", &mut self.game.metrics_addr)?;
+        vars.set_list("GAME_TRUSTED_PROXIES", &mut self.game.server.trusted_proxies);
+        vars.set_some("CAPTURE_FILE", &mut self.game.capture.file)?;
+        if let Some(all) = (vars.0)("CAPTURE_ALL") {
+            self.game.capture.record_all = all == "1" || all == "true";
+        }
+        Ok(())
+    }
+
+    /// Checks the values the types could not, like the ranges and the
+    /// proxies.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.log.verbosity > 4 {
+            return Err(Error::invalid("log.verbosity", "must be between 0 and 4"));
+        }
+        self.database.validate()?;
+        self.bus.validate()?;
+        if self.auth.port == 0 {
+            return Err(Error::invalid("auth.port", "must not be 0"));
+        }
+        if self.auth.bus_port == 0 || self.auth.bus_port == self.auth.port {
+            return Err(Error::invalid("auth.bus_port", "must not be 0 or auth.port"));
+        }
+        if self.game.realm.trim().is_empty() {
+            return Err(Error::invalid("game.realm", "must not be empty"));
+        }
+        if self.game.auth_bus.trim().is_empty() {
+            return Err(Error::invalid("game.auth_bus", "must not be empty"));
+        }
+        if self.game.capacity == 0 {
+            return Err(Error::invalid("game.capacity", "must not be 0"));
+        }
+        self.auth.guest.validate()?;
+        self.game.tokens.validate()?;
+        self.auth.server.apply(&mut tq_server::Config::default())?;
+        self.game.server.apply(&mut tq_server::Config::default())?;
+        Ok(())
+    }
+
+    /// The database to connect to, `coemu.db` in the data directory unless
+    /// set.
+    pub fn database_url(&self) -> String {
+        match &self.database.url {
+            Some(url) => url.clone(),
+            None => format!("sqlite://{}/coemu.db?mode=rwc", self.data_dir.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// 0 is errors only, 4 is everything.
+    pub verbosity: u8,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { verbosity: 2 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// See [`Config::database_url`].
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_connections: 42,
+            min_connections: 4,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.max_connections == 0 {
+            return Err(Error::invalid("database.max_connections", "must not be 0"));
+        }
+        if self.min_connections > self.max_connections {
+            return Err(Error::invalid(
+                "database.min_connections",
+                "must not be more than database.max_connections",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The inter-server bus, see [`tq_bus`].
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BusConfig {
+    /// Shared by every server, the auth server could not talk to the game
+    /// servers unless set.
+    pub secret: Option<String>,
+}
+
+impl core::fmt::Debug for BusConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BusConfig")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl BusConfig {
+    /// The bus secret, an error if it is not set.
+    pub fn secret(&self) -> Result<tq_bus::Secret, Error> {
+        match &self.secret {
+            Some(secret) => Ok(tq_bus::Secret::new(secret)),
+            None => Err(Error::invalid("bus.secret", "must be set")),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        match &self.secret {
+            Some(secret) if secret.len() < tq_bus::Secret::MIN_LEN => Err(Error::invalid(
+                "bus.secret",
+                format!("must be at least {} bytes", tq_bus::Secret::MIN_LEN),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub port: u16,
+    /// Where the game servers send their heartbeats, see [`tq_bus`].
+    pub bus_port: u16,
+    /// Where the packet handlers WASM modules are.
+    pub modules_dir: PathBuf,
+    /// How often to check the modules for changes and reload them, in
+    /// seconds, never if 0.
+    pub reload_secs: u64,
+    pub guest: GuestConfig,
+    /// Serve the metrics on this address, off unless set.
+    pub metrics_addr: Option<SocketAddr>,
+    pub server: ServerConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            port: 9958,
+            bus_port: 9957,
+            modules_dir: PathBuf::from("./target/wasm32-unknown-unknown/wasm"),
+            reload_secs: 2,
+            guest: GuestConfig::default(),
+            metrics_addr: None,
+            server: ServerConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// The realm this server is, its address and port are in the database.
+    pub realm: String,
+    /// The bus of the account server, to send the heartbeats to.
+    pub auth_bus: String,
+    /// How many players the realm takes before it is full.
+    pub capacity: u32,
+    /// Serve the metrics on this address, off unless set.
+    pub metrics_addr: Option<SocketAddr>,
+    pub tokens: TokensConfig,
+    pub capture: CaptureConfig,
+    pub server: ServerConfig,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            realm: String::from("CoEmu"),
+            auth_bus: String::from("127.0.0.1:9957"),
+            capacity: 1000,
+            metrics_addr: None,
+            tokens: TokensConfig::default(),
+            capture: CaptureConfig::default(),
+            server: ServerConfig::default(),
+        }
+    }
+}
+
+/// The budget of the packet modules of the account server, for every packet.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuestConfig {
+    /// Roughly how many WASM instructions a module could run.
+    pub fuel: u64,
+    /// How large the memory of a module could grow, in MiB.
+    pub memory_mb: usize,
+    /// How long a module could take, waiting on the database included.
+    pub timeout_secs: u64,
+}
+
+impl Default for GuestConfig {
+    fn default() -> Self {
+        Self {
+            fuel: 50_000_000,
+            memory_mb: 32,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl GuestConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.fuel == 0 {
+            return Err(Error::invalid("auth.guest.fuel", "must not be 0"));
+        }
+        if self.memory_mb == 0 || self.memory_mb > 4096 {
+            return Err(Error::invalid("auth.guest.memory_mb", "must be between 1 and 4096"));
+        }
+        if self.timeout_secs == 0 {
+            return Err(Error::invalid("auth.guest.timeout_secs", "must not be 0"));
+        }
+        Ok(())
+    }
+}
+
+/// The login and character creation tokens of the game server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokensConfig {
+    /// How long the client has to present its login token.
+    pub login_ttl_secs: u64,
+    /// How long the client has to create its character.
+    pub creation_ttl_secs: u64,
+    /// Only accept the tokens from the address that logged in on the account
+    /// server, turn it off if the two servers could see different addresses.
+    pub bind_ip: bool,
+}
+
+impl Default for TokensConfig {
+    fn default() -> Self {
+        Self {
+            login_ttl_secs: 60,
+            creation_ttl_secs: 600,
+            bind_ip: true,
+        }
+    }
+}
+
+impl TokensConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.login_ttl_secs == 0 {
+            return Err(Error::invalid("game.tokens.login_ttl_secs", "must not be 0"));
+        }
+        if self.creation_ttl_secs == 0 {
+            return Err(Error::invalid("game.tokens.creation_ttl_secs", "must not be 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Packet capture, see `tq_server::CaptureConfig`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Capturing is off unless set.
+    pub file: Option<PathBuf>,
+    pub record_all: bool,
+}
+
+impl CaptureConfig {
+    pub fn to_server_config(&self) -> Option<tq_server::CaptureConfig> {
+        self.file.as_ref().map(|path| tq_server::CaptureConfig {
+            path: path.clone(),
+            record_all: self.record_all,
+        })
+    }
+}
+
+/// The connection settings of a server, every setting that is not set keeps
+/// the server's own default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The game client patch, like `5017`.
+    pub patch: Option<u16>,
+    /// Every game client patch served, instead of `patch`, the connections
+    /// get theirs picked when they connect.
+    pub patches: Vec<u16>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    /// The IP time-to-live of the accepted sockets.
+    pub ttl: Option<u32>,
+    /// The biggest frame a client could send, in bytes.
+    pub max_frame_size: Option<u16>,
+    /// The load balancers allowed to send the PROXY protocol header, as
+    /// CIDRs, empty if there is none.
+    pub trusted_proxies: Vec<String>,
+    pub violations: ViolationsConfig,
+}
+
+/// How many protocol violations a client gets away with.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViolationsConfig {
+    /// Disconnect the clients with more strikes than that, no limit if not
+    /// set.
+    pub max_strikes: Option<u32>,
+    /// Also ban their IP for that many seconds.
+    pub ban_secs: Option<u64>,
+    /// How many strikes every kind of violation is worth, like
+    /// `malformed_packet = 2`, one if not set.
+    pub strikes: HashMap<String, u32>,
+}
+
+impl ServerConfig {
+    /// Sets what is set here on the server configuration.
+    pub fn apply(&self, config: &mut tq_server::Config) -> Result<(), Error> {
+        if self.patch.is_some() && !self.patches.is_empty() {
+            return Err(Error::invalid("server.patches", "set either patch or patches"));
+        }
+        let patches: Vec<_> = self.patch.iter().chain(&self.patches).collect();
+        if !patches.is_empty() {
+            let mut protocols = patches
+                .into_iter()
+                .map(|patch| ProtocolVersion::from_str(&patch.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::invalid("server.patches", e.to_string()))?;
+            protocols.sort();
+            protocols.dedup();
+            config.protocols = protocols;
+        }
+        if let Some(max) = self.max_connections {
+            config.admission.max_connections = Some(max);
+        }
+        if let Some(max) = self.max_connections_per_ip {
+            config.admission.max_connections_per_ip = Some(max);
+        }
+        if let Some(ttl) = self.ttl {
+            config.stream.ttl = Some(ttl);
+        }
+        if let Some(size) = self.max_frame_size {
+            if (size as usize) <= tq_codec::frame::HEAD_LEN {
+                return Err(Error::invalid("server.max_frame_size", "too small for any packet"));
+            }
+            config.stream.max_frame_size = size;
+        }
+        if !self.trusted_proxies.is_empty() {
+            let proxies = self.trusted_proxies.join(",");
+            let proxy = proxies
+                .parse()
+                .map_err(|e: tq_server::proxy::ProxyError| Error::invalid("server.trusted_proxies", e.to_string()))?;
+            config.proxy = Some(proxy);
+        }
+        self.violations.apply(&mut config.violations)
+    }
+}
+
+impl ViolationsConfig {
+    pub fn apply(&self, config: &mut tq_server::violation::ViolationConfig) -> Result<(), Error> {
+        if let Some(max) = self.max_strikes {
+            config.max_strikes = Some(max);
+        }
+        if let Some(secs) = self.ban_secs {
+            config.action = ViolationAction::Ban(Duration::from_secs(secs));
+        }
+        for (kind, strikes) in &self.strikes {
+            let kind = ViolationKind::from_str(kind)
+                .map_err(|e| Error::invalid("server.violations.strikes", e.to_string()))?;
+            config.strikes.insert(kind, *strikes);
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the environment variables.
+struct Vars<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn get<T>(&self, var: &'static str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: core::fmt::Display,
+    {
+        match (self.0)(var) {
+            Some(value) => value.trim().parse().map(Some).map_err(|e: T::Err| Error::Env {
+                var,
+                reason: e.to_string(),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn set<T>(&self, var: &'static str, setting: &mut T) -> Result<(), Error>
+    where
+        T: FromStr,
+        T::Err: core::fmt::Display,
+    {
+        if let Some(value) = self.get(var)? {
+            *setting = value;
+        }
+        Ok(())
+    }
+
+    fn set_some<T>(&self, var: &'static str, setting: &mut Option<T>) -> Result<(), Error>
+    where
+        T: FromStr,
+        T::Err: core::fmt::Display,
+    {
+        if let Some(value) = self.get(var)? {
+            *setting = Some(value);
+        }
+        Ok(())
+    }
+
+    fn set_all<T>(&self, var: &'static str, setting: &mut Vec<T>) -> Result<(), Error>
+    where
+        T: FromStr,
+        T::Err: core::fmt::Display,
+    {
+        let mut values = Vec::new();
+        self.set_list(var, &mut values);
+        if !values.is_empty() {
+            *setting = values
+                .iter()
+                .map(|v| v.parse())
+                .collect::<Result<_, T::Err>>()
+                .map_err(|e| Error::Env {
+                    var,
+                    reason: e.to_string(),
+                })?;
+        }
+        Ok(())
+    }
+
+    fn set_list(&self, var: &'static str, setting: &mut Vec<String>) {
+        if let Some(value) = (self.0)(var) {
+            *setting = value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_override() {
+        let mut config: Config = toml::from_str(
+            r#"
+            data_dir = "/srv/coemu"
+
+            [database]
+            max_connections = 8
+
+            [game]
+            realm = "Test"
+
+            [game.server]
+            patch = 5018
+            trusted_proxies = ["10.0.0.0/8"]
+
+            [game.server.violations]
+            max_strikes = 3
+            ban_secs = 60
+            strikes = { malformed_packet = 2 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.database_url(), "sqlite:///srv/coemu/coemu.db?mode=rwc");
+        assert_eq!(config.database.min_connections, 4);
+        assert_eq!(config.auth.port, 9958);
+
+        let vars: HashMap<_, _> = [
+            ("AUTH_PORT", "9959"),
+            ("GAME_REALM", "Env"),
+            ("GAME_CAPACITY", "50"),
+            ("CAPTURE_FILE", "game.tqcap"),
+        ]
+        .into_iter()
+        .collect();
+        config.apply_vars(|var| vars.get(var).map(|v| v.to_string())).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.auth.port, 9959);
+        assert_eq!(config.game.realm, "Env");
+        assert_eq!(config.game.capacity, 50);
+        assert!(config.game.capture.to_server_config().is_some());
+
+        let mut server = tq_server::Config::default();
+        config.game.server.apply(&mut server).unwrap();
+        assert_eq!(server.protocols, [ProtocolVersion::V5018]);
+        assert!(server.proxy.is_some());
+        assert_eq!(server.stream, tq_server::StreamConfig::default());
+        assert_eq!(server.violations.max_strikes, Some(3));
+        assert_eq!(server.violations.action, ViolationAction::Ban(Duration::from_secs(60)));
+        assert_eq!(server.violations.strikes_of(ViolationKind::MalformedPacket), 2);
+        assert_eq!(server.violations.strikes_of(ViolationKind::UnknownPacket), 1);
+
+        config.game.server.patch = None;
+        let vars = |var: &str| (var == "GAME_PATCHES").then(|| String::from("5018, 5017"));
+        config.apply_vars(vars).unwrap();
+        config.game.server.apply(&mut server).unwrap();
+        assert_eq!(server.protocols, [ProtocolVersion::V5017, ProtocolVersion::V5018]);
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        assert!(toml::from_str::<Config>("[auth]\nprot = 9958").is_err());
+        let config: Config = toml::from_str("[database]\nmin_connections = 50").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[game.server]\ntrusted_proxies = [\"proxy\"]").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[game]\nrealm = \"\"").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[game.tokens]\nlogin_ttl_secs = 0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[game]\ncapacity = 0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[auth.guest]\nmemory_mb = 0").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[auth]\nbus_port = 9958").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[auth.server.violations.strikes]\nspeeding = 1").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[game.server]\npatch = 5017\npatches = [5018]").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config = toml::from_str("[bus]\nsecret = \"short\"").unwrap();
+        assert!(config.validate().is_err());
+        assert!(Config::default().bus.secret().is_err());
+        let mut config = Config::default();
+        assert!(config.apply_vars(|_| Some(String::from("nope"))).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Blowfish in 64-bit cipher feedback mode (CFB64), which replaced the
+//! [`TQCipher`](crate::TQCipher) in the game server in patch 5018.
+//!
+//! The game server starts with a static key, and switches to the key agreed
+//! on in the Diffie-Hellman key exchange (see `dh`) before the first game
+//! packet. Each direction has its own IV and position in the keystream, just
+//! like OpenSSL's `BF_cfb64_encrypt`, which is what the game client uses.
+
+use blowfish::Blowfish;
+use parking_lot::RwLock;
+
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// The key the game server starts with, before the key exchange.
+pub const DEFAULT_KEY: &[u8] = b"DR654dt34trg4UI6";
+
+/// Blowfish only uses the first 72 bytes of the key.
+const MAX_KEY_SIZE: usize = 72;
+
+const BLOCK_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Default)]
+struct Feedback {
+    iv: [u8; BLOCK_SIZE],
+    /// How much of the current keystream block we used.
+    num: usize,
+}
+
+impl Feedback {
+    fn new(iv: [u8; BLOCK_SIZE]) -> Self {
+        Self { iv, num: 0 }
+    }
+}
+
+struct Inner {
+    blowfish: Blowfish,
+    encrypt: Feedback,
+    decrypt: Feedback,
+}
+
+/// Blowfish CFB64 cipher, the same for both the client and the server, as long
+/// as their IVs are swapped.
+#[derive(Clone)]
+pub struct BlowfishCipher {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl BlowfishCipher {
+    /// Creates a new cipher with `key` and zero IVs.
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                blowfish: expand_key(key),
+                encrypt: Feedback::default(),
+                decrypt: Feedback::default(),
+            })),
+        }
+    }
+
+    /// Switches to a new key, the IVs get reset to zero.
+    pub fn set_key(&self, key: &[u8]) {
+        let mut inner = self.inner.write();
+        inner.blowfish = expand_key(key);
+        inner.encrypt = Feedback::default();
+        inner.decrypt = Feedback::default();
+    }
+
+    /// Sets the IVs of both directions, starting a new keystream.
+    pub fn set_ivs(&self, encrypt_iv: [u8; BLOCK_SIZE], decrypt_iv: [u8; BLOCK_SIZE]) {
+        let mut inner = self.inner.write();
+        inner.encrypt = Feedback::new(encrypt_iv);
+        inner.decrypt = Feedback::new(decrypt_iv);
+    }
+}
+
+impl Default for BlowfishCipher {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEY)
+    }
+}
+
+impl super::Cipher for BlowfishCipher {
+    /// The keys come from the key exchange instead, see
+    /// [`BlowfishCipher::set_key`].
+    fn generate_keys(&self, _seed: u64) {}
+
+    fn decrypt(&self, data: &mut [u8]) {
+        let mut inner = self.inner.write();
+        let Inner { blowfish, decrypt, .. } = &mut *inner;
+        for byte in data.iter_mut() {
+            if decrypt.num == 0 {
+                decrypt.iv = encrypt_block(blowfish, decrypt.iv);
+            }
+            let c = *byte;
+            *byte ^= decrypt.iv[decrypt.num];
+            // The feedback is the ciphertext.
+            decrypt.iv[decrypt.num] = c;
+            decrypt.num = (decrypt.num + 1) % BLOCK_SIZE;
+        }
+    }
+
+    fn encrypt(&self, data: &mut [u8]) {
+        let mut inner = self.inner.write();
+        let Inner { blowfish, encrypt, .. } = &mut *inner;
+        for byte in data.iter_mut() {
+            if encrypt.num == 0 {
+                encrypt.iv = encrypt_block(blowfish, encrypt.iv);
+            }
+            *byte ^= encrypt.iv[encrypt.num];
+            encrypt.iv[encrypt.num] = *byte;
+            encrypt.num = (encrypt.num + 1) % BLOCK_SIZE;
+        }
+    }
+}
+
+fn expand_key(key: &[u8]) -> Blowfish {
+    let key = &key[..key.len().min(MAX_KEY_SIZE)];
+    let mut blowfish = Blowfish::bc_init_state();
+    if !key.is_empty() {
+        blowfish.bc_expand_key(key);
+    }
+    blowfish
+}
+
+fn encrypt_block(blowfish: &Blowfish, block: [u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+    let l = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+    let r = u32::from_be_bytes([block[4], block[5], block[6], block[7]]);
+    let [l, r] = blowfish.bc_encrypt([l, r]);
+    let mut out = [0u8; BLOCK_SIZE];
+    out[..4].copy_from_slice(&l.to_be_bytes());
+    out[4..].copy_from_slice(&r.to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cipher;
+
+    const KEY: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xF0, 0xE1, 0xD2, 0xC3, 0xB4, 0xA5, 0x96, 0x87,
+    ];
+    const IV: [u8; 8] = [0xFE, 0xDC, 0xBA, 0x98, 0x76, 0x54, 0x32, 0x10];
+    const PLAIN: &[u8; 29] = b"7654321 Now is the time for \0";
+    // From OpenSSL's `bftest.c`.
+    const CFB64: [u8; 29] = [
+        0xE7, 0x32, 0x14, 0xA2, 0x82, 0x21, 0x39, 0xCA, 0xF2, 0x6E, 0xCF, 0x6D, 0x2E, 0xB9, 0xE7, 0x6E, 0x3D, 0xA3,
+        0xDE, 0x04, 0xD1, 0x51, 0x72, 0x00, 0x51, 0x9D, 0x57, 0xA6, 0xC3,
+    ];
+
+    #[test]
+    fn ecb_known_answer() {
+        // The first vector from Eric Young's test set.
+        let blowfish = expand_key(&[0u8; 8]);
+        let block = encrypt_block(&blowfish, [0u8; 8]);
+        assert_eq!(block, [0x4E, 0xF9, 0x97, 0x45, 0x61, 0x98, 0xDD, 0x78]);
+    }
+
+    #[test]
+    fn cfb64_known_answer() {
+        let cipher = BlowfishCipher::new(&KEY);
+        cipher.set_ivs(IV, IV);
+        let mut data = *PLAIN;
+        cipher.encrypt(&mut data);
+        assert_eq!(data, CFB64);
+        cipher.decrypt(&mut data);
+        assert_eq!(&data, PLAIN);
+    }
+
+    #[test]
+    fn cfb64_keeps_its_place_between_calls() {
+        let cipher = BlowfishCipher::new(&KEY);
+        cipher.set_ivs(IV, IV);
+        let mut data = *PLAIN;
+        // Not on a block boundary on purpose.
+        let (a, b) = data.split_at_mut(13);
+        cipher.encrypt(a);
+        let (b, c) = b.split_at_mut(3);
+        cipher.encrypt(b);
+        cipher.encrypt(c);
+        assert_eq!(data, CFB64);
+    }
+}
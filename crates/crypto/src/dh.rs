@@ -0,0 +1,126 @@
+//! Diffie-Hellman key exchange, used since patch 5018 to agree on the
+//! [`BlowfishCipher`](crate::BlowfishCipher) key before the first game packet.
+//!
+//! Everything goes over the wire as upper case hex strings, and so does the
+//! shared secret when it is used as the Blowfish key.
+
+use num_bigint_dig::BigUint;
+
+/// The prime the game server sends in its half of the key exchange.
+pub const DEFAULT_P: &str = "E7A69EBDF105F2A6BBDEAD7E798F76A209AD73FB466431E2E7352ED262F8C558F10BEFEA977DE9E21DCEE9B04D245F300ECCBBA03E72630556D011023F9E857F";
+/// The generator the game server sends in its half of the key exchange.
+pub const DEFAULT_G: &str = "05";
+
+/// Size of the random private keys, in bytes.
+const PRIVATE_KEY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhError {
+    /// Not a hex number.
+    InvalidNumber,
+    /// The other side's public key is out of range.
+    InvalidPublicKey,
+}
+
+impl core::fmt::Display for DhError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidNumber => write!(f, "Invalid hex number"),
+            Self::InvalidPublicKey => write!(f, "Invalid public key"),
+        }
+    }
+}
+
+impl std::error::Error for DhError {}
+
+/// One side of the key exchange.
+#[derive(Clone)]
+pub struct DiffieHellman {
+    p: BigUint,
+    g: BigUint,
+    private_key: BigUint,
+    public_key: BigUint,
+}
+
+impl DiffieHellman {
+    /// Creates a new side with a random private key.
+    pub fn new(p: &str, g: &str) -> Result<Self, DhError> {
+        let private_key = rand::random::<[u8; PRIVATE_KEY_SIZE]>();
+        Self::with_private_key(p, g, &private_key)
+    }
+
+    /// Creates a new side with a known private key (big endian), mostly
+    /// useful for tests.
+    pub fn with_private_key(p: &str, g: &str, private_key: &[u8]) -> Result<Self, DhError> {
+        let p = parse_hex(p)?;
+        let g = parse_hex(g)?;
+        let private_key = BigUint::from_bytes_be(private_key);
+        let public_key = g.modpow(&private_key, &p);
+        Ok(Self {
+            p,
+            g,
+            private_key,
+            public_key,
+        })
+    }
+
+    /// The prime, as sent over the wire.
+    pub fn p(&self) -> String {
+        to_hex(&self.p)
+    }
+
+    /// The generator, as sent over the wire.
+    pub fn g(&self) -> String {
+        to_hex(&self.g)
+    }
+
+    /// Our public key, as sent over the wire.
+    pub fn public_key(&self) -> String {
+        to_hex(&self.public_key)
+    }
+
+    /// Computes the shared secret from the other side's public key.
+    pub fn compute_key(&self, public_key: &str) -> Result<String, DhError> {
+        let public_key = parse_hex(public_key)?;
+        if public_key == BigUint::default() || public_key >= self.p {
+            return Err(DhError::InvalidPublicKey);
+        }
+        let secret = public_key.modpow(&self.private_key, &self.p);
+        Ok(to_hex(&secret))
+    }
+}
+
+fn parse_hex(s: &str) -> Result<BigUint, DhError> {
+    BigUint::parse_bytes(s.as_bytes(), 16).ok_or(DhError::InvalidNumber)
+}
+
+fn to_hex(n: &BigUint) -> String {
+    n.to_str_radix(16).to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_answer() {
+        // p = 23, g = 5, a = 6, b = 15.
+        let alice = DiffieHellman::with_private_key("17", "05", &[6]).unwrap();
+        let bob = DiffieHellman::with_private_key("17", "05", &[15]).unwrap();
+        assert_eq!(alice.public_key(), "8");
+        assert_eq!(bob.public_key(), "13");
+        assert_eq!(alice.compute_key(&bob.public_key()).unwrap(), "2");
+        assert_eq!(bob.compute_key(&alice.public_key()).unwrap(), "2");
+    }
+
+    #[test]
+    fn both_sides_agree() {
+        let server = DiffieHellman::new(DEFAULT_P, DEFAULT_G).unwrap();
+        let client = DiffieHellman::new(&server.p(), &server.g()).unwrap();
+        let a = server.compute_key(&client.public_key()).unwrap();
+        let b = client.compute_key(&server.public_key()).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(server.compute_key("0"), Err(DhError::InvalidPublicKey));
+        assert_eq!(server.compute_key(&server.p()), Err(DhError::InvalidPublicKey));
+    }
+}
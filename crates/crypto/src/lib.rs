@@ -20,6 +20,12 @@ pub use nop::NopCipher;
 mod cq_cipher;
 pub use cq_cipher::CQCipher;
 
+pub mod blowfish;
+pub use blowfish::BlowfishCipher;
+
+#[cfg(feature = "dh")]
+pub mod dh;
+
 /// Defines generalized methods for ciphers used by
 /// `Server` for encrypting and decrypting
 /// data to and from the game client.
@@ -187,4 +187,26 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn cq_cipher_is_the_client_side() {
+        let server = TQCipher::new();
+        let client = crate::CQCipher::new();
+        let msg = *b"Hello from the other side";
+        for seed in [None, Some(0x1234_5678_9ABC)] {
+            if let Some(seed) = seed {
+                server.generate_keys(seed);
+                client.generate_keys(seed);
+            }
+            let mut buffer = msg;
+            client.encrypt(&mut buffer);
+            assert_ne!(buffer, msg);
+            server.decrypt(&mut buffer);
+            assert_eq!(buffer, msg);
+
+            server.encrypt(&mut buffer);
+            client.decrypt(&mut buffer);
+            assert_eq!(buffer, msg);
+        }
+    }
 }
@@ -14,10 +14,13 @@ pub struct Account {
 #[cfg(feature = "sqlx")]
 impl Account {
     pub async fn auth(pool: &sqlx::SqlitePool, username: &str, password: &str) -> Result<Account, crate::Error> {
+        let timer = crate::metrics::time("account_auth");
         let maybe_account = sqlx::query_as::<_, Self>("SELECT * FROM accounts WHERE username = ?;")
             .bind(username)
             .fetch_optional(pool)
             .await?;
+        // Checking the password is not part of the query.
+        drop(timer);
         match maybe_account {
             Some(account) => {
                 let matched = bcrypt::verify(password, &account.password)?;
@@ -40,6 +43,7 @@ impl Account {
         offset: Option<i64>,
     ) -> Result<Vec<Self>, crate::Error> {
         use futures::TryFutureExt;
+        let _timer = crate::metrics::time("account_all");
         sqlx::query_as::<_, Self>("SELECT * FROM accounts LIMIT ? OFFSET ?;")
             .bind(limit.unwrap_or(100))
             .bind(offset.unwrap_or(0))
@@ -53,6 +57,7 @@ impl Account {
     /// Creates a new account in the database.
     pub async fn create(mut self, pool: &sqlx::SqlitePool) -> Result<Self, crate::Error> {
         let password = bcrypt::hash(&self.password, bcrypt::DEFAULT_COST)?;
+        let _timer = crate::metrics::time("account_create");
         let res = sqlx::query("INSERT INTO accounts (username, password, name, email) VALUES (?, ?, ?, ?);")
             .bind(&self.username)
             .bind(&password)
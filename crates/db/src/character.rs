@@ -45,6 +45,7 @@ pub struct Location {
 #[cfg(feature = "sqlx")]
 impl Character {
     pub async fn from_account(pool: &sqlx::SqlitePool, id: u32) -> Result<Option<Self>, crate::Error> {
+        let _timer = crate::metrics::time("character_from_account");
         let maybe_character = sqlx::query_as::<_, Self>("SELECT * FROM characters WHERE account_id = ?;")
             .bind(id)
             .fetch_optional(pool)
@@ -53,6 +54,7 @@ impl Character {
     }
 
     pub async fn name_taken(pool: &sqlx::SqlitePool, name: &str) -> Result<bool, crate::Error> {
+        let _timer = crate::metrics::time("character_name_taken");
         let result = sqlx::query_as::<_, (i32,)>("SELECT EXISTS (SELECT 1 FROM characters WHERE name = ? LIMIT 1);")
             .bind(name)
             .fetch_optional(pool)
@@ -66,6 +68,7 @@ impl Character {
     }
 
     pub async fn by_id(pool: &sqlx::SqlitePool, id: i32) -> Result<Self, crate::Error> {
+        let _timer = crate::metrics::time("character_by_id");
         let c = sqlx::query_as::<_, Self>("SELECT * FROM characters WHERE character_id = ?;")
             .bind(id)
             .fetch_one(pool)
@@ -74,6 +77,7 @@ impl Character {
     }
 
     pub async fn save(self, pool: &sqlx::SqlitePool) -> Result<i32, crate::Error> {
+        let _timer = crate::metrics::time("character_save");
         let (id,) = sqlx::query_as::<_, (i32,)>(
             "
             INSERT INTO characters
@@ -116,6 +120,7 @@ impl Character {
     }
 
     pub async fn update(self, pool: &sqlx::SqlitePool) -> Result<(), crate::Error> {
+        let _timer = crate::metrics::time("character_update");
         sqlx::query(
             "
             UPDATE characters
@@ -11,4 +11,6 @@ pub enum Error {
     InvalidPassword,
     #[error("Creating account failed")]
     CreateAccountFailed,
+    #[error("Invalid realm status")]
+    InvalidRealmStatus,
 }
@@ -5,5 +5,9 @@ pub mod map;
 pub mod npc;
 pub mod portal;
 pub mod realm;
+pub mod violation;
+
+#[cfg(feature = "sqlx")]
+mod metrics;
 
 pub use error::Error;
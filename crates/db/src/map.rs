@@ -19,6 +19,7 @@ impl Map {
     pub async fn load_all(pool: &sqlx::SqlitePool) -> Result<Vec<Self>, crate::Error> {
         use tokio_stream::StreamExt;
 
+        let _timer = crate::metrics::time("map_load_all");
         let mut maps = Vec::new();
         let mut s = sqlx::query_as::<_, Self>("SELECT * FROM maps;").fetch(pool);
         while let Some(maybe_map) = s.next().await {
@@ -36,6 +37,7 @@ impl Map {
     }
 
     pub async fn load(pool: &sqlx::SqlitePool, id: i32) -> Result<Option<Self>, crate::Error> {
+        let _timer = crate::metrics::time("map_load");
         let maybe_map = sqlx::query_as::<_, Self>("SELECT * FROM maps WHERE id = ?;")
             .bind(id)
             .fetch_optional(pool)
@@ -0,0 +1,30 @@
+//! Query latency, see [`tq_metrics`].
+
+use std::time::Instant;
+
+/// Observes the time since it got created into the query latency histogram
+/// once dropped.
+pub(crate) struct QueryTimer {
+    query: &'static str,
+    started: Instant,
+}
+
+/// Starts timing `query`, keep the timer alive until the query is done.
+pub(crate) fn time(query: &'static str) -> QueryTimer {
+    QueryTimer {
+        query,
+        started: Instant::now(),
+    }
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        tq_metrics::histogram(
+            "coemu_db_query_seconds",
+            "Time spent in database queries.",
+            tq_metrics::LATENCY_BUCKETS,
+            &[("query", self.query)],
+        )
+        .observe_duration(self.started.elapsed());
+    }
+}
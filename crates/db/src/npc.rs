@@ -22,6 +22,7 @@ impl Npc {
     pub async fn by_map(pool: &sqlx::SqlitePool, id: i32) -> Result<Vec<Self>, crate::Error> {
         use tokio_stream::StreamExt;
 
+        let _timer = crate::metrics::time("npc_by_map");
         let mut npcs = Vec::new();
         let mut s = sqlx::query_as::<_, Self>("SELECT * FROM npcs WHERE map_id = ?;")
             .bind(id)
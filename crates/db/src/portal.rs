@@ -16,6 +16,7 @@ impl Portal {
     pub async fn by_map(pool: &sqlx::SqlitePool, from: i32) -> Result<Vec<Self>, crate::Error> {
         use tokio_stream::StreamExt;
 
+        let _timer = crate::metrics::time("portal_by_map");
         let mut portals = Vec::new();
         let mut s = sqlx::query_as::<_, Self>("SELECT * FROM portals WHERE from_map_id = ?;")
             .bind(from)
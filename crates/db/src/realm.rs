@@ -1,6 +1,6 @@
 /// Realms are configured instances of the game server. This struct defines
 /// routing details for authenticated clients to be redirected to. Redirection
-/// involves access token leasing, provided by the game server via RPC.
+/// involves access token leasing, provided by the game server over the bus.
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
 pub struct Realm {
@@ -8,11 +8,14 @@ pub struct Realm {
     pub name: String,
     pub game_ip_address: String,
     pub game_port: i16,
+    /// Where the game server answers the other servers, see `tq-bus`.
+    pub bus_port: i16,
 }
 
 #[cfg(feature = "sqlx")]
 impl Realm {
     pub async fn by_name(pool: &sqlx::SqlitePool, name: &str) -> Result<Option<Self>, crate::Error> {
+        let _timer = crate::metrics::time("realm_by_name");
         let realm = sqlx::query_as::<_, Self>("SELECT * FROM realms WHERE name = ?;")
             .bind(name)
             .fetch_optional(pool)
@@ -20,3 +23,59 @@ impl Realm {
         Ok(realm)
     }
 }
+
+/// What the account server knows about a realm, from the heartbeats of its
+/// game server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum RealmStatus {
+    /// No heartbeat lately, or ever.
+    #[default]
+    Offline = 0,
+    Online = 1,
+    /// Online, but there is no room for more players.
+    Full = 2,
+    /// Down for maintenance, come back later.
+    Maintenance = 3,
+    /// Closed to new logins.
+    Locked = 4,
+}
+
+impl RealmStatus {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Offline),
+            1 => Some(Self::Online),
+            2 => Some(Self::Full),
+            3 => Some(Self::Maintenance),
+            4 => Some(Self::Locked),
+            _ => None,
+        }
+    }
+
+    /// A short name for the status, like `maintenance`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Offline => "offline",
+            Self::Online => "online",
+            Self::Full => "full",
+            Self::Maintenance => "maintenance",
+            Self::Locked => "locked",
+        }
+    }
+}
+
+impl core::str::FromStr for RealmStatus {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "offline" => Ok(Self::Offline),
+            "online" => Ok(Self::Online),
+            "full" => Ok(Self::Full),
+            "maintenance" => Ok(Self::Maintenance),
+            "locked" => Ok(Self::Locked),
+            _ => Err(crate::Error::InvalidRealmStatus),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+/// A protocol violation committed by a client, recorded by the servers for
+/// later review.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Violation {
+    pub violation_id: i64,
+    pub ip_address: String,
+    /// The account of the client, if it logged in already.
+    pub account_id: Option<i32>,
+    /// The kind of the violation, like `malformed_packet`.
+    pub kind: String,
+    pub packet_id: i32,
+    pub details: String,
+    /// The strikes of the connection so far, this one included.
+    pub strikes: i32,
+    /// When it got recorded, in seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+#[cfg(feature = "sqlx")]
+impl Violation {
+    /// Records the violation, returns its id.
+    pub async fn save(self, pool: &sqlx::SqlitePool) -> Result<i64, crate::Error> {
+        let _timer = crate::metrics::time("violation_save");
+        let (id,) = sqlx::query_as::<_, (i64,)>(
+            "
+            INSERT INTO violations
+                (ip_address, account_id, kind, packet_id, details, strikes)
+            VALUES
+                (?, ?, ?, ?, ?, ?)
+            RETURNING violation_id;
+            ",
+        )
+        .bind(self.ip_address)
+        .bind(self.account_id)
+        .bind(self.kind)
+        .bind(self.packet_id)
+        .bind(self.details)
+        .bind(self.strikes)
+        .fetch_one(pool)
+        .await?;
+        Ok(id)
+    }
+
+    /// The latest violations from `ip_address`, newest first.
+    pub async fn by_ip(pool: &sqlx::SqlitePool, ip_address: &str, limit: i64) -> Result<Vec<Self>, crate::Error> {
+        let _timer = crate::metrics::time("violation_by_ip");
+        let violations = sqlx::query_as::<_, Self>(
+            "SELECT * FROM violations WHERE ip_address = ? ORDER BY violation_id DESC LIMIT ?;",
+        )
+        .bind(ip_address)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+        Ok(violations)
+    }
+}
@@ -0,0 +1,112 @@
+//! A tiny HTTP endpoint serving a [`Registry`] for Prometheus to scrape.
+//!
+//! It only knows `GET /metrics`, anything else is a `404`, and every
+//! connection is closed after a single response. It is meant to listen on a
+//! local address, not to face the internet.
+
+use crate::Registry;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// The biggest request we read, anything bigger is not a scrape.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// How long a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Binds `addr` and serves `registry` in the background, returning the bound
+/// address.
+pub async fn spawn(addr: impl ToSocketAddrs, registry: &'static Registry) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    tracing::info!(%addr, "Serving metrics on http://{addr}/metrics");
+    tokio::spawn(serve(listener, registry));
+    Ok(addr)
+}
+
+/// Serves `registry` on every connection accepted by `listener`, forever.
+pub async fn serve(listener: TcpListener, registry: &'static Registry) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(error = ?e, "Error while accepting a metrics connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, registry).await {
+                tracing::debug!(error = ?e, "Error while serving metrics");
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))??;
+    let mut parts = request.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = registry.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: \
+                 close\r\n\r\n{body}",
+                body.len()
+            )
+        },
+        _ => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads up to the end of the request head, returning the request line.
+async fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut buf = Vec::with_capacity(512);
+    loop {
+        if buf.len() >= MAX_REQUEST_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Request too big"));
+        }
+        let n = stream.read_buf(&mut buf).await?;
+        if n == 0 || buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+    let line = buf.split(|b| *b == b'\n').next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(line).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn scrape() {
+        let registry = Box::leak(Box::new(Registry::new()));
+        registry.counter("scrapes_total", "Scrapes.", &[]).inc();
+        let addr = spawn("127.0.0.1:0", registry).await.unwrap();
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("# TYPE scrapes_total counter\nscrapes_total 1\n"));
+
+        let response = get(addr, "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,424 @@
+//! This crate contains the metrics of the servers, counters, gauges and
+//! histograms kept in a [`Registry`] and rendered in the Prometheus text
+//! format, see the [`http`] module for the endpoint serving them.
+//!
+//! Most of the time the metrics live in the global [`registry`], using the
+//! helpers at the root of this crate:
+//!
+//! ```
+//! let sent = tq_metrics::counter("coemu_packets_sent_total", "Packets sent.", &[("packet_id", "1004")]);
+//! sent.inc();
+//! assert!(tq_metrics::registry().render().contains("coemu_packets_sent_total{packet_id=\"1004\"} 1"));
+//! ```
+//!
+//! Getting a metric with the same name and labels twice returns the same
+//! metric, so it is fine to look them up where they are used.
+
+use core::fmt::Write;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+pub mod http;
+
+/// Buckets for latencies, in seconds, from half a millisecond to 2.5 seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Buckets for sizes and counts, powers of two up to 1024.
+pub const SIZE_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0];
+
+/// The labels of a single metric, in the order they were given.
+type Labels = Vec<(&'static str, String)>;
+
+/// Computes the values of a gauge family when it gets rendered.
+type GaugeFn = Box<dyn Fn() -> Vec<(Labels, f64)> + Send + Sync>;
+
+/// A value that only goes up.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn add(&self, n: i64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct HistogramInner {
+    /// Upper bounds of the buckets, the `+Inf` one is implied.
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative, the last one is `+Inf`.
+    buckets: Vec<AtomicU64>,
+    /// Sum of all the observations, as `f64` bits.
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+/// Observations counted in buckets, like latencies.
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<HistogramInner>);
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self(Arc::new(HistogramInner {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+            count: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let inner = &self.0;
+        let i = inner
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(inner.bounds.len());
+        inner.buckets[i].fetch_add(1, Ordering::Relaxed);
+        inner.count.fetch_add(1, Ordering::Relaxed);
+        let mut sum = inner.sum.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(sum) + value).to_bits();
+            match inner
+                .sum
+                .compare_exchange_weak(sum, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => sum = current,
+            }
+        }
+    }
+
+    /// Observes `duration` in seconds.
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.0.sum.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+struct Family {
+    help: &'static str,
+    kind: Kind,
+    metrics: BTreeMap<Labels, Metric>,
+    /// Computed when rendered, for gauges only.
+    collect: Option<GaugeFn>,
+}
+
+/// Holds every metric by name and labels.
+#[derive(Default)]
+pub struct Registry {
+    families: RwLock<BTreeMap<&'static str, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets or creates the counter `name` with these `labels`.
+    pub fn counter(&self, name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Counter {
+        match self.get_or_insert(
+            name,
+            help,
+            Kind::Counter,
+            labels,
+            || Metric::Counter(Counter::default()),
+        ) {
+            Some(Metric::Counter(c)) => c,
+            _ => Counter::default(),
+        }
+    }
+
+    /// Gets or creates the gauge `name` with these `labels`.
+    pub fn gauge(&self, name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Gauge {
+        match self.get_or_insert(name, help, Kind::Gauge, labels, || Metric::Gauge(Gauge::default())) {
+            Some(Metric::Gauge(g)) => g,
+            _ => Gauge::default(),
+        }
+    }
+
+    /// Gets or creates the histogram `name` with these `labels`, `bounds` are
+    /// only used the first time.
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        bounds: &'static [f64],
+        labels: &[(&'static str, &str)],
+    ) -> Histogram {
+        match self.get_or_insert(name, help, Kind::Histogram, labels, || {
+            Metric::Histogram(Histogram::new(bounds))
+        }) {
+            Some(Metric::Histogram(h)) => h,
+            _ => Histogram::new(bounds),
+        }
+    }
+
+    /// Registers the gauge family `name`, its values get computed by `f`
+    /// every time the registry is rendered, good for values that are already
+    /// kept somewhere else, like the number of loaded maps.
+    ///
+    /// Registering the same name again replaces `f`.
+    pub fn gauge_fn<F>(&self, name: &'static str, help: &'static str, f: F)
+    where
+        F: Fn() -> Vec<(Vec<(&'static str, String)>, f64)> + Send + Sync + 'static,
+    {
+        let mut families = self.families.write();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind: Kind::Gauge,
+            metrics: BTreeMap::new(),
+            collect: None,
+        });
+        if family.kind != Kind::Gauge {
+            tracing::error!(%name, kind = family.kind.as_str(), "Metric already registered with another kind");
+            return;
+        }
+        family.collect = Some(Box::new(f));
+    }
+
+    fn get_or_insert(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: Kind,
+        labels: &[(&'static str, &str)],
+        new: impl FnOnce() -> Metric,
+    ) -> Option<Metric> {
+        let labels: Labels = labels.iter().map(|(k, v)| (*k, (*v).to_owned())).collect();
+        if let Some(family) = self.families.read().get(name) {
+            if family.kind != kind {
+                tracing::error!(%name, kind = family.kind.as_str(), "Metric already registered with another kind");
+                return None;
+            }
+            if let Some(metric) = family.metrics.get(&labels) {
+                return Some(metric.clone());
+            }
+        }
+        let mut families = self.families.write();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind,
+            metrics: BTreeMap::new(),
+            collect: None,
+        });
+        if family.kind != kind {
+            tracing::error!(%name, kind = family.kind.as_str(), "Metric already registered with another kind");
+            return None;
+        }
+        Some(family.metrics.entry(labels).or_insert_with(new).clone())
+    }
+
+    /// Renders every metric in the Prometheus text format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let families = self.families.read();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {name} {}", family.help);
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind.as_str());
+            for (labels, metric) in &family.metrics {
+                match metric {
+                    Metric::Counter(c) => write_sample(&mut out, name, "", labels, None, c.get() as f64),
+                    Metric::Gauge(g) => write_sample(&mut out, name, "", labels, None, g.get() as f64),
+                    Metric::Histogram(h) => write_histogram(&mut out, name, labels, h),
+                }
+            }
+            if let Some(collect) = &family.collect {
+                for (labels, value) in collect() {
+                    write_sample(&mut out, name, "", &labels, None, value);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &Labels, h: &Histogram) {
+    let inner = &h.0;
+    let mut cumulative = 0;
+    for (i, bucket) in inner.buckets.iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let le = match inner.bounds.get(i) {
+            Some(bound) => bound.to_string(),
+            None => String::from("+Inf"),
+        };
+        write_sample(out, name, "_bucket", labels, Some(&le), cumulative as f64);
+    }
+    write_sample(out, name, "_sum", labels, None, h.sum());
+    write_sample(out, name, "_count", labels, None, h.count() as f64);
+}
+
+fn write_sample(out: &mut String, name: &str, suffix: &str, labels: &Labels, le: Option<&str>, value: f64) {
+    out.push_str(name);
+    out.push_str(suffix);
+    let le = le.map(|le| ("le", le));
+    let mut labels = labels.iter().map(|(k, v)| (*k, v.as_str())).chain(le).peekable();
+    if labels.peek().is_some() {
+        out.push('{');
+        for (i, (key, value)) in labels.enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{key}=\"");
+            for c in value.chars() {
+                match c {
+                    '\\' => out.push_str("\\\\"),
+                    '"' => out.push_str("\\\""),
+                    '\n' => out.push_str("\\n"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+/// The registry every server reports to.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// Shorthand for [`Registry::counter`] on the global [`registry`].
+pub fn counter(name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Counter {
+    registry().counter(name, help, labels)
+}
+
+/// Shorthand for [`Registry::gauge`] on the global [`registry`].
+pub fn gauge(name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Gauge {
+    registry().gauge(name, help, labels)
+}
+
+/// Shorthand for [`Registry::histogram`] on the global [`registry`].
+pub fn histogram(
+    name: &'static str,
+    help: &'static str,
+    bounds: &'static [f64],
+    labels: &[(&'static str, &str)],
+) -> Histogram {
+    registry().histogram(name, help, bounds, labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_format() {
+        let registry = Registry::new();
+        let accepted = registry.counter("accepted_total", "Accepted connections.", &[]);
+        accepted.inc();
+        accepted.add(2);
+        let packets = registry.counter("packets_total", "Packets.", &[("packet_id", "1004")]);
+        packets.inc();
+        // Same name and labels, same counter.
+        registry
+            .counter("packets_total", "Packets.", &[("packet_id", "1004")])
+            .inc();
+        registry.gauge("maps", "Maps.", &[("name", "a \"b\"")]).set(-1);
+        let latency = registry.histogram("latency_seconds", "Latency.", &[0.1, 1.0], &[]);
+        latency.observe(0.05);
+        latency.observe(0.5);
+        latency.observe(5.0);
+        registry.gauge_fn("entities", "Entities.", || {
+            vec![(vec![("map_id", String::from("1002"))], 3.0)]
+        });
+        // Not a counter.
+        registry.gauge("accepted_total", "Oops.", &[]).set(10);
+
+        let expected = "\
+# HELP accepted_total Accepted connections.
+# TYPE accepted_total counter
+accepted_total 3
+# HELP entities Entities.
+# TYPE entities gauge
+entities{map_id=\"1002\"} 3
+# HELP latency_seconds Latency.
+# TYPE latency_seconds histogram
+latency_seconds_bucket{le=\"0.1\"} 1
+latency_seconds_bucket{le=\"1\"} 2
+latency_seconds_bucket{le=\"+Inf\"} 3
+latency_seconds_sum 5.55
+latency_seconds_count 3
+# HELP maps Maps.
+# TYPE maps gauge
+maps{name=\"a \\\"b\\\"\"} -1
+# HELP packets_total Packets.
+# TYPE packets_total counter
+packets_total{packet_id=\"1004\"} 2
+";
+        assert_eq!(registry.render(), expected);
+    }
+}
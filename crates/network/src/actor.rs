@@ -1,25 +1,59 @@
-use crate::{Error, PacketEncode};
+use crate::{Error, PacketEncode, Phase, ProtocolVersion, Violation};
 use async_trait::async_trait;
 use bytes::Bytes;
 use core::hash::Hash;
+use core::net::SocketAddr;
 use core::ops::Deref;
-use core::sync::atomic::{AtomicUsize, Ordering};
-use futures::TryFutureExt;
-use tokio::sync::mpsc::Sender;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio::sync::Notify;
 use tracing::instrument;
 
 #[cfg(not(feature = "std"))]
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
 #[cfg(feature = "std")]
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Message {
     GenerateKeys(u64),
     Packet(u16, Bytes),
+    /// Switch packet capturing on or off for this connection.
+    Capture(bool),
     Shutdown,
 }
 
+/// What to do with a packet when the outbound queue of an actor is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SendPolicy {
+    /// Wait until there is room in the queue, this slows down the sender.
+    #[default]
+    Wait,
+    /// Drop the packet, good for packets that get outdated fast, like
+    /// movement.
+    Drop,
+    /// Drop the packet and disconnect the client, it is too slow to keep up.
+    Disconnect,
+}
+
+/// Which [`SendPolicy`] to use for every packet.
+///
+/// By default, every packet waits.
+#[derive(Debug, Clone, Default)]
+pub struct OutboundPolicy {
+    /// The policy of the packets that are not in `per_packet`.
+    pub default: SendPolicy,
+    /// The policy of specific packet ids.
+    pub per_packet: BTreeMap<u16, SendPolicy>,
+}
+
+impl OutboundPolicy {
+    pub fn policy(&self, packet_id: u16) -> SendPolicy {
+        self.per_packet.get(&packet_id).copied().unwrap_or(self.default)
+    }
+}
+
 /// This struct is the main actor type for the server. It is a wrapper around
 /// connections to client and its state.
 #[derive(Debug)]
@@ -36,6 +70,12 @@ pub struct Actor<S: ActorState> {
 pub struct ActorHandle {
     id: Arc<AtomicUsize>,
     tx: Sender<Message>,
+    policy: Arc<OutboundPolicy>,
+    disconnect: Arc<Notify>,
+    protocol: ProtocolVersion,
+    addr: Option<SocketAddr>,
+    phase: Arc<AtomicU8>,
+    violations: Option<UnboundedSender<Violation>>,
 }
 
 impl<S: ActorState> Hash for Actor<S> {
@@ -83,15 +123,45 @@ impl ActorState for () {
 
 impl<S: ActorState> Actor<S> {
     pub fn new(tx: Sender<Message>) -> Self {
+        Self::with_policy(tx, Default::default())
+    }
+
+    /// Creates a new actor, using `policy` when its outbound queue is full.
+    pub fn with_policy(tx: Sender<Message>, policy: Arc<OutboundPolicy>) -> Self {
         Self {
             state: S::init(),
             handle: ActorHandle {
                 id: Arc::new(AtomicUsize::new(0)),
                 tx,
+                policy,
+                disconnect: Arc::new(Notify::new()),
+                protocol: ProtocolVersion::default(),
+                addr: None,
+                phase: Arc::new(AtomicU8::new(Phase::default() as u8)),
+                violations: None,
             },
         }
     }
 
+    /// The actor speaks `protocol`, see [`Actor::protocol`].
+    pub fn with_protocol(mut self, protocol: ProtocolVersion) -> Self {
+        self.handle.protocol = protocol;
+        self
+    }
+
+    /// The client is at `addr`, see [`Actor::addr`].
+    pub fn with_addr(mut self, addr: SocketAddr) -> Self {
+        self.handle.addr = Some(addr);
+        self
+    }
+
+    /// Sends the [`Violation`]s reported for this actor to `tx`, see
+    /// [`Actor::report`].
+    pub fn with_violations(mut self, tx: UnboundedSender<Violation>) -> Self {
+        self.handle.violations = Some(tx);
+        self
+    }
+
     /// Returns a cheap clone of the actor handle
     pub fn handle(&self) -> ActorHandle {
         self.handle.clone()
@@ -105,6 +175,32 @@ impl<S: ActorState> Actor<S> {
         self.handle.set_id(id)
     }
 
+    /// The protocol version this actor's client speaks, to pick the packet
+    /// layouts that changed between the patches.
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.handle.protocol()
+    }
+
+    /// The address of this actor's client, the real one if it is behind a
+    /// trusted proxy, `None` if the server did not tell.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.handle.addr()
+    }
+
+    /// Where this actor's connection is, see [`Phase`].
+    pub fn phase(&self) -> Phase {
+        self.handle.phase()
+    }
+
+    pub fn set_phase(&self, phase: Phase) {
+        self.handle.set_phase(phase)
+    }
+
+    /// Reports a protocol violation of this actor's client.
+    pub fn report(&self, violation: Violation) {
+        self.handle.report(violation)
+    }
+
     /// Enqueue the packet and send it to the client connected to this actor
     #[instrument(skip(self, packet))]
     pub async fn send<P: PacketEncode>(&self, packet: P) -> Result<(), P::Error> {
@@ -127,6 +223,22 @@ impl<S: ActorState> Actor<S> {
         self.handle.generate_keys(seed).await
     }
 
+    #[instrument(skip(self))]
+    pub async fn set_capture(&self, enabled: bool) -> Result<(), Error> {
+        self.handle.set_capture(enabled).await
+    }
+
+    /// Number of messages waiting in the outbound queue.
+    pub fn queue_depth(&self) -> usize {
+        self.handle.queue_depth()
+    }
+
+    /// Resolves once someone asked to disconnect this actor, see
+    /// [`ActorHandle::disconnect`].
+    pub async fn disconnected(&self) {
+        self.handle.disconnected().await
+    }
+
     #[instrument(skip(self))]
     pub async fn shutdown(&self) -> Result<(), Error> {
         self.handle.shutdown().await
@@ -142,11 +254,57 @@ impl ActorHandle {
         self.id.store(id, Ordering::Relaxed);
     }
 
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    pub fn phase(&self) -> Phase {
+        Phase::from_u8(self.phase.load(Ordering::Relaxed))
+    }
+
+    pub fn set_phase(&self, phase: Phase) {
+        self.phase.store(phase as u8, Ordering::Relaxed);
+    }
+
+    /// Reports a protocol violation of this actor's client, the server counts
+    /// it against the client, and could disconnect it for that.
+    pub fn report(&self, violation: Violation) {
+        tracing::debug!(actor = self.id(), %violation, "Protocol violation");
+        if let Some(tx) = &self.violations {
+            // The connection is already gone otherwise.
+            let _ = tx.send(violation);
+        }
+    }
+
+    /// Number of messages waiting in the outbound queue.
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Asks the connection task to disconnect this actor, without going
+    /// through the outbound queue (which could be full).
+    pub fn disconnect(&self) {
+        self.set_phase(Phase::Disconnecting);
+        self.disconnect.notify_one();
+    }
+
+    /// Resolves once [`ActorHandle::disconnect`] gets called.
+    pub async fn disconnected(&self) {
+        self.disconnect.notified().await
+    }
+
     /// Enqueue the packet and send it to the client connected to this actor
+    ///
+    /// If the outbound queue is full, the [`SendPolicy`] of that packet
+    /// decides what happens, dropping the packet is not an error.
     #[instrument(skip(self, packet))]
     pub async fn send<P: PacketEncode>(&self, packet: P) -> Result<(), P::Error> {
         let msg = packet.encode()?;
-        self.tx.send(msg.into()).map_err(Into::into).await?;
+        self.enqueue(msg).await?;
         Ok(())
     }
 
@@ -158,13 +316,30 @@ impl ActorHandle {
         P: PacketEncode,
         I: IntoIterator<Item = P>,
     {
-        let tasks = packets
-            .into_iter()
-            .flat_map(|packet| packet.encode().map(|msg| msg.into()))
-            .map(|msg| self.tx.send(msg).map_err(crate::Error::from));
         // Wait for all the messages to be sent (in order)
-        for task in tasks {
-            task.await?;
+        for msg in packets.into_iter().flat_map(|packet| packet.encode()) {
+            self.enqueue(msg).await?;
+        }
+        Ok(())
+    }
+
+    async fn enqueue(&self, (packet_id, bytes): (u16, Bytes)) -> Result<(), Error> {
+        let msg = match self.tx.try_send(Message::Packet(packet_id, bytes)) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_)) => return Err(Error::SendError),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+        match self.policy.policy(packet_id) {
+            SendPolicy::Wait => {
+                self.tx.send(msg).await?;
+            },
+            SendPolicy::Drop => {
+                tracing::trace!(actor = self.id(), %packet_id, "Outbound queue is full, dropping packet");
+            },
+            SendPolicy::Disconnect => {
+                tracing::warn!(actor = self.id(), %packet_id, "Outbound queue is full, disconnecting");
+                self.disconnect();
+            },
         }
         Ok(())
     }
@@ -176,9 +351,45 @@ impl ActorHandle {
         Ok(())
     }
 
+    /// Starts or stops capturing the packets of this connection, this does
+    /// nothing if the server has no capture file configured.
+    #[instrument(skip(self))]
+    pub async fn set_capture(&self, enabled: bool) -> Result<(), Error> {
+        self.tx.send(Message::Capture(enabled)).await?;
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     pub async fn shutdown(&self) -> Result<(), Error> {
         self.tx.send(Message::Shutdown).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn full_queue_follows_policy() {
+        const MOVE: u16 = 1005;
+        let policy = OutboundPolicy {
+            default: SendPolicy::Disconnect,
+            per_packet: BTreeMap::from([(MOVE, SendPolicy::Drop)]),
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        let actor = Actor::<()>::with_policy(tx, Arc::new(policy));
+        let handle = actor.handle();
+        handle.enqueue((MOVE, Bytes::new())).await.unwrap();
+        assert_eq!(actor.queue_depth(), 1);
+        // Queue is full, movement gets dropped.
+        handle.enqueue((MOVE, Bytes::new())).await.unwrap();
+        assert_eq!(actor.queue_depth(), 1);
+        // Anything else disconnects the actor.
+        handle.enqueue((1004, Bytes::new())).await.unwrap();
+        actor.disconnected().await;
+        assert_eq!(rx.recv().await, Some(Message::Packet(MOVE, Bytes::new())));
+        assert_eq!(actor.queue_depth(), 0);
+    }
+}
@@ -24,7 +24,21 @@ mod error;
 pub use error::Error;
 
 mod actor;
-pub use actor::{Actor, ActorHandle, ActorState, Message};
+pub use actor::{Actor, ActorHandle, ActorState, Message, OutboundPolicy, SendPolicy};
+
+mod protocol;
+pub use protocol::{ProtocolCipher, ProtocolVersion, VersionedCipher};
+
+mod phase;
+pub use phase::{Phase, Phases};
+
+mod violation;
+pub use violation::{Violation, ViolationKind};
+
+mod registry;
+pub use registry::{FieldInfo, PacketInfo};
+
+pub mod middleware;
 
 /// Assoucitates a packet structure with a packet ID. This is used for
 /// serialization and deserialization of packets. The packet ID is used to
@@ -32,6 +46,8 @@ pub use actor::{Actor, ActorHandle, ActorState, Message};
 /// deserialize the packet.
 pub trait PacketID {
     const PACKET_ID: u16;
+    /// The packet name, id and wire layout.
+    const PACKET_INFO: PacketInfo;
 }
 
 #[async_trait]
@@ -39,6 +55,10 @@ pub trait PacketProcess {
     type Error;
     type ActorState: ActorState;
     type State: Send + Sync;
+    /// The [`Phase`]s of the connection this packet is allowed in, the
+    /// derived [`PacketHandler`] rejects it in any other phase, before it
+    /// gets decoded.
+    const PHASES: Phases = Phases::ALL;
     /// Process can be invoked by a packet after decode has been called to
     /// structure packet fields and properties. For the server
     /// implementations, this is called in the packet handler after the
@@ -73,6 +93,14 @@ pub trait PacketHandler {
     type Error: PacketEncode + Send + Sync;
     type ActorState: ActorState;
     type State: Send + Sync + 'static;
+    /// The packets this handler understands, see [`packets!`].
+    const PACKETS: &'static [PacketInfo] = &[];
+
+    /// Looks up a packet this handler understands by its id.
+    fn packet(id: u16) -> Option<&'static PacketInfo> {
+        Self::PACKETS.iter().find(|p| p.id == id)
+    }
+
     async fn handle(
         packet: (u16, Bytes),
         state: &Self::State,
@@ -127,6 +155,11 @@ where
 
 impl PacketID for () {
     const PACKET_ID: u16 = 0;
+    const PACKET_INFO: PacketInfo = PacketInfo {
+        name: "()",
+        id: 0,
+        fields: &[],
+    };
 }
 
 pub struct ErrorPacket<T: PacketEncode>(pub T);
@@ -0,0 +1,140 @@
+//! Middleware, code that runs around every packet a derived
+//! [`PacketHandler`] handles, for the concerns that are the same for every
+//! packet, like logging, timing or checking the actor is logged in.
+//!
+//! They are declared on the handler enum, the first one is the outermost, it
+//! sees the packet first and the result last:
+//!
+//! ```ignore
+//! #[derive(PacketHandler)]
+//! #[handle(state = State, actor_state = ActorState, middleware = [CatchPanic, Timing])]
+//! pub enum Handler {
+//!     MsgConnect,
+//! }
+//! ```
+
+use crate::{Actor, PacketHandler};
+use bytes::Bytes;
+use core::future::Future;
+use core::pin::Pin;
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+
+/// The rest of the chain, the next middleware or the packet handler itself.
+pub struct Next<'a, E> {
+    inner: Pin<Box<dyn Future<Output = Result<(), E>> + Send + 'a>>,
+}
+
+impl<'a, E> Next<'a, E> {
+    pub fn new(inner: Pin<Box<dyn Future<Output = Result<(), E>> + Send + 'a>>) -> Self {
+        Self { inner }
+    }
+
+    /// Runs the rest of the chain, not running it drops the packet.
+    pub async fn run(self) -> Result<(), E> {
+        self.inner.await
+    }
+}
+
+/// Runs around the handling of every packet of `H`.
+#[async_trait::async_trait]
+pub trait Middleware<H: PacketHandler> {
+    async fn call(
+        packet: &(u16, Bytes),
+        state: &H::State,
+        actor: &Actor<H::ActorState>,
+        next: Next<'_, H::Error>,
+    ) -> Result<(), H::Error>;
+}
+
+#[cfg(feature = "std")]
+pub use self::std_middleware::{CatchPanic, Timing};
+
+#[cfg(feature = "std")]
+mod std_middleware {
+    use super::*;
+    use core::task::{Context, Poll};
+    use std::any::Any;
+    use std::panic::AssertUnwindSafe;
+    use std::time::{Duration, Instant};
+
+    /// Logs how long every packet took, with a warning for the slow ones.
+    pub struct Timing;
+
+    impl Timing {
+        /// Anything slower than that gets a warning.
+        pub const SLOW: Duration = Duration::from_millis(100);
+    }
+
+    #[async_trait::async_trait]
+    impl<H> Middleware<H> for Timing
+    where
+        H: PacketHandler,
+        H::Error: 'static,
+    {
+        async fn call(
+            packet: &(u16, Bytes),
+            _state: &H::State,
+            _actor: &Actor<H::ActorState>,
+            next: Next<'_, H::Error>,
+        ) -> Result<(), H::Error> {
+            let started = Instant::now();
+            let result = next.run().await;
+            let elapsed = started.elapsed();
+            if elapsed > Self::SLOW {
+                tracing::warn!(packet_id = packet.0, ?elapsed, "Slow packet handler");
+            } else {
+                tracing::trace!(packet_id = packet.0, ?elapsed, "Packet handled");
+            }
+            result
+        }
+    }
+
+    /// Turns a panic while handling a packet into an error, instead of
+    /// taking the whole connection task down with it.
+    pub struct CatchPanic;
+
+    #[async_trait::async_trait]
+    impl<H> Middleware<H> for CatchPanic
+    where
+        H: PacketHandler,
+        H::Error: From<crate::Error> + 'static,
+    {
+        async fn call(
+            packet: &(u16, Bytes),
+            _state: &H::State,
+            _actor: &Actor<H::ActorState>,
+            next: Next<'_, H::Error>,
+        ) -> Result<(), H::Error> {
+            match CatchUnwind(next.inner).await {
+                Ok(result) => result,
+                Err(panic) => {
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .copied()
+                        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("unknown");
+                    tracing::error!(packet_id = packet.0, %reason, "Packet handler panicked");
+                    Err(crate::Error::Other(format!("Packet handler panicked: {reason}")).into())
+                },
+            }
+        }
+    }
+
+    /// Catches the panics while polling the inner future.
+    struct CatchUnwind<F>(F);
+
+    impl<F: Future + Unpin> Future for CatchUnwind<F> {
+        type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let inner = &mut self.0;
+            match std::panic::catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+                Ok(Poll::Pending) => Poll::Pending,
+                Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+                Err(panic) => Poll::Ready(Err(panic)),
+            }
+        }
+    }
+}
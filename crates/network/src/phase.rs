@@ -0,0 +1,77 @@
+use core::ops::BitOr;
+
+/// Where a connection is in its lifetime, it decides which packets the actor
+/// could send, see [`crate::PacketProcess::PHASES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u8)]
+pub enum Phase {
+    /// Just connected, nothing is known about the client yet.
+    #[default]
+    Handshake = 0,
+    /// Logged in, but the account has no character, the client is creating
+    /// one.
+    CharacterCreation = 1,
+    /// Logged in with a character.
+    InWorld = 2,
+    /// The connection is going away, nothing gets handled anymore.
+    Disconnecting = 3,
+}
+
+impl Phase {
+    pub(crate) fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Handshake,
+            1 => Self::CharacterCreation,
+            2 => Self::InWorld,
+            _ => Self::Disconnecting,
+        }
+    }
+}
+
+impl core::fmt::Display for Phase {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Handshake => write!(f, "Handshake"),
+            Self::CharacterCreation => write!(f, "CharacterCreation"),
+            Self::InWorld => write!(f, "InWorld"),
+            Self::Disconnecting => write!(f, "Disconnecting"),
+        }
+    }
+}
+
+/// A set of [`Phase`]s.
+///
+/// ```
+/// use tq_network::{Phase, Phases};
+///
+/// let phases = Phases::HANDSHAKE | Phases::IN_WORLD;
+/// assert!(phases.contains(Phase::InWorld));
+/// assert!(!phases.contains(Phase::CharacterCreation));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Phases(u8);
+
+impl Phases {
+    /// Every phase except [`Phase::Disconnecting`].
+    pub const ALL: Self = Self(Self::HANDSHAKE.0 | Self::CHARACTER_CREATION.0 | Self::IN_WORLD.0);
+    pub const CHARACTER_CREATION: Self = Self::of(Phase::CharacterCreation);
+    pub const HANDSHAKE: Self = Self::of(Phase::Handshake);
+    pub const IN_WORLD: Self = Self::of(Phase::InWorld);
+    pub const NONE: Self = Self(0);
+
+    pub const fn of(phase: Phase) -> Self {
+        Self(1 << phase as u8)
+    }
+
+    pub const fn contains(self, phase: Phase) -> bool {
+        self.0 & Self::of(phase).0 != 0
+    }
+}
+
+impl BitOr for Phases {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
@@ -0,0 +1,213 @@
+//! Protocol versions, what changes on the wire between the game client
+//! patches a server could speak.
+//!
+//! The version is picked per connection, out of the ones the server speaks
+//! (see `tq_server::Config::protocols`), it decides the cipher, whether there
+//! is a key exchange before the first packet and whether the frames are
+//! sealed. The handlers could ask the actor for its [`ProtocolVersion`] to
+//! pick the packet layouts.
+
+use core::fmt;
+use core::str::FromStr;
+use tq_codec::frame::{CLIENT_SEAL, SERVER_SEAL};
+use tq_crypto::{BlowfishCipher, CQCipher, Cipher, NopCipher, TQCipher};
+
+#[cfg(not(feature = "std"))]
+use alloc::format;
+
+/// The game client patches we know how to talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ProtocolVersion {
+    /// Patches up to 5017, using the [`TQCipher`] with no key exchange.
+    #[default]
+    V5017,
+    /// Patches from 5018, using the [`BlowfishCipher`] after a
+    /// Diffie-Hellman key exchange, with sealed frames.
+    V5018,
+}
+
+impl ProtocolVersion {
+    /// Whether the connection starts with the key exchange, see
+    /// `tq_codec::handshake`.
+    pub fn has_key_exchange(self) -> bool {
+        self >= Self::V5018
+    }
+
+    /// Ends every frame the server sends, if any.
+    pub fn server_seal(self) -> &'static [u8] {
+        match self {
+            Self::V5017 => &[],
+            Self::V5018 => SERVER_SEAL,
+        }
+    }
+
+    /// Ends every frame the client sends, if any.
+    pub fn client_seal(self) -> &'static [u8] {
+        match self {
+            Self::V5017 => &[],
+            Self::V5018 => CLIENT_SEAL,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V5017 => write!(f, "5017"),
+            Self::V5018 => write!(f, "5018"),
+        }
+    }
+}
+
+/// Parses the patch number, any patch picks the newest version it could
+/// speak.
+impl FromStr for ProtocolVersion {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patch: u16 = s
+            .trim()
+            .parse()
+            .map_err(|_| crate::Error::Other(format!("Invalid patch number: {s}")))?;
+        match patch {
+            0..=5017 => Ok(Self::V5017),
+            _ => Ok(Self::V5018),
+        }
+    }
+}
+
+/// A cipher the server could pick per [`ProtocolVersion`].
+pub trait VersionedCipher: Cipher {
+    /// Whether this cipher could be used for `version`.
+    fn supports(version: ProtocolVersion) -> bool;
+
+    /// A new server side cipher for a connection speaking `version`.
+    fn for_version(version: ProtocolVersion) -> Self;
+
+    /// The cipher the key exchange switches to the agreed key, if this
+    /// connection has one.
+    fn key_exchange(&self) -> Option<&BlowfishCipher> {
+        None
+    }
+}
+
+impl VersionedCipher for TQCipher {
+    fn supports(version: ProtocolVersion) -> bool {
+        version == ProtocolVersion::V5017
+    }
+
+    fn for_version(_version: ProtocolVersion) -> Self {
+        Self::new()
+    }
+}
+
+impl VersionedCipher for CQCipher {
+    fn supports(version: ProtocolVersion) -> bool {
+        version == ProtocolVersion::V5017
+    }
+
+    fn for_version(_version: ProtocolVersion) -> Self {
+        Self::new()
+    }
+}
+
+impl VersionedCipher for NopCipher {
+    fn supports(version: ProtocolVersion) -> bool {
+        version == ProtocolVersion::V5017
+    }
+
+    fn for_version(_version: ProtocolVersion) -> Self {
+        Self
+    }
+}
+
+/// Any of the ciphers, picked by the [`ProtocolVersion`] at connect time.
+#[derive(Clone)]
+pub enum ProtocolCipher {
+    /// The server side, up to patch 5017.
+    TQ(TQCipher),
+    /// The client side, up to patch 5017.
+    CQ(CQCipher),
+    /// Both sides, from patch 5018.
+    Blowfish(BlowfishCipher),
+}
+
+impl ProtocolCipher {
+    /// A new client side cipher for a connection speaking `version`.
+    pub fn client(version: ProtocolVersion) -> Self {
+        match version {
+            ProtocolVersion::V5017 => Self::CQ(CQCipher::new()),
+            ProtocolVersion::V5018 => Self::Blowfish(BlowfishCipher::default()),
+        }
+    }
+}
+
+impl Default for ProtocolCipher {
+    fn default() -> Self {
+        Self::for_version(ProtocolVersion::default())
+    }
+}
+
+impl Cipher for ProtocolCipher {
+    fn generate_keys(&self, seed: u64) {
+        match self {
+            Self::TQ(c) => c.generate_keys(seed),
+            Self::CQ(c) => c.generate_keys(seed),
+            Self::Blowfish(c) => c.generate_keys(seed),
+        }
+    }
+
+    fn decrypt(&self, data: &mut [u8]) {
+        match self {
+            Self::TQ(c) => c.decrypt(data),
+            Self::CQ(c) => c.decrypt(data),
+            Self::Blowfish(c) => c.decrypt(data),
+        }
+    }
+
+    fn encrypt(&self, data: &mut [u8]) {
+        match self {
+            Self::TQ(c) => c.encrypt(data),
+            Self::CQ(c) => c.encrypt(data),
+            Self::Blowfish(c) => c.encrypt(data),
+        }
+    }
+}
+
+impl VersionedCipher for ProtocolCipher {
+    fn supports(_version: ProtocolVersion) -> bool {
+        true
+    }
+
+    fn for_version(version: ProtocolVersion) -> Self {
+        match version {
+            ProtocolVersion::V5017 => Self::TQ(TQCipher::new()),
+            ProtocolVersion::V5018 => Self::Blowfish(BlowfishCipher::default()),
+        }
+    }
+
+    fn key_exchange(&self) -> Option<&BlowfishCipher> {
+        match self {
+            Self::Blowfish(c) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_patches() {
+        assert_eq!("5017".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V5017);
+        assert_eq!("4343".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V5017);
+        assert_eq!("5018".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V5018);
+        assert_eq!(" 5065 ".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V5018);
+        assert!("latest".parse::<ProtocolVersion>().is_err());
+        assert!(ProtocolCipher::for_version(ProtocolVersion::V5018)
+            .key_exchange()
+            .is_some());
+        assert!(TQCipher::for_version(ProtocolVersion::V5017).key_exchange().is_none());
+    }
+}
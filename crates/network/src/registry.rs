@@ -0,0 +1,142 @@
+use core::fmt;
+
+/// Describes a packet and its wire layout, derived with [`crate::PacketID`].
+///
+/// Packet ids only have to be unique within a registry (see [`packets!`]),
+/// the account and the game server both have their own `MsgConnect` with the
+/// id 1052 for example.
+///
+/// [`packets!`]: crate::packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    pub name: &'static str,
+    pub id: u16,
+    /// The fields in the order they are on the wire.
+    pub fields: &'static [FieldInfo],
+}
+
+/// A field of a packet, see [`PacketInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    /// The field type, as it is written in the packet struct.
+    pub ty: &'static str,
+    /// How many bytes the field takes on the wire, `None` if it depends on
+    /// the value (like strings) or it is not known.
+    pub size: Option<usize>,
+}
+
+impl PacketInfo {
+    /// The size of the packet body, `None` if any of its fields has no fixed
+    /// size.
+    pub const fn size(&self) -> Option<usize> {
+        let mut size = 0;
+        let mut i = 0;
+        while i < self.fields.len() {
+            match self.fields[i].size {
+                Some(s) => size += s,
+                None => return None,
+            }
+            i += 1;
+        }
+        Some(size)
+    }
+
+    /// The field at `offset` bytes in the packet body, if all the fields
+    /// before it have a fixed size.
+    pub fn field_at(&self, offset: usize) -> Option<&FieldInfo> {
+        let mut start = 0;
+        for field in self.fields {
+            let size = field.size?;
+            if offset < start + size {
+                return Some(field);
+            }
+            start += size;
+        }
+        None
+    }
+}
+
+/// Prints the packet layout, one field per line with its offset.
+impl fmt::Display for PacketInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.name, self.id)?;
+        let mut offset = Some(0);
+        for field in self.fields {
+            match offset {
+                Some(o) => write!(f, "  {:<6}", o)?,
+                None => write!(f, "  {:<6}", "?")?,
+            }
+            write!(f, "{:<24}{:<24}", field.name, field.ty)?;
+            match field.size {
+                Some(size) => writeln!(f, "{}", size)?,
+                None => writeln!(f, "?")?,
+            }
+            offset = offset.zip(field.size).map(|(o, s)| o + s);
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `&'static [PacketInfo]` registry out of packet types, it fails to
+/// compile if any two of them have the same id.
+///
+/// ```
+/// use tq_network::{packets, PacketID, PacketInfo};
+///
+/// #[derive(PacketID)]
+/// #[packet(id = 1001)]
+/// struct MsgPing {
+///     timestamp: u32,
+/// }
+///
+/// #[derive(PacketID)]
+/// #[packet(id = 1002)]
+/// struct MsgPong {
+///     timestamp: u32,
+///     name: String,
+/// }
+///
+/// const PACKETS: &[PacketInfo] = packets![MsgPing, MsgPong];
+/// assert_eq!(PACKETS[0].size(), Some(4));
+/// assert_eq!(PACKETS[1].size(), None);
+/// assert_eq!(PACKETS[1].field_at(2).map(|f| f.name), Some("timestamp"));
+/// ```
+///
+/// ```compile_fail
+/// use tq_network::{packets, PacketID, PacketInfo};
+///
+/// #[derive(PacketID)]
+/// #[packet(id = 1001)]
+/// struct MsgPing;
+///
+/// #[derive(PacketID)]
+/// #[packet(id = 1001)]
+/// struct MsgPong;
+///
+/// const PACKETS: &[PacketInfo] = packets![MsgPing, MsgPong];
+/// ```
+#[macro_export]
+macro_rules! packets {
+    ($($packet:ty),* $(,)?) => {{
+        const _: () = {
+            $crate::__assert_unique_ids!($($packet),*);
+        };
+        &[$(<$packet as $crate::PacketID>::PACKET_INFO),*]
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __assert_unique_ids {
+    ($first:ty $(, $rest:ty)*) => {
+        $(
+            assert!(
+                <$first as $crate::PacketID>::PACKET_ID != <$rest as $crate::PacketID>::PACKET_ID,
+                concat!("`", stringify!($first), "` and `", stringify!($rest), "` have the same packet id"),
+            );
+        )*
+        $crate::__assert_unique_ids!($($rest),*);
+    };
+    () => {};
+}
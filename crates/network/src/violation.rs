@@ -0,0 +1,111 @@
+use crate::Phase;
+
+#[cfg(not(feature = "std"))]
+use alloc::format;
+
+/// Something a well behaved client never sends, reported with
+/// [`crate::ActorHandle::report`] and counted against the client by the
+/// server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The packet body could not be decoded.
+    MalformedPacket { packet_id: u16 },
+    /// There is no handler for that packet id.
+    UnknownPacket { packet_id: u16 },
+    /// The frame is bigger than the server accepts.
+    OversizedFrame { packet_id: u16, len: u16 },
+    /// A field is out of its range, like a value that is not any of the
+    /// variants of its enum.
+    InvalidValue { packet_id: u16, field: &'static str },
+    /// The packet is not allowed in the current [`Phase`] of the connection.
+    OutOfPhase { packet_id: u16, phase: Phase },
+    /// A login (or character creation) token got refused, like an unknown
+    /// or an expired one.
+    InvalidToken { packet_id: u16, reason: &'static str },
+}
+
+/// The kind of a [`Violation`], without the details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ViolationKind {
+    MalformedPacket,
+    UnknownPacket,
+    OversizedFrame,
+    InvalidValue,
+    OutOfPhase,
+    InvalidToken,
+}
+
+impl Violation {
+    pub fn kind(&self) -> ViolationKind {
+        match self {
+            Self::MalformedPacket { .. } => ViolationKind::MalformedPacket,
+            Self::UnknownPacket { .. } => ViolationKind::UnknownPacket,
+            Self::OversizedFrame { .. } => ViolationKind::OversizedFrame,
+            Self::InvalidValue { .. } => ViolationKind::InvalidValue,
+            Self::OutOfPhase { .. } => ViolationKind::OutOfPhase,
+            Self::InvalidToken { .. } => ViolationKind::InvalidToken,
+        }
+    }
+
+    pub fn packet_id(&self) -> u16 {
+        match *self {
+            Self::MalformedPacket { packet_id }
+            | Self::UnknownPacket { packet_id }
+            | Self::OversizedFrame { packet_id, .. }
+            | Self::InvalidValue { packet_id, .. }
+            | Self::OutOfPhase { packet_id, .. }
+            | Self::InvalidToken { packet_id, .. } => packet_id,
+        }
+    }
+}
+
+impl core::fmt::Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::MalformedPacket { packet_id } => write!(f, "Malformed Packet {}", packet_id),
+            Self::UnknownPacket { packet_id } => write!(f, "Unknown Packet {}", packet_id),
+            Self::OversizedFrame { packet_id, len } => {
+                write!(f, "Oversized Frame ({} bytes, Packet {})", len, packet_id)
+            },
+            Self::InvalidValue { packet_id, field } => {
+                write!(f, "Invalid Value of `{}` in Packet {}", field, packet_id)
+            },
+            Self::OutOfPhase { packet_id, phase } => {
+                write!(f, "Packet {} is not allowed in the {} phase", packet_id, phase)
+            },
+            Self::InvalidToken { packet_id, reason } => {
+                write!(f, "Invalid Token in Packet {}: {}", packet_id, reason)
+            },
+        }
+    }
+}
+
+impl ViolationKind {
+    /// A short name for the kind, like `malformed_packet`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MalformedPacket => "malformed_packet",
+            Self::UnknownPacket => "unknown_packet",
+            Self::OversizedFrame => "oversized_frame",
+            Self::InvalidValue => "invalid_value",
+            Self::OutOfPhase => "out_of_phase",
+            Self::InvalidToken => "invalid_token",
+        }
+    }
+}
+
+impl core::str::FromStr for ViolationKind {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "malformed_packet" => Ok(Self::MalformedPacket),
+            "unknown_packet" => Ok(Self::UnknownPacket),
+            "oversized_frame" => Ok(Self::OversizedFrame),
+            "invalid_value" => Ok(Self::InvalidValue),
+            "out_of_phase" => Ok(Self::OutOfPhase),
+            "invalid_token" => Ok(Self::InvalidToken),
+            v => Err(crate::Error::Other(format!("Unknown violation kind {v}"))),
+        }
+    }
+}
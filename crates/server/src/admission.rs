@@ -0,0 +1,292 @@
+//! Connection admission control.
+//!
+//! Before a new connection gets its own task, it has to be admitted by the
+//! [`Admission`] controller, which keeps track of how many connections are
+//! open in total and per source IP, and which IPs are banned. Admitted
+//! connections hold an [`AdmissionPermit`] for as long as they are alive,
+//! dropping it frees the slot again.
+//!
+//! Rejected connections are told why before getting closed, but only a few
+//! of them at a time, see [`AdmissionConfig::max_rejections`].
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Why a connection was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The server reached its maximum number of connections.
+    ServerFull,
+    /// This IP already has too many open connections.
+    TooManyConnections(IpAddr),
+    /// This IP is banned for a while, see [`Admission::ban`].
+    Banned(IpAddr),
+}
+
+impl RejectReason {
+    /// A short name for the reason, without the details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ServerFull => "server_full",
+            Self::TooManyConnections(_) => "too_many_connections",
+            Self::Banned(_) => "banned",
+        }
+    }
+}
+
+impl core::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ServerFull => write!(f, "Server is full"),
+            Self::TooManyConnections(ip) => write!(f, "Too many connections from {ip}"),
+            Self::Banned(ip) => write!(f, "{ip} is banned"),
+        }
+    }
+}
+
+/// Exponential backoff used when accepting a new connection fails, for
+/// example when we run out of file descriptors (`EMFILE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptBackoff {
+    /// How long to wait after the first error.
+    pub initial: Duration,
+    /// The maximum time to wait between retries.
+    pub max: Duration,
+}
+
+impl AcceptBackoff {
+    /// Returns the delay to use after `delay`, doubling it up to `max`.
+    pub fn next(&self, delay: Duration) -> Duration {
+        if delay.is_zero() {
+            self.initial
+        } else {
+            delay.saturating_mul(2).min(self.max)
+        }
+    }
+}
+
+impl Default for AcceptBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Admission policies applied to every new connection.
+///
+/// By default, every connection is accepted.
+#[derive(Debug, Clone)]
+pub struct AdmissionConfig {
+    /// Maximum number of connections open at the same time.
+    pub max_connections: Option<usize>,
+    /// Maximum number of connections open at the same time from the same IP.
+    pub max_connections_per_ip: Option<usize>,
+    /// Maximum number of rejected connections being told why at the same
+    /// time, the ones over it are closed right away.
+    pub max_rejections: usize,
+    /// Backoff applied when accepting a new connection fails.
+    pub accept_backoff: AcceptBackoff,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_rejections: 32,
+            accept_backoff: AcceptBackoff::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    /// The banned IPs, until when.
+    banned: HashMap<IpAddr, Instant>,
+}
+
+/// Keeps track of the open connections, built from an [`AdmissionConfig`].
+#[derive(Debug, Clone)]
+pub struct Admission {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    counters: Arc<Mutex<Counters>>,
+    rejections: Arc<Semaphore>,
+}
+
+impl Admission {
+    pub fn new(config: &AdmissionConfig) -> Self {
+        Self {
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            counters: Default::default(),
+            rejections: Arc::new(Semaphore::new(config.max_rejections)),
+        }
+    }
+
+    /// Tries to admit a new connection from `ip`.
+    ///
+    /// The returned permit must be kept alive as long as the connection is
+    /// open.
+    pub fn admit(&self, ip: IpAddr) -> Result<AdmissionPermit, RejectReason> {
+        let mut counters = self.counters.lock().expect("admission lock poisoned");
+        if let Some(&until) = counters.banned.get(&ip) {
+            if until > Instant::now() {
+                return Err(RejectReason::Banned(ip));
+            }
+            counters.banned.remove(&ip);
+        }
+        if matches!(self.max_connections, Some(max) if counters.total >= max) {
+            return Err(RejectReason::ServerFull);
+        }
+        let from_ip = counters.per_ip.get(&ip).copied().unwrap_or_default();
+        if matches!(self.max_connections_per_ip, Some(max) if from_ip >= max) {
+            return Err(RejectReason::TooManyConnections(ip));
+        }
+        counters.total += 1;
+        *counters.per_ip.entry(ip).or_default() += 1;
+        Ok(AdmissionPermit {
+            ip,
+            counters: self.counters.clone(),
+        })
+    }
+
+    /// Takes one of the [`AdmissionConfig::max_rejections`] slots, to tell a
+    /// rejected connection why, `None` if they are all taken and it should
+    /// just be closed.
+    pub fn try_reject(&self) -> Option<OwnedSemaphorePermit> {
+        self.rejections.clone().try_acquire_owned().ok()
+    }
+
+    /// Number of connections currently open.
+    pub fn connections(&self) -> usize {
+        self.counters.lock().expect("admission lock poisoned").total
+    }
+
+    /// Refuses any new connection from `ip` for `duration`, the open ones are
+    /// not affected.
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        ban(&self.counters, ip, duration);
+    }
+}
+
+/// A slot held by an admitted connection, released on drop.
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    ip: IpAddr,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl AdmissionPermit {
+    /// Bans the IP of this connection, see [`Admission::ban`].
+    pub fn ban(&self, duration: Duration) {
+        ban(&self.counters, self.ip, duration);
+    }
+}
+
+fn ban(counters: &Mutex<Counters>, ip: IpAddr, duration: Duration) {
+    let now = Instant::now();
+    let until = now + duration;
+    let mut counters = counters.lock().expect("admission lock poisoned");
+    // Most banned IPs never come back to be dropped in `admit`.
+    counters.banned.retain(|_, until| *until > now);
+    let banned = counters.banned.entry(ip).or_insert(until);
+    *banned = (*banned).max(until);
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        let mut counters = self.counters.lock().expect("admission lock poisoned");
+        counters.total = counters.total.saturating_sub(1);
+        if let Some(n) = counters.per_ip.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                counters.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_are_enforced_and_released() {
+        let a: IpAddr = [10, 0, 0, 1].into();
+        let b: IpAddr = [10, 0, 0, 2].into();
+        let admission = Admission::new(&AdmissionConfig {
+            max_connections: Some(3),
+            max_connections_per_ip: Some(2),
+            ..Default::default()
+        });
+        let p1 = admission.admit(a).unwrap();
+        let _p2 = admission.admit(a).unwrap();
+        assert_eq!(admission.admit(a).unwrap_err(), RejectReason::TooManyConnections(a));
+        let _p3 = admission.admit(b).unwrap();
+        assert_eq!(admission.admit(b).unwrap_err(), RejectReason::ServerFull);
+        drop(p1);
+        assert_eq!(admission.connections(), 2);
+        assert!(admission.admit(a).is_ok());
+    }
+
+    #[test]
+    fn rejections_are_capped() {
+        let admission = Admission::new(&AdmissionConfig {
+            max_rejections: 2,
+            ..Default::default()
+        });
+        let r1 = admission.try_reject().unwrap();
+        let _r2 = admission.try_reject().unwrap();
+        assert!(admission.try_reject().is_none());
+        drop(r1);
+        assert!(admission.try_reject().is_some());
+    }
+
+    #[test]
+    fn banned_ips_are_rejected_until_it_expires() {
+        let a: IpAddr = [10, 0, 0, 1].into();
+        let admission = Admission::new(&AdmissionConfig::default());
+        admission.ban(a, Duration::from_secs(60));
+        assert_eq!(admission.admit(a).unwrap_err(), RejectReason::Banned(a));
+        assert!(admission.admit([10, 0, 0, 2].into()).is_ok());
+        admission.ban(a, Duration::ZERO);
+        assert_eq!(admission.admit(a).unwrap_err(), RejectReason::Banned(a));
+        // Expired.
+        let admission = Admission::new(&AdmissionConfig::default());
+        admission.ban(a, Duration::ZERO);
+        assert!(admission.admit(a).is_ok());
+        // Expired bans are dropped even if the IP never comes back.
+        let b: IpAddr = [10, 0, 0, 2].into();
+        admission.ban(a, Duration::ZERO);
+        admission.ban(b, Duration::from_secs(60));
+        let banned = admission
+            .counters
+            .lock()
+            .unwrap()
+            .banned
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(banned, [b]);
+    }
+
+    #[test]
+    fn accept_backoff_doubles_up_to_max() {
+        let backoff = AcceptBackoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+        };
+        let first = backoff.next(Duration::ZERO);
+        assert_eq!(first, Duration::from_millis(100));
+        assert_eq!(backoff.next(first), Duration::from_millis(200));
+        assert_eq!(backoff.next(Duration::from_millis(200)), Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use tq_codec::frame::MAX_FRAME_SIZE;
+use tq_network::{OutboundPolicy, ProtocolVersion};
+
+use crate::admission::AdmissionConfig;
+use crate::idle::IdleConfig;
+use crate::proxy::ProxyConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::violation::ViolationConfig;
+
+/// Server Configuration, controls how the server treats its connections.
+///
+/// The [`Default`] configuration has no limits at all, and speaks the
+/// default [`ProtocolVersion`] only.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Which connections are allowed in.
+    pub admission: AdmissionConfig,
+    /// When to consider a connection dead.
+    pub idle: IdleConfig,
+    /// Packets and bytes budgets for every connection.
+    pub rate_limit: RateLimitConfig,
+    /// How many protocol violations a connection gets away with.
+    pub violations: ViolationConfig,
+    /// Where to capture the packets, if at all.
+    pub capture: Option<CaptureConfig>,
+    /// The outbound queue of every connection.
+    pub outbound: OutboundConfig,
+    /// The game client patches the connections could speak, the server's
+    /// cipher has to support all of them. With more than one, every
+    /// connection gets its own, see [`crate::protocol`].
+    pub protocols: Vec<ProtocolVersion>,
+    /// Read the PROXY protocol header from the trusted proxies, if this
+    /// server is behind a load balancer.
+    pub proxy: Option<ProxyConfig>,
+    /// The socket and framing of every connection.
+    pub stream: StreamConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            admission: Default::default(),
+            idle: Default::default(),
+            rate_limit: Default::default(),
+            violations: Default::default(),
+            capture: Default::default(),
+            outbound: Default::default(),
+            protocols: vec![ProtocolVersion::default()],
+            proxy: Default::default(),
+            stream: Default::default(),
+        }
+    }
+}
+
+/// Socket and framing options of every connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// The IP time-to-live of the accepted sockets, the system default if
+    /// `None`.
+    pub ttl: Option<u32>,
+    /// The biggest frame a client could send, see
+    /// [`tq_codec::frame::FrameDecoder::with_max_size`].
+    pub max_frame_size: u16,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Some(5),
+            max_frame_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Outbound queue configuration, the packets waiting to be written to the
+/// client.
+#[derive(Debug, Clone)]
+pub struct OutboundConfig {
+    /// How many packets could wait in the queue.
+    pub queue_size: usize,
+    /// What to do when the queue is full.
+    pub policy: OutboundPolicy,
+    /// How the queued packets get written to the socket.
+    pub coalesce: CoalesceConfig,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 1024,
+            policy: OutboundPolicy::default(),
+            coalesce: CoalesceConfig::default(),
+        }
+    }
+}
+
+/// Write coalescing, instead of writing every packet on its own, the packets
+/// already waiting in the outbound queue get encrypted into one buffer and
+/// written to the socket at once.
+///
+/// A batch is flushed once the queue is empty (after waiting `linger` for
+/// more packets), or it reached `max_packets` or `max_bytes`, whichever comes
+/// first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    /// The most packets in a single write.
+    pub max_packets: usize,
+    /// Flush once that many bytes got buffered.
+    pub max_bytes: usize,
+    /// How long to wait for more packets once the queue is empty, this is the
+    /// most a packet could be delayed. Zero never waits, it only batches what
+    /// is already queued.
+    pub linger: Duration,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            max_packets: 64,
+            max_bytes: 16 * 1024,
+            linger: Duration::ZERO,
+        }
+    }
+}
+
+/// Packet capture configuration, see [`tq_codec::capture`].
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// The capture file, shared between all the connections.
+    pub path: PathBuf,
+    /// Start recording every connection once it gets connected, otherwise
+    /// recording has to be switched on per actor, see
+    /// [`tq_network::ActorHandle::set_capture`].
+    pub record_all: bool,
+}
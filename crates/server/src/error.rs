@@ -3,6 +3,7 @@ pub enum Error {
     TQNetwork(tq_network::Error),
     AddrParseError(std::net::AddrParseError),
     IO(std::io::Error),
+    Proxy(crate::proxy::ProxyError),
     Internal(Box<dyn std::error::Error + Send + Sync>),
 }
 
@@ -12,6 +13,7 @@ impl core::fmt::Display for Error {
             Self::TQNetwork(e) => write!(f, "TQNetwork Error: {}", e),
             Self::AddrParseError(e) => write!(f, "AddrParse Error: {}", e),
             Self::IO(e) => write!(f, "IO Error: {}", e),
+            Self::Proxy(e) => write!(f, "Proxy Error: {}", e),
             Self::Internal(s) => write!(f, "Internal Error: {}", s),
         }
     }
@@ -35,4 +37,10 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<crate::proxy::ProxyError> for Error {
+    fn from(e: crate::proxy::ProxyError) -> Self {
+        Self::Proxy(e)
+    }
+}
+
 impl std::error::Error for Error {}
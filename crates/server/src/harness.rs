@@ -0,0 +1,243 @@
+//! In-memory test harness, runs a [`TQServer`] without any real sockets.
+//!
+//! Every client gets a [`tokio::io::duplex`] pair, the server half is served
+//! exactly like an accepted TCP connection (same hooks, same config), while
+//! the other half is a scripted [`TestClient`] using the real client cipher,
+//! so tests could assert on the exact packets every client receives.
+//!
+//! ```ignore
+//! let harness = Harness::<GameServer>::new(state);
+//! let mut client = harness.connect(CQCipher::new());
+//! client.send(MsgConnect { token, ..Default::default() }).await?;
+//! let msg: MsgTalk = client.recv_packet().await;
+//! harness.shutdown().await;
+//! ```
+//!
+//! Packet capture is not part of the harness. Admission control is, every
+//! client comes from the loopback IP, so a ban there bans all of them.
+
+use crate::admission::Admission;
+use crate::{reject_stream, serve_stream, Config, Connection, Error, Shutdown, TQServer};
+use bytes::Bytes;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::DuplexStream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tq_codec::{TQCodec, TQDecoder, TQEncoder};
+use tq_crypto::Cipher;
+use tq_network::{OutboundPolicy, PacketDecode, PacketEncode, PacketHandler, PacketID};
+
+/// How long a [`TestClient`] waits for a packet before giving up.
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of the in-memory pipe, in each direction.
+const PIPE_SIZE: usize = 64 * 1024;
+
+type State<S> = <<S as TQServer>::PacketHandler as PacketHandler>::State;
+
+struct Inner<S: TQServer> {
+    state: &'static State<S>,
+    config: Arc<Config>,
+    outbound_policy: Arc<OutboundPolicy>,
+    admission: Admission,
+    notify_shutdown: broadcast::Sender<()>,
+    next_port: AtomicU16,
+    connections: Mutex<Vec<JoinHandle<Result<(), Error>>>>,
+}
+
+/// Runs a [`TQServer`] over in-memory connections.
+///
+/// Cheap to clone, all the clones serve the same server.
+pub struct Harness<S: TQServer> {
+    inner: Arc<Inner<S>>,
+}
+
+impl<S: TQServer> Clone for Harness<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S: TQServer + 'static> Harness<S> {
+    /// Creates a new harness with the default (unlimited) configuration.
+    pub fn new(state: &'static State<S>) -> Self {
+        Self::with_config(state, Config::default())
+    }
+
+    pub fn with_config(state: &'static State<S>, config: Config) -> Self {
+        let (notify_shutdown, _) = broadcast::channel(1);
+        Self {
+            inner: Arc::new(Inner {
+                state,
+                outbound_policy: Arc::new(config.outbound.policy.clone()),
+                admission: Admission::new(&config.admission),
+                config: Arc::new(config),
+                notify_shutdown,
+                next_port: AtomicU16::new(1),
+                connections: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Opens a new connection to the server, returning the client half.
+    ///
+    /// Every connection gets a different (made up) loopback address, which is
+    /// what [`TQServer::on_connected`] sees.
+    pub fn open(&self) -> (DuplexStream, SocketAddr) {
+        let (client, server) = tokio::io::duplex(PIPE_SIZE);
+        let port = self.inner.next_port.fetch_add(1, Ordering::Relaxed);
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let inner = self.inner.clone();
+        let shutdown = Shutdown::new(inner.notify_shutdown.subscribe());
+        let task = tokio::spawn(async move {
+            let permit = match inner.admission.admit(addr.ip()) {
+                Ok(permit) => permit,
+                Err(reason) => {
+                    let Some(_rejecting) = inner.admission.try_reject() else {
+                        return Ok(());
+                    };
+                    return reject_stream::<S, _>(server, &inner.config.protocols, inner.state, reason).await;
+                },
+            };
+            let conn = Connection {
+                addr,
+                permit,
+                recorder: None,
+                shutdown,
+            };
+            let result =
+                serve_stream::<S, _>(server, conn, &inner.config, inner.outbound_policy.clone(), inner.state).await;
+            if let Err(e) = &result {
+                tracing::error!(%addr, error = %e, "Test connection failed");
+            }
+            result
+        });
+        self.inner
+            .connections
+            .lock()
+            .expect("connections lock poisoned")
+            .push(task);
+        (client, addr)
+    }
+
+    /// Connects a new [`TestClient`], `cipher` should be the client side of
+    /// [`TQServer::Cipher`].
+    ///
+    /// The [`TestClient`] does not do the key exchange, for the protocol
+    /// versions that have one, run it over [`Harness::open`] instead.
+    pub fn connect<C: Cipher>(&self, cipher: C) -> TestClient<C> {
+        let (stream, addr) = self.open();
+        TestClient::new(stream, addr, cipher)
+    }
+
+    /// Shuts the server down the same way [`TQServer::run`] does, and waits
+    /// for every connection to finish.
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        S::on_shutdown(self.inner.state).await?;
+        let _ = self.inner.notify_shutdown.send(());
+        let connections = std::mem::take(&mut *self.inner.connections.lock().expect("connections lock poisoned"));
+        for connection in connections {
+            connection.await.map_err(|e| Error::Internal(e.into()))??;
+        }
+        Ok(())
+    }
+}
+
+/// A scripted game client, talking to the server over an in-memory pipe.
+pub struct TestClient<C: Cipher> {
+    addr: SocketAddr,
+    cipher: C,
+    encoder: TQEncoder<DuplexStream, C>,
+    decoder: TQDecoder<DuplexStream, C>,
+}
+
+impl<C: Cipher> TestClient<C> {
+    /// Wraps any stream connected to a server, using the client `cipher`.
+    pub fn new(stream: DuplexStream, addr: SocketAddr, cipher: C) -> Self {
+        let (encoder, decoder) = TQCodec::new(stream, cipher.clone()).split();
+        Self {
+            addr,
+            cipher,
+            encoder,
+            decoder,
+        }
+    }
+
+    /// The address the server sees for this client.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Switches to the new keys, like the game client does after sending
+    /// `MsgConnect`.
+    pub fn generate_keys(&self, seed: u64) {
+        self.cipher.generate_keys(seed);
+    }
+
+    pub async fn send<P>(&mut self, packet: P) -> Result<(), Error>
+    where
+        P: PacketEncode,
+        Error: From<P::Error>,
+    {
+        let packet = packet.encode()?;
+        self.encoder.send(packet).await?;
+        Ok(())
+    }
+
+    /// Receives the next packet, `None` once the server closed the
+    /// connection.
+    ///
+    /// Fails if nothing arrives within [`RECV_TIMEOUT`].
+    pub async fn recv(&mut self) -> Result<Option<(u16, Bytes)>, Error> {
+        match tokio::time::timeout(RECV_TIMEOUT, self.decoder.next()).await {
+            Ok(Some(packet)) => Ok(Some(packet?)),
+            Ok(None) => Ok(None),
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "No packet received").into()),
+        }
+    }
+
+    /// Receives the next packet and decodes it as `P`.
+    ///
+    /// # Panics
+    ///
+    /// If the connection got closed, timed out or the next packet is not a
+    /// `P`.
+    pub async fn recv_packet<P>(&mut self) -> P
+    where
+        P: PacketID + PacketDecode<Packet = P>,
+    {
+        let (id, bytes) = self
+            .recv()
+            .await
+            .expect("Failed to receive packet")
+            .expect("Connection closed");
+        assert_eq!(id, P::PACKET_ID, "Unexpected packet");
+        P::decode(&bytes).unwrap_or_else(|e| panic!("Failed to decode packet #{id}: {e:?}"))
+    }
+
+    /// Waits for the server to close the connection.
+    ///
+    /// # Panics
+    ///
+    /// If a packet arrives first, or the connection is still open after
+    /// [`RECV_TIMEOUT`].
+    pub async fn expect_closed(&mut self) {
+        match self.recv().await.expect("Connection still open") {
+            None => {},
+            Some((id, _)) => panic!("Expected the connection to close, got packet #{id}"),
+        }
+    }
+
+    /// Closes the connection from the client side.
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.encoder.close().await?;
+        Ok(())
+    }
+}
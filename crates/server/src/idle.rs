@@ -0,0 +1,65 @@
+//! Idle connections detection.
+//!
+//! A client that stops sending data (or a half-open TCP session) would keep
+//! its connection task alive forever, so every connection has a read-idle
+//! timeout that depends on whether it finished logging in or not, see
+//! [`TQServer::is_logged_in`](crate::TQServer::is_logged_in).
+//!
+//! Optionally, the server could send a keepalive packet every once in a while
+//! (see [`TQServer::on_keepalive`](crate::TQServer::on_keepalive)), writing to
+//! a dead connection fails and closes it sooner.
+
+use std::future;
+use std::time::Duration;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// Idle timeouts and keepalive configuration.
+///
+/// By default, connections never time out and no keepalive is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleConfig {
+    /// How long a client can stay silent before logging in.
+    pub login: Option<Duration>,
+    /// How long a client can stay silent after logging in.
+    pub in_game: Option<Duration>,
+    /// How often the server sends a keepalive to the client.
+    pub keepalive: Option<Duration>,
+}
+
+impl IdleConfig {
+    /// Returns the idle timeout for a connection.
+    pub fn timeout(&self, logged_in: bool) -> Option<Duration> {
+        if logged_in {
+            self.in_game
+        } else {
+            self.login
+        }
+    }
+
+    /// Creates the keepalive timer, the first tick is after one full period.
+    pub(crate) fn keepalive_interval(&self) -> Option<Interval> {
+        self.keepalive.map(|period| {
+            let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        })
+    }
+}
+
+/// Sleeps until the deadline, or forever if there is none.
+pub(crate) async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// Waits for the next tick, or forever if there is no interval.
+pub(crate) async fn tick(interval: Option<&mut Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        },
+        None => future::pending().await,
+    }
+}
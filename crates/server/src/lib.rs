@@ -1,24 +1,77 @@
 //! This crate contains Creating Servers common code.
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::sync::mpsc;
-use tokio::task::Builder;
-use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::{Builder, JoinSet};
+use tokio::time::Instant;
 use tokio_stream::StreamExt;
-use tq_codec::{TQCodec, TQEncoder};
+use tq_codec::capture::{CaptureFile, Recorder};
+use tq_codec::frame::FrameError;
+use tq_codec::{handshake, TQCodec, TQEncoder};
 use tq_crypto::Cipher;
-use tq_network::{Actor, ActorState, Message, PacketHandler};
+use tq_network::{
+    Actor, ActorState, Message, OutboundPolicy, PacketHandler, Phase, ProtocolVersion, VersionedCipher, Violation,
+};
 
 mod error;
 pub use error::Error;
 
+mod shutdown;
+pub use shutdown::Shutdown;
+
+mod config;
+pub use config::{CaptureConfig, CoalesceConfig, Config, OutboundConfig, StreamConfig};
+
+pub mod admission;
+use admission::{Admission, AdmissionPermit, RejectReason};
+
+pub mod idle;
+
+pub mod protocol;
+
+pub mod proxy;
+
+mod metrics;
+
+pub mod rate_limit;
+use rate_limit::{RateLimitAction, RateLimiter};
+
+pub mod violation;
+use violation::{Strikes, ViolationAction};
+
+#[cfg(feature = "harness")]
+pub mod harness;
+
+/// How long the server waits for the connected clients to disconnect while
+/// shutting down.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long we wait for a rejected client to get its rejection message
+/// before closing the connection.
+pub const REJECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client has to finish the key exchange, for the protocol
+/// versions that have one.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many violations of a single connection could be waiting for
+/// [`TQServer::on_violation`], the ones over it are counted but not recorded.
+pub const MAX_PENDING_VIOLATIONS: usize = 32;
+
 #[async_trait]
 pub trait TQServer: Sized + Send + Sync {
-    type Cipher: Cipher;
+    type Cipher: VersionedCipher;
     type ActorState: ActorState;
     type PacketHandler: PacketHandler<ActorState = Self::ActorState>;
 
@@ -46,120 +99,684 @@ pub trait TQServer: Sized + Send + Sync {
         Ok(())
     }
 
+    /// Returns `true` if that actor finished logging in, used to pick its
+    /// idle timeout, see [`idle::IdleConfig`].
+    fn is_logged_in(actor: &Actor<Self::ActorState>) -> bool {
+        let _ = actor;
+        false
+    }
+
+    /// Get Called every [`idle::IdleConfig::keepalive`] for every
+    /// connection, a good chance to send the client something to make sure
+    /// the connection is still alive. Returing Error here will disconnect the
+    /// client.
+    #[tracing::instrument(skip(state, actor), fields(actor = actor.id()))]
+    async fn on_keepalive(
+        state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+    ) -> Result<(), Error> {
+        let _ = state;
+        let _ = actor;
+        Ok(())
+    }
+
+    /// Get Called when a new connection gets rejected by the admission
+    /// control, the actor here is only good for sending a few packets to the
+    /// client explaining why, the connection gets closed right after.
+    #[tracing::instrument(skip(state, actor), fields(actor = actor.id()))]
+    async fn on_rejected(
+        state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+        reason: RejectReason,
+    ) -> Result<(), Error> {
+        let _ = state;
+        let _ = actor;
+        let _ = reason;
+        Ok(())
+    }
+
+    /// Get Called for every protocol violation of a client, after counting
+    /// its strikes (`strikes` is the total so far), a good chance to record it
+    /// for later review, see [`violation`].
+    #[tracing::instrument(skip(state, actor), fields(actor = actor.id()))]
+    async fn on_violation(
+        state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+        addr: SocketAddr,
+        violation: Violation,
+        strikes: u32,
+    ) -> Result<(), Error> {
+        let _ = state;
+        let _ = actor;
+        let _ = addr;
+        let _ = violation;
+        let _ = strikes;
+        Ok(())
+    }
+
+    /// Get Called once the server starts shutting down, after it stopped
+    /// accepting new connections and before the connected clients get
+    /// disconnected. A good chance to warn the connected clients.
+    #[tracing::instrument(skip(state))]
+    async fn on_shutdown(state: &<Self::PacketHandler as PacketHandler>::State) -> Result<(), Error> {
+        let _ = state;
+        Ok(())
+    }
+
     /// Runs the server and listen on the configured Address for new
     /// Connections.
-    #[tracing::instrument(skip(state))]
-    async fn run<A>(addr: A, state: &'static <Self::PacketHandler as PacketHandler>::State) -> Result<(), Error>
+    ///
+    /// Once a Ctrl-C is received, the server stops accepting new connections,
+    /// calls [`TQServer::on_shutdown`], then disconnects every connected
+    /// client (running [`TQServer::on_disconnected`] for each of them) and
+    /// waits for them to finish, up to [`SHUTDOWN_TIMEOUT`]. The connections
+    /// still open after that get aborted, so none of them uses `state` once
+    /// this returns.
+    #[tracing::instrument(skip(config, state))]
+    async fn run<A>(
+        addr: A,
+        config: Config,
+        state: &'static <Self::PacketHandler as PacketHandler>::State,
+    ) -> Result<(), Error>
     where
         A: Debug + ToSocketAddrs + Send + Sync,
     {
+        if config.protocols.is_empty() {
+            return Err(Error::Internal("The server has no protocol to speak".into()));
+        }
+        if let Some(protocol) = config.protocols.iter().find(|p| !Self::Cipher::supports(**p)) {
+            let e = format!("The server cipher does not support protocol {protocol}");
+            return Err(Error::Internal(e.into()));
+        }
+        if let Err(e) = config.rate_limit.validate() {
+            return Err(Error::Internal(e.into()));
+        }
         let listener = TcpListener::bind(addr).await?;
-        let main_loop_task = Builder::new().name("Server Main Loop").spawn(async {
-            let mut incoming = TcpListenerStream::new(listener);
+        let capture = match &config.capture {
+            Some(c) => {
+                tracing::info!(path = %c.path.display(), record_all = c.record_all, "Capturing packets");
+                Some(CaptureFile::create(&c.path)?)
+            },
+            None => None,
+        };
+        let outbound_policy = Arc::new(config.outbound.policy.clone());
+        let config = Arc::new(config);
+        // Used to tell every connection task that the server is going down.
+        let (notify_shutdown, _) = broadcast::channel::<()>(1);
+        // Stops the main loop from accepting new connections.
+        let (stop_accepting, mut accepting_stopped) = oneshot::channel::<()>();
+        let main_loop_notify = notify_shutdown.clone();
+        let mut main_loop_task = Builder::new().name("Server Main Loop").spawn(async move {
+            // Every connection task, even the rejected ones, so shutdown
+            // could wait for them, they all borrow the state.
+            let mut connections = JoinSet::new();
+            let admission = Admission::new(&config.admission);
+            let backoff = config.admission.accept_backoff;
+            // No rejection message is worth a key exchange.
+            let key_exchange = config.protocols.iter().all(|p| p.has_key_exchange());
+            let mut accept_delay = Duration::ZERO;
             tracing::trace!("Starting Server main loop");
             tracing::info!("Server is Ready for New Connections.");
-            while let Some(stream) = incoming.next().await {
-                let stream = match stream {
-                    Ok(s) => {
-                        tracing::debug!("Got Connection from {}", s.peer_addr()?);
-                        s.set_nodelay(true)?;
-                        s.set_linger(None)?;
-                        s.set_ttl(5)?;
-                        s
+            let result: Result<(), Error> = loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    // Forget about the connections that are done.
+                    Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+                    _ = &mut accepting_stopped => break Ok(()),
+                };
+                let (stream, addr) = match accepted {
+                    Ok((s, addr)) => {
+                        accept_delay = Duration::ZERO;
+                        tracing::debug!("Got Connection from {addr}");
+                        if let Err(e) = set_socket_options(&s, &config.stream) {
+                            // Only this socket is broken, not the listener.
+                            tracing::warn!(%addr, error = ?e, "Could not set socket options, dropping it.");
+                            continue;
+                        }
+                        (s, addr)
                     },
                     Err(e) => {
+                        // Most of the accept errors are resource exhaustion
+                        // (like EMFILE), retrying right away would only spin.
+                        accept_delay = backoff.next(accept_delay);
                         tracing::error!(
                             error = ?e,
+                            retry_in = ?accept_delay,
                             "Error while accepting new connection, dropping it."
                         );
+                        tokio::time::sleep(accept_delay).await;
                         continue;
                     },
                 };
-                Builder::new().name("TCP Stream").spawn(async {
-                    tracing::trace!("Calling on_connected lifetime hook");
-                    Self::on_connected(state, stream.peer_addr()?).await?;
-                    let (tx, rx) = mpsc::channel(1024);
-                    let actor = Actor::<Self::ActorState>::new(tx);
-                    match handle_stream::<Self>(stream, state, &actor, rx).await {
+                let shutdown = Shutdown::new(main_loop_notify.subscribe());
+                let admission = admission.clone();
+                let config = config.clone();
+                let outbound_policy = outbound_policy.clone();
+                let record_all = config.capture.as_ref().is_some_and(|c| c.record_all);
+                let recorder = capture.as_ref().map(|c| c.recorder(record_all));
+                // Reading the PROXY header could take a while, so everything
+                // from here runs in the connection task.
+                let spawned = connections.build_task().name("TCP Stream").spawn(async move {
+                    let mut stream = stream;
+                    let addr = match proxy::resolve(&mut stream, addr, config.proxy.as_ref()).await {
+                        Ok(real) => {
+                            if real != addr {
+                                tracing::debug!(proxy = %addr, addr = %real, "Connection is proxied");
+                            }
+                            real
+                        },
                         Err(e) => {
-                            tracing::error!("{e}");
+                            tracing::warn!(proxy = %addr, error = %e, "Dropping proxied connection.");
+                            metrics::connections_rejected("bad_proxy_header").inc();
+                            return Ok(());
                         },
-                        Ok(_) => {
-                            tracing::debug!("Client Disconnected.");
+                    };
+                    let permit = match admission.admit(addr.ip()) {
+                        Ok(permit) => permit,
+                        Err(reason) => {
+                            tracing::warn!(%addr, %reason, "Rejecting connection.");
+                            metrics::connections_rejected(reason.name()).inc();
+                            let Some(_rejecting) = admission.try_reject().filter(|_| !key_exchange) else {
+                                tracing::debug!(%addr, "Closing the rejected connection right away.");
+                                return Ok(());
+                            };
+                            return reject_stream::<Self, _>(stream, &config.protocols, state, reason).await;
                         },
-                    }
-                    tracing::trace!("Calling on_disconnected lifetime hook");
-                    Self::on_disconnected(state, actor).await?;
-                    tracing::debug!("Task Ended.");
-                    Result::<_, Error>::Ok(())
-                })?;
-            }
-            Result::<_, Error>::Ok(())
+                    };
+                    metrics::connections_accepted().inc();
+                    let conn = Connection {
+                        addr,
+                        permit,
+                        recorder,
+                        shutdown,
+                    };
+                    serve_stream::<Self, _>(stream, conn, &config, outbound_policy, state).await
+                });
+                if let Err(e) = spawned {
+                    break Err(e.into());
+                }
+            };
+            (connections, result)
         })?;
         let ctrl_c = tokio::signal::ctrl_c();
-        tokio::select! {
-            _ = ctrl_c => {
-                tracing::debug!("Ctrl-C received, shutting down.");
-            },
-            _ = main_loop_task => {
+        let ended = tokio::select! {
+            _ = ctrl_c => None,
+            main_loop = &mut main_loop_task => Some(main_loop),
+        };
+        let main_loop = match ended {
+            Some(main_loop) => {
                 tracing::debug!("Main Loop Task Ended, shutting down.");
+                main_loop
+            },
+            None => {
+                tracing::debug!("Ctrl-C received, shutting down.");
+                let _ = stop_accepting.send(());
+                main_loop_task.await
             },
         };
         tracing::debug!("Server is shutting down.");
+        // The main loop does not accept new connections anymore.
+        let (mut connections, result) = main_loop.map_err(|e| Error::Internal(e.into()))?;
+        if let Err(e) = result {
+            tracing::error!(error = ?e, "Server main loop failed");
+        }
+        tracing::trace!("Calling on_shutdown lifetime hook");
+        if let Err(e) = Self::on_shutdown(state).await {
+            tracing::error!(error = ?e, "Error while running on_shutdown hook");
+        }
+        // Tell every connected client to disconnect, it is fine if there is
+        // no one listening.
+        let _ = notify_shutdown.send(());
+        tracing::debug!(connections = connections.len(), "Waiting for all connections to close.");
+        let all_closed = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if all_closed.is_err() {
+            tracing::warn!(
+                timeout = ?SHUTDOWN_TIMEOUT,
+                connections = connections.len(),
+                "Timed out while waiting for connections to close, some clients may not be saved."
+            );
+            // The state could be gone once we return, nothing may use it
+            // after that.
+            connections.shutdown().await;
+        }
         Ok(())
     }
 }
 
+/// Applies the [`StreamConfig`] to a newly accepted socket.
+fn set_socket_options(stream: &TcpStream, config: &StreamConfig) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_linger(None)?;
+    if let Some(ttl) = config.ttl {
+        stream.set_ttl(ttl)?;
+    }
+    Ok(())
+}
+
+/// An admitted connection, what [`serve_stream`] needs to know about it
+/// besides the stream itself.
+pub(crate) struct Connection {
+    /// The client address, the real one if it is behind a trusted proxy.
+    pub addr: SocketAddr,
+    /// Keeps the admission slot as long as the connection is open.
+    pub permit: AdmissionPermit,
+    pub recorder: Option<Recorder>,
+    pub shutdown: Shutdown,
+}
+
+/// Serves a single connection over any transport, from
+/// [`TQServer::on_connected`] to [`TQServer::on_disconnected`].
+async fn serve_stream<S, T>(
+    stream: T,
+    mut conn: Connection,
+    config: &Config,
+    outbound_policy: Arc<OutboundPolicy>,
+    state: &<S::PacketHandler as PacketHandler>::State,
+) -> Result<(), Error>
+where
+    S: TQServer,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (stream, protocol) = match protocol::detect(stream, &config.protocols).await {
+        Ok(detected) => detected,
+        Err(e) => {
+            tracing::debug!(error = %e, "Could not detect the client protocol.");
+            return Ok(());
+        },
+    };
+    tracing::trace!("Calling on_connected lifetime hook");
+    S::on_connected(state, conn.addr).await?;
+    let (tx, rx) = mpsc::channel(config.outbound.queue_size);
+    let (violations_tx, violations) = mpsc::unbounded_channel();
+    let actor = Actor::<S::ActorState>::with_policy(tx, outbound_policy)
+        .with_protocol(protocol)
+        .with_addr(conn.addr)
+        .with_violations(violations_tx);
+    match handle_stream::<S, _>(stream, &mut conn, config, state, &actor, rx, violations).await {
+        Err(e) => {
+            tracing::error!("{e}");
+        },
+        Ok(_) => {
+            tracing::debug!("Client Disconnected.");
+        },
+    }
+    actor.set_phase(Phase::Disconnecting);
+    tracing::trace!("Calling on_disconnected lifetime hook");
+    S::on_disconnected(state, actor).await?;
+    tracing::debug!("Task Ended.");
+    Ok(())
+}
+
+/// Sends the rejection packets (see [`TQServer::on_rejected`]) to the client
+/// then closes the connection.
+///
+/// The caller should hold one of the [`Admission::try_reject`] slots while
+/// this runs. The clients speaking a protocol with a key exchange are not
+/// worth it, they only get the connection closed.
+#[tracing::instrument(skip(stream, state))]
+async fn reject_stream<S, T>(
+    stream: T,
+    protocols: &[ProtocolVersion],
+    state: &<S::PacketHandler as PacketHandler>::State,
+    reason: RejectReason,
+) -> Result<(), Error>
+where
+    S: TQServer,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (stream, protocol) = protocol::detect(stream, protocols).await?;
+    if protocol.has_key_exchange() {
+        return Ok(());
+    }
+    let (codec, cipher) = open_codec::<S, _>(stream, protocol).await?;
+    let (encoder, _) = codec.split();
+    let (tx, rx) = mpsc::channel(16);
+    let actor = Actor::<S::ActorState>::new(tx).with_protocol(protocol);
+    let message_task = Builder::new().name("Rejection Message Handler").spawn(handle_msg(
+        rx,
+        encoder,
+        cipher,
+        CoalesceConfig::default(),
+    ))?;
+    if let Err(e) = S::on_rejected(state, &actor, reason).await {
+        tracing::error!(error = ?e, "Error while running on_rejected hook");
+    }
+    let _ = actor.shutdown().await;
+    if tokio::time::timeout(REJECTION_TIMEOUT, message_task).await.is_err() {
+        tracing::debug!("Timed out while sending the rejection to the client.");
+    }
+    Ok(())
+}
+
+/// Picks the cipher for `protocol` and runs the key exchange, if it has one,
+/// before the first frame.
+async fn open_codec<S, T>(mut stream: T, protocol: ProtocolVersion) -> Result<(TQCodec<T, S::Cipher>, S::Cipher), Error>
+where
+    S: TQServer,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let cipher = S::Cipher::for_version(protocol);
+    if let Some(blowfish) = cipher.key_exchange() {
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake::accept(&mut stream, blowfish))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "Key exchange timed out"))?
+            .map_err(std::io::Error::from)?;
+        tracing::trace!(%protocol, "Key exchange done");
+    }
+    let codec = TQCodec::new(stream, cipher.clone()).with_seals(protocol.client_seal(), protocol.server_seal());
+    Ok((codec, cipher))
+}
+
 #[tracing::instrument(skip_all, err)]
-async fn handle_stream<S: TQServer>(
-    stream: TcpStream,
+async fn handle_stream<S, T>(
+    stream: T,
+    conn: &mut Connection,
+    config: &Config,
     state: &<S::PacketHandler as PacketHandler>::State,
     actor: &Actor<S::ActorState>,
     rx: mpsc::Receiver<Message>,
-) -> Result<(), Error> {
-    let cipher = S::Cipher::default();
-    let (encoder, mut decoder) = TQCodec::new(stream, cipher.clone()).split();
+    mut violations: mpsc::UnboundedReceiver<Violation>,
+) -> Result<(), Error>
+where
+    S: TQServer,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (codec, cipher) = open_codec::<S, T>(stream, actor.protocol()).await?;
+    let codec = codec.with_max_frame_size(config.stream.max_frame_size);
+    let codec = match conn.recorder.take() {
+        Some(recorder) => codec.with_recorder(recorder),
+        None => codec,
+    };
+    let (encoder, mut decoder) = codec.split();
     // Start MsgHandler in a seprate task.
-    let message_task = Builder::new()
-        .name("Message Handler")
-        .spawn(handle_msg(rx, encoder, cipher))?;
+    let message_task =
+        Builder::new()
+            .name("Message Handler")
+            .spawn(handle_msg(rx, encoder, cipher, config.outbound.coalesce))?;
+    let mut rate_limiter = RateLimiter::new(&config.rate_limit);
+    let mut packet_metrics = metrics::PacketMetrics::new(S::PacketHandler::PACKETS);
+    let mut strikes = Strikes::new(&config.violations);
+    let mut records = ViolationRecords::<S>::new(state, actor, conn.addr);
+    let mut keepalive = config.idle.keepalive_interval();
+    let mut last_read = Instant::now();
+    // The packet over its budget and when to check it again, while the
+    // connection is throttled, nothing else is read until then.
+    let mut throttled: Option<((u16, Bytes), Instant)> = None;
 
-    while let Some(packet) = decoder.next().await {
-        let (id, bytes) = packet?;
-        if let Err(err) = S::PacketHandler::handle((id, bytes), state, actor).await {
+    'connection: loop {
+        let idle_deadline = config.idle.timeout(S::is_logged_in(actor)).map(|t| last_read + t);
+        let throttle_deadline = throttled.as_ref().map(|(_, until)| *until);
+        let (maybe_packet, retried) = tokio::select! {
+            packet = decoder.next(), if throttled.is_none() => (packet, false),
+            _ = records.next(), if records.is_recording() => continue,
+            _ = idle::sleep_until(throttle_deadline), if throttled.is_some() => {
+                let (packet, _) = throttled.take().expect("only polled while throttled");
+                (Some(Ok(packet)), true)
+            },
+            _ = actor.disconnected() => {
+                tracing::debug!(queue_depth = actor.queue_depth(), "Actor got disconnected.");
+                break;
+            },
+            _ = idle::sleep_until(idle_deadline) => {
+                tracing::debug!("Connection is idle for too long, disconnecting.");
+                break;
+            },
+            _ = idle::tick(keepalive.as_mut()) => {
+                if let Err(e) = S::on_keepalive(state, actor).await {
+                    tracing::debug!(error = ?e, "Failed to send keepalive, disconnecting.");
+                    break;
+                }
+                continue;
+            },
+            _ = conn.shutdown.recv() => {
+                tracing::debug!("Server is shutting down, closing the connection.");
+                // Let the message handler flush whatever is still queued
+                // before closing the socket.
+                records.flush().await;
+                let _ = actor.shutdown().await;
+                let _ = message_task.await;
+                return Ok(());
+            },
+        };
+        let Some(packet) = maybe_packet else {
+            break;
+        };
+        let (id, bytes) = match packet {
+            Ok(packet) => packet,
+            Err(e) => {
+                // The decoder is out of sync after that, so the client gets
+                // disconnected anyway, but it still counts.
+                if let Some(&FrameError::TooBig { len, packet_id }) =
+                    e.get_ref().and_then(|e| e.downcast_ref::<FrameError>())
+                {
+                    let violation = Violation::OversizedFrame { packet_id, len };
+                    strike(violation, &mut strikes, conn, &mut records);
+                }
+                records.flush().await;
+                return Err(e.into());
+            },
+        };
+        if !retried {
+            last_read = Instant::now();
+            packet_metrics.received(id).inc();
+        }
+        if let Err(wait) = rate_limiter.check(id, bytes.len()) {
+            match config.rate_limit.action {
+                RateLimitAction::Drop => {
+                    tracing::debug!(packet_id = %id, ?wait, "Rate limit exceeded, dropping packet.");
+                    continue;
+                },
+                RateLimitAction::Throttle => {
+                    if !retried {
+                        tracing::debug!(packet_id = %id, ?wait, "Rate limit exceeded, throttling.");
+                    }
+                    let Some(until) = Instant::now().checked_add(wait) else {
+                        tracing::warn!(packet_id = %id, "Rate limit never refills, disconnecting.");
+                        break;
+                    };
+                    throttled = Some(((id, bytes), until));
+                    continue;
+                },
+                RateLimitAction::Disconnect => {
+                    tracing::warn!(packet_id = %id, "Rate limit exceeded, disconnecting.");
+                    break;
+                },
+            }
+        }
+        let started = Instant::now();
+        let result = S::PacketHandler::handle((id, bytes), state, actor).await;
+        packet_metrics.handler_latency(id).observe_duration(started.elapsed());
+        // The actor id could change after handling a packet (on login).
+        if let Some(recorder) = decoder.recorder() {
+            recorder.set_actor_id(actor.id() as u32);
+        }
+        if let Err(err) = result {
             let result = actor.send(err).await;
             if let Err(e) = result {
                 tracing::error!(?e, "Got Error while sending error packet, stopping task.");
                 break;
             }
         }
+        while let Ok(violation) = violations.try_recv() {
+            if !strike(violation, &mut strikes, conn, &mut records) {
+                break 'connection;
+            }
+        }
     }
+    records.flush().await;
     message_task.abort();
     tracing::debug!("Socket Closed, stopping task.");
     Ok(())
 }
 
+/// Counts the strikes of a `violation` and queues it to be recorded, returns
+/// `false` if the client has to be disconnected for it.
+fn strike<'a, S: TQServer + 'a>(
+    violation: Violation,
+    strikes: &mut Strikes<'_>,
+    conn: &Connection,
+    records: &mut ViolationRecords<'a, S>,
+) -> bool {
+    metrics::violations(violation.kind().name()).inc();
+    let action = strikes.add(&violation);
+    records.push(violation, strikes.total());
+    match action {
+        None => true,
+        Some(ViolationAction::Disconnect) => {
+            tracing::warn!(%violation, strikes = strikes.total(), "Too many strikes, disconnecting.");
+            false
+        },
+        Some(ViolationAction::Ban(duration)) => {
+            tracing::warn!(%violation, strikes = strikes.total(), ?duration, "Too many strikes, banning.");
+            conn.permit.ban(duration);
+            false
+        },
+    }
+}
+
+type Recording<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+/// The violations of a connection waiting for [`TQServer::on_violation`].
+///
+/// They are recorded one at a time next to the read loop, so a slow record
+/// (like a database insert) does not hold the packets back, and there are
+/// never more than [`MAX_PENDING_VIOLATIONS`] of them waiting.
+struct ViolationRecords<'a, S: TQServer> {
+    state: &'a <S::PacketHandler as PacketHandler>::State,
+    actor: &'a Actor<S::ActorState>,
+    addr: SocketAddr,
+    pending: VecDeque<(Violation, u32)>,
+    recording: Option<Recording<'a>>,
+}
+
+impl<'a, S: TQServer + 'a> ViolationRecords<'a, S> {
+    fn new(
+        state: &'a <S::PacketHandler as PacketHandler>::State,
+        actor: &'a Actor<S::ActorState>,
+        addr: SocketAddr,
+    ) -> Self {
+        Self {
+            state,
+            actor,
+            addr,
+            pending: VecDeque::new(),
+            recording: None,
+        }
+    }
+
+    fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Queues `violation` with the `strikes` so far, or drops it if there are
+    /// too many waiting already.
+    fn push(&mut self, violation: Violation, strikes: u32) {
+        if self.pending.len() >= MAX_PENDING_VIOLATIONS {
+            tracing::debug!(%violation, "Too many violations waiting to be recorded, dropping it.");
+            metrics::violations_dropped().inc();
+            return;
+        }
+        self.pending.push_back((violation, strikes));
+        self.start_next();
+    }
+
+    fn start_next(&mut self) {
+        if self.recording.is_none() {
+            if let Some((violation, strikes)) = self.pending.pop_front() {
+                self.recording = Some(S::on_violation(self.state, self.actor, self.addr, violation, strikes));
+            }
+        }
+    }
+
+    /// Finishes the current record and starts the next one, never completes
+    /// if there is nothing to record.
+    async fn next(&mut self) {
+        let Some(recording) = self.recording.as_mut() else {
+            return std::future::pending().await;
+        };
+        if let Err(e) = recording.await {
+            tracing::error!(error = ?e, "Error while running on_violation hook");
+        }
+        self.recording = None;
+        self.start_next();
+    }
+
+    /// Records everything still waiting.
+    async fn flush(&mut self) {
+        while self.is_recording() {
+            self.next().await;
+        }
+    }
+}
+
 #[tracing::instrument(skip(rx, encoder, cipher))]
-async fn handle_msg<C: Cipher>(
-    rx: mpsc::Receiver<Message>,
-    mut encoder: TQEncoder<TcpStream, C>,
+async fn handle_msg<T, C>(
+    mut rx: mpsc::Receiver<Message>,
+    mut encoder: TQEncoder<T, C>,
     cipher: C,
-) -> Result<(), Error> {
+    coalesce: CoalesceConfig,
+) -> Result<(), Error>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Cipher,
+{
     use Message::*;
-    let mut rx_stream = ReceiverStream::new(rx);
-    while let Some(msg) = rx_stream.next().await {
-        match msg {
-            GenerateKeys(seed) => {
-                cipher.generate_keys(seed);
-            },
-            Packet(id, bytes) => {
-                encoder.send((id, bytes)).await?;
-            },
-            Shutdown => {
-                encoder.close().await?;
+    let queue_depth = metrics::outbound_queue_depth();
+    while let Some(msg) = rx.recv().await {
+        queue_depth.observe((rx.len() + 1) as f64);
+        // Everything already queued goes out in a single write.
+        let mut next = Some(msg);
+        let mut batched = 0;
+        let linger = Instant::now() + coalesce.linger;
+        while let Some(msg) = next.take() {
+            match msg {
+                GenerateKeys(seed) => {
+                    // The packets before this one are already encrypted
+                    // with the old keys.
+                    cipher.generate_keys(seed);
+                },
+                Packet(id, bytes) => {
+                    metrics::packets_sent(id).inc();
+                    encoder.feed((id, bytes))?;
+                    batched += 1;
+                },
+                Capture(enabled) => match encoder.recorder() {
+                    Some(recorder) => {
+                        tracing::info!(session = recorder.session(), enabled, "Switching packet capture");
+                        recorder.set_enabled(enabled);
+                    },
+                    None => {
+                        tracing::warn!("Packet capture is not configured, ignoring.");
+                    },
+                },
+                Shutdown => {
+                    encoder.flush().await?;
+                    encoder.close().await?;
+                    return Ok(());
+                },
+            };
+            if batched >= coalesce.max_packets || encoder.buffered() >= coalesce.max_bytes {
                 break;
-            },
-        };
+            }
+            next = match rx.try_recv() {
+                Ok(msg) => Some(msg),
+                Err(_) if batched > 0 && !coalesce.linger.is_zero() => {
+                    tokio::time::timeout_at(linger, rx.recv()).await.ok().flatten()
+                },
+                Err(_) => None,
+            };
+        }
+        if batched > 1 {
+            tracing::trace!(
+                packets = batched,
+                bytes = encoder.buffered(),
+                "Writing coalesced packets"
+            );
+        }
+        encoder.flush().await?;
     }
     tracing::debug!("Socket Closed, stopping handle message.");
     encoder.close().await?;
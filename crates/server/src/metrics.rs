@@ -0,0 +1,134 @@
+//! The connection and packet metrics every server reports, see
+//! [`tq_metrics`].
+
+use std::collections::HashMap;
+use tq_metrics::{Counter, Histogram, LATENCY_BUCKETS, SIZE_BUCKETS};
+use tq_network::PacketInfo;
+
+/// The label of the packets a server does not know about, so a client
+/// could not add series by sending made up packet ids.
+const UNKNOWN_PACKET: &str = "unknown";
+
+pub(crate) fn connections_accepted() -> Counter {
+    tq_metrics::counter(
+        "coemu_connections_accepted_total",
+        "Connections accepted by the server.",
+        &[],
+    )
+}
+
+pub(crate) fn connections_rejected(reason: &str) -> Counter {
+    tq_metrics::counter(
+        "coemu_connections_rejected_total",
+        "Connections rejected by the admission control.",
+        &[("reason", reason)],
+    )
+}
+
+pub(crate) fn violations(kind: &str) -> Counter {
+    tq_metrics::counter(
+        "coemu_protocol_violations_total",
+        "Protocol violations committed by the clients.",
+        &[("kind", kind)],
+    )
+}
+
+pub(crate) fn violations_dropped() -> Counter {
+    tq_metrics::counter(
+        "coemu_protocol_violations_dropped_total",
+        "Protocol violations not recorded, too many were waiting already.",
+        &[],
+    )
+}
+
+pub(crate) fn packets_received(packet_id: &str) -> Counter {
+    tq_metrics::counter(
+        "coemu_packets_received_total",
+        "Packets received from the clients.",
+        &[("packet_id", packet_id)],
+    )
+}
+
+pub(crate) fn packets_sent(packet_id: u16) -> Counter {
+    tq_metrics::counter(
+        "coemu_packets_sent_total",
+        "Packets sent to the clients.",
+        &[("packet_id", &packet_id.to_string())],
+    )
+}
+
+pub(crate) fn handler_latency(packet_id: &str) -> Histogram {
+    tq_metrics::histogram(
+        "coemu_packet_handler_seconds",
+        "Time spent in the packet handler.",
+        LATENCY_BUCKETS,
+        &[("packet_id", packet_id)],
+    )
+}
+
+/// The metrics of the packets a connection receives, looked up once per
+/// packet id. Only the packets of `known` get their own series, the rest
+/// share the [`UNKNOWN_PACKET`] one.
+pub(crate) struct PacketMetrics {
+    known: &'static [PacketInfo],
+    /// Keyed by the packet id, `None` for the unknown ones.
+    cache: HashMap<Option<u16>, (Counter, Histogram)>,
+}
+
+impl PacketMetrics {
+    pub fn new(known: &'static [PacketInfo]) -> Self {
+        Self {
+            known,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, packet_id: u16) -> &(Counter, Histogram) {
+        let key = self.known.iter().any(|p| p.id == packet_id).then_some(packet_id);
+        self.cache.entry(key).or_insert_with(|| {
+            let label = key.map_or_else(|| UNKNOWN_PACKET.to_owned(), |id| id.to_string());
+            (packets_received(&label), handler_latency(&label))
+        })
+    }
+
+    pub fn received(&mut self, packet_id: u16) -> &Counter {
+        &self.get(packet_id).0
+    }
+
+    pub fn handler_latency(&mut self, packet_id: u16) -> &Histogram {
+        &self.get(packet_id).1
+    }
+}
+
+pub(crate) fn outbound_queue_depth() -> Histogram {
+    tq_metrics::histogram(
+        "coemu_outbound_queue_depth",
+        "Messages waiting in the outbound queue, every time it gets drained.",
+        SIZE_BUCKETS,
+        &[],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_packets_share_a_series() {
+        const KNOWN: &[PacketInfo] = &[PacketInfo {
+            name: "MsgKnown",
+            id: 64_001,
+            fields: &[],
+        }];
+        let mut metrics = PacketMetrics::new(KNOWN);
+        let unknown = packets_received(UNKNOWN_PACKET).get();
+        let known = packets_received("64001").get();
+        metrics.received(64_001).inc();
+        for id in 1..=100 {
+            metrics.received(id).inc();
+        }
+        assert_eq!(packets_received("64001").get(), known + 1);
+        assert_eq!(packets_received(UNKNOWN_PACKET).get(), unknown + 100);
+        assert_eq!(metrics.cache.len(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+//! Picks the [`ProtocolVersion`] of every new connection, for the servers
+//! speaking more than one (see [`Config::protocols`](crate::Config)).
+//!
+//! The patches without a key exchange start with the client sending its
+//! first packet right away, while the ones with a key exchange wait for the
+//! server to start it. So we give the client [`DETECT_TIMEOUT`] to talk first:
+//! if it does, it speaks the newest version without a key exchange, otherwise
+//! the newest one with it. Whatever the client sent is read again by the
+//! codec, see [`Rewind`].
+
+use bytes::{Buf, Bytes};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tq_network::ProtocolVersion;
+
+/// How long a client has to send something before we start the key
+/// exchange, when both kinds of versions are served.
+pub const DETECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Detects the version `stream` speaks out of `protocols`.
+///
+/// Only waits for the client if `protocols` has versions with and without a
+/// key exchange, otherwise there is nothing to tell apart.
+pub async fn detect<T>(mut stream: T, protocols: &[ProtocolVersion]) -> io::Result<(Rewind<T>, ProtocolVersion)>
+where
+    T: AsyncRead + Unpin,
+{
+    let talks_first = protocols.iter().copied().filter(|p| !p.has_key_exchange()).max();
+    let waits = protocols.iter().copied().filter(|p| p.has_key_exchange()).max();
+    let (talks_first, waits) = match (talks_first, waits) {
+        (Some(talks_first), Some(waits)) => (talks_first, waits),
+        (Some(protocol), None) | (None, Some(protocol)) => return Ok((Rewind::new(stream), protocol)),
+        (None, None) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "No protocol to detect")),
+    };
+    let mut buf = [0u8; 64];
+    match tokio::time::timeout(DETECT_TIMEOUT, stream.read(&mut buf)).await {
+        Ok(Ok(0)) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(Ok(n)) => Ok((
+            Rewind::with_prefix(stream, Bytes::copy_from_slice(&buf[..n])),
+            talks_first,
+        )),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok((Rewind::new(stream), waits)),
+    }
+}
+
+/// A stream that reads `prefix` before anything else, the bytes read while
+/// detecting the protocol.
+#[derive(Debug)]
+pub struct Rewind<T> {
+    prefix: Bytes,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub fn new(inner: T) -> Self {
+        Self::with_prefix(inner, Bytes::new())
+    }
+
+    pub fn with_prefix(inner: T, prefix: Bytes) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Rewind<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.prefix.has_remaining() {
+            let n = self.prefix.len().min(buf.remaining());
+            buf.put_slice(&self.prefix[..n]);
+            self.prefix.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Rewind<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    const BOTH: [ProtocolVersion; 2] = [ProtocolVersion::V5017, ProtocolVersion::V5018];
+
+    #[tokio::test]
+    async fn clients_talking_first_skip_the_key_exchange() {
+        let (mut client, server) = tokio::io::duplex(64);
+        client.write_all(b"hello").await.unwrap();
+        let (mut stream, protocol) = detect(server, &BOTH).await.unwrap();
+        assert_eq!(protocol, ProtocolVersion::V5017);
+        client.write_all(b" world").await.unwrap();
+        drop(client);
+        let mut read = Vec::new();
+        stream.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn silent_clients_get_the_key_exchange() {
+        let (_client, server) = tokio::io::duplex(64);
+        let (_, protocol) = detect(server, &BOTH).await.unwrap();
+        assert_eq!(protocol, ProtocolVersion::V5018);
+        // Nothing to wait for with a single version.
+        let (_client, server) = tokio::io::duplex(64);
+        let started = std::time::Instant::now();
+        let (_, protocol) = detect(server, &[ProtocolVersion::V5017]).await.unwrap();
+        assert_eq!(protocol, ProtocolVersion::V5017);
+        assert!(started.elapsed() < DETECT_TIMEOUT);
+    }
+}
@@ -0,0 +1,364 @@
+//! HAProxy's PROXY protocol, [v1 and v2][1], for servers running behind a TCP
+//! load balancer.
+//!
+//! The balancer sends a header with the real client address right after it
+//! connects, before anything the client sent. We only read it from the
+//! trusted proxies in [`ProxyConfig`], anyone else is served directly with
+//! the address we see.
+//!
+//! [1]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Starts every v2 header.
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+/// The longest v1 header, `\r\n` included.
+const V1_MAX_LEN: usize = 107;
+
+/// PROXY protocol configuration of a listener.
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Only these proxies could tell us the client address.
+    pub trusted: Vec<Cidr>,
+    /// How long a trusted proxy has to send the header.
+    pub header_timeout: Duration,
+}
+
+impl ProxyConfig {
+    /// Trusts the proxies in `trusted`.
+    pub fn new(trusted: Vec<Cidr>) -> Self {
+        Self {
+            trusted,
+            header_timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// Parses a comma separated list of trusted proxies, like
+/// `10.0.0.0/8,192.168.1.10`.
+impl FromStr for ProxyConfig {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trusted = s
+            .split(',')
+            .filter(|c| !c.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(trusted))
+    }
+}
+
+/// A range of IP addresses, like `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, ProxyError> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(ProxyError::InvalidCidr);
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // An IPv4 client could show up as an IPv4-mapped IPv6 address.
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Parses `addr/prefix`, a plain address is a range of one.
+impl FromStr for Cidr {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ProxyError::InvalidCidr)?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ProxyError::InvalidCidr)?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl core::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    IO(io::Error),
+    /// Not a PROXY protocol header.
+    InvalidHeader,
+    /// The proxy did not send the header in time.
+    TimedOut,
+    /// Not a valid CIDR.
+    InvalidCidr,
+}
+
+impl core::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "IO Error: {}", e),
+            Self::InvalidHeader => write!(f, "Invalid PROXY header"),
+            Self::TimedOut => write!(f, "Timed out waiting for the PROXY header"),
+            Self::InvalidCidr => write!(f, "Invalid CIDR"),
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(e: io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+/// The address the connection is really from, reading the PROXY header if
+/// `peer` is a trusted proxy.
+pub(crate) async fn resolve<S>(
+    stream: &mut S,
+    peer: SocketAddr,
+    config: Option<&ProxyConfig>,
+) -> Result<SocketAddr, ProxyError>
+where
+    S: AsyncRead + Unpin,
+{
+    let Some(config) = config.filter(|c| c.trusts(peer.ip())) else {
+        return Ok(peer);
+    };
+    let source = tokio::time::timeout(config.header_timeout, read_header(stream))
+        .await
+        .map_err(|_| ProxyError::TimedOut)??;
+    // Health checks from the proxy itself have no source.
+    Ok(source.unwrap_or(peer))
+}
+
+/// Reads a v1 or a v2 header, and nothing after it, returning the client
+/// address if the proxy sent one.
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>, ProxyError>
+where
+    S: AsyncRead + Unpin,
+{
+    // The shortest v1 header (`PROXY UNKNOWN\r\n`) is longer than that.
+    let mut header = vec![0u8; V2_SIGNATURE.len()];
+    stream.read_exact(&mut header).await?;
+    if header == V2_SIGNATURE {
+        let mut rest = [0u8; 4];
+        stream.read_exact(&mut rest).await?;
+        header.extend_from_slice(&rest);
+        let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let start = header.len();
+        header.resize(start + len, 0);
+        stream.read_exact(&mut header[start..]).await?;
+        parse_v2(&header)
+    } else if header.starts_with(b"PROXY ") {
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= V1_MAX_LEN {
+                return Err(ProxyError::InvalidHeader);
+            }
+            header.push(stream.read_u8().await?);
+        }
+        parse_v1(&header)
+    } else {
+        Err(ProxyError::InvalidHeader)
+    }
+}
+
+/// Parses a v1 (text) header, like `PROXY TCP4 10.0.0.1 10.0.0.2 5816
+/// 9958\r\n`.
+pub fn parse_v1(header: &[u8]) -> Result<Option<SocketAddr>, ProxyError> {
+    let line = header.strip_suffix(b"\r\n").ok_or(ProxyError::InvalidHeader)?;
+    let line = std::str::from_utf8(line).map_err(|_| ProxyError::InvalidHeader)?;
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(ProxyError::InvalidHeader);
+    }
+    let v4 = match parts.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(ProxyError::InvalidHeader),
+    };
+    let mut next = || parts.next().ok_or(ProxyError::InvalidHeader);
+    let src: IpAddr = next()?.parse().map_err(|_| ProxyError::InvalidHeader)?;
+    let dst: IpAddr = next()?.parse().map_err(|_| ProxyError::InvalidHeader)?;
+    let src_port: u16 = next()?.parse().map_err(|_| ProxyError::InvalidHeader)?;
+    let _dst_port: u16 = next()?.parse().map_err(|_| ProxyError::InvalidHeader)?;
+    if src.is_ipv4() != v4 || dst.is_ipv4() != v4 || parts.next().is_some() {
+        return Err(ProxyError::InvalidHeader);
+    }
+    Ok(Some(SocketAddr::new(src, src_port)))
+}
+
+/// Parses a v2 (binary) header, the signature included.
+pub fn parse_v2(header: &[u8]) -> Result<Option<SocketAddr>, ProxyError> {
+    if header.len() < 16 || header[..12] != V2_SIGNATURE {
+        return Err(ProxyError::InvalidHeader);
+    }
+    let (version, command) = (header[12] >> 4, header[12] & 0x0F);
+    let family = header[13];
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let addrs = header.get(16..16 + len).ok_or(ProxyError::InvalidHeader)?;
+    if version != 2 {
+        return Err(ProxyError::InvalidHeader);
+    }
+    match command {
+        // LOCAL, the proxy talking for itself.
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {},
+        _ => return Err(ProxyError::InvalidHeader),
+    }
+    // Anything after the addresses is TLVs, which we do not need.
+    match family {
+        // TCP over IPv4
+        0x11 => {
+            let addrs: &[u8; 12] = addrs
+                .get(..12)
+                .and_then(|a| a.try_into().ok())
+                .ok_or(ProxyError::InvalidHeader)?;
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // TCP over IPv6
+        0x21 => {
+            let addrs: &[u8; 36] = addrs
+                .get(..36)
+                .and_then(|a| a.try_into().ok())
+                .ok_or(ProxyError::InvalidHeader)?;
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addrs[..16]);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        },
+        // UNSPEC, UDP or unix sockets, nothing we could use.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn v2(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        header.extend_from_slice(addrs);
+        header
+    }
+
+    #[test]
+    fn v1_headers() {
+        let addr = parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 9958\r\n").unwrap();
+        assert_eq!(addr, Some("192.168.0.1:56324".parse().unwrap()));
+        let addr = parse_v1(b"PROXY TCP6 ::1 ::2 56324 5816\r\n").unwrap();
+        assert_eq!(addr, Some("[::1]:56324".parse().unwrap()));
+        assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_v1(b"PROXY TCP4 ::1 ::2 1 2\r\n").is_err());
+        assert!(parse_v1(b"PROXY TCP4 10.0.0.1 10.0.0.2 1\r\n").is_err());
+        assert!(parse_v1(b"PROXY TCP4 10.0.0.1 10.0.0.2 1 2").is_err());
+    }
+
+    #[test]
+    fn v2_headers() {
+        let addrs = [10, 0, 0, 1, 10, 0, 0, 2, 0x16, 0xB8, 0x26, 0xE6];
+        let addr = parse_v2(&v2(1, 0x11, &addrs)).unwrap();
+        assert_eq!(addr, Some("10.0.0.1:5816".parse().unwrap()));
+        // With a TLV after the addresses.
+        let mut with_tlv = addrs.to_vec();
+        with_tlv.extend_from_slice(&[0x04, 0x00, 0x01, 0xFF]);
+        assert_eq!(parse_v2(&v2(1, 0x11, &with_tlv)).unwrap(), addr);
+        let mut addrs = [0u8; 36];
+        addrs[15] = 1;
+        addrs[32..34].copy_from_slice(&5816u16.to_be_bytes());
+        let addr = parse_v2(&v2(1, 0x21, &addrs)).unwrap();
+        assert_eq!(addr, Some("[::1]:5816".parse().unwrap()));
+        assert_eq!(parse_v2(&v2(0, 0x00, &[])).unwrap(), None);
+        assert!(parse_v2(&v2(1, 0x11, &[10, 0, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn cidrs() {
+        let lan: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(lan.contains([10, 1, 2, 3].into()));
+        assert!(!lan.contains([11, 0, 0, 1].into()));
+        assert!(lan.contains("::ffff:10.0.0.1".parse().unwrap()));
+        let one: Cidr = "192.168.1.10".parse().unwrap();
+        assert!(one.contains([192, 168, 1, 10].into()));
+        assert!(!one.contains([192, 168, 1, 11].into()));
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains([1, 2, 3, 4].into()));
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains([10, 0, 0, 1].into()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("proxy".parse::<Cidr>().is_err());
+        let config: ProxyConfig = "10.0.0.0/8, fd00::/8".parse().unwrap();
+        assert!(config.trusts([10, 0, 0, 1].into()));
+        assert!(!config.trusts([127, 0, 0, 1].into()));
+    }
+
+    #[tokio::test]
+    async fn only_trusted_proxies_are_read() {
+        let config = ProxyConfig::new(vec!["127.0.0.1".parse().unwrap()]);
+        let proxy: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 5816 9958\r\nhello")
+            .await
+            .unwrap();
+        let addr = resolve(&mut server, proxy, Some(&config)).await.unwrap();
+        assert_eq!(addr, "203.0.113.7:5816".parse().unwrap());
+        // Nothing after the header got read.
+        let mut rest = [0u8; 5];
+        server.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"hello");
+
+        let direct: SocketAddr = "198.51.100.1:40000".parse().unwrap();
+        let addr = resolve(&mut server, direct, Some(&config)).await.unwrap();
+        assert_eq!(addr, direct);
+        let addr = resolve(&mut server, proxy, None).await.unwrap();
+        assert_eq!(addr, proxy);
+    }
+}
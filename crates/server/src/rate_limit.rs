@@ -0,0 +1,233 @@
+//! Per-connection packet rate limiting.
+//!
+//! Every connection gets its own [`RateLimiter`], which is a set of token
+//! buckets: one for the number of packets, one for the number of bytes and
+//! one for every packet id that has its own budget. A packet is only allowed
+//! if *all* the buckets it touches have enough tokens, otherwise nothing is
+//! consumed and the limiter tells us how long we should wait before trying
+//! again.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What to do with a packet that goes over its budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitAction {
+    /// Drop the packet silently and keep the connection.
+    #[default]
+    Drop,
+    /// Wait until the budget refills before handling the packet, this stops
+    /// reading from the socket and slows down the client.
+    Throttle,
+    /// Disconnect the client.
+    Disconnect,
+}
+
+/// A token bucket budget, `rate` tokens are added every second, and up to
+/// `burst` tokens could be saved for later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// How many tokens are refilled every second.
+    pub rate: u32,
+    /// The maximum number of tokens that could be saved.
+    pub burst: u32,
+}
+
+impl Budget {
+    pub const fn new(rate: u32, burst: u32) -> Self {
+        Self { rate, burst }
+    }
+}
+
+/// Rate limits applied to every connection.
+///
+/// By default, nothing is limited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    /// Number of packets per second for the whole connection.
+    pub packets: Option<Budget>,
+    /// Number of bytes per second for the whole connection.
+    pub bytes: Option<Budget>,
+    /// Number of packets per second for a specific packet id, this is checked
+    /// on top of the connection budgets.
+    pub per_packet: HashMap<u16, Budget>,
+    /// What to do when a client goes over its budget.
+    pub action: RateLimitAction,
+}
+
+impl RateLimitConfig {
+    /// Returns `true` if there is at least one budget configured.
+    pub fn is_enabled(&self) -> bool {
+        self.packets.is_some() || self.bytes.is_some() || !self.per_packet.is_empty()
+    }
+
+    /// Checks that every configured budget refills, a client over a budget
+    /// with a `rate` of zero would never get its packets through, and with
+    /// [`RateLimitAction::Throttle`] it would wait forever.
+    pub fn validate(&self) -> Result<(), String> {
+        let budgets = [("packets", self.packets.as_ref()), ("bytes", self.bytes.as_ref())];
+        for (name, budget) in budgets {
+            if budget.is_some_and(|b| b.rate == 0) {
+                return Err(format!("The {name} rate limit budget has a rate of 0"));
+            }
+        }
+        if let Some(id) = self.per_packet.iter().find(|(_, b)| b.rate == 0).map(|(id, _)| id) {
+            return Err(format!("The rate limit budget of packet #{id} has a rate of 0"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(budget: Budget, now: Instant) -> Self {
+        let capacity = budget.burst.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            rate: budget.rate as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// How long to wait until there is `cost` tokens in the bucket.
+    fn wait_for(&self, cost: f64) -> Duration {
+        // A single request bigger than the whole bucket would never pass, so
+        // we only ask for a full bucket in that case.
+        let cost = cost.min(self.capacity);
+        if self.tokens >= cost {
+            Duration::ZERO
+        } else if self.rate <= 0.0 {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64((cost - self.tokens) / self.rate)
+        }
+    }
+
+    fn consume(&mut self, cost: f64) {
+        self.tokens = (self.tokens - cost.min(self.capacity)).max(0.0);
+    }
+}
+
+/// Per connection rate limiter, built from a [`RateLimitConfig`].
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    packets: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    per_packet: HashMap<u16, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self::new_at(config, Instant::now())
+    }
+
+    fn new_at(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            packets: config.packets.map(|b| TokenBucket::new(b, now)),
+            bytes: config.bytes.map(|b| TokenBucket::new(b, now)),
+            per_packet: config
+                .per_packet
+                .iter()
+                .map(|(id, b)| (*id, TokenBucket::new(*b, now)))
+                .collect(),
+        }
+    }
+
+    /// Checks a packet against all of its budgets.
+    ///
+    /// Returns `Ok(())` and consumes the tokens if the packet is allowed,
+    /// otherwise nothing is consumed and we return how long to wait before
+    /// the packet would be allowed.
+    pub fn check(&mut self, packet_id: u16, len: usize) -> Result<(), Duration> {
+        self.check_at(Instant::now(), packet_id, len)
+    }
+
+    fn check_at(&mut self, now: Instant, packet_id: u16, len: usize) -> Result<(), Duration> {
+        let mut buckets = [
+            self.packets.as_mut().map(|b| (b, 1.0)),
+            self.bytes.as_mut().map(|b| (b, len as f64)),
+            self.per_packet.get_mut(&packet_id).map(|b| (b, 1.0)),
+        ];
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in buckets.iter_mut().flatten() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(*cost));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (bucket, cost) in buckets.iter_mut().flatten() {
+            bucket.consume(*cost);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_packet_budget() {
+        let now = Instant::now();
+        let config = RateLimitConfig {
+            per_packet: HashMap::from([(1005, Budget::new(2, 2))]),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new_at(&config, now);
+        assert_eq!(limiter.check_at(now, 1005, 8), Ok(()));
+        assert_eq!(limiter.check_at(now, 1005, 8), Ok(()));
+        // out of budget for that packet, but others are still fine.
+        assert_eq!(limiter.check_at(now, 1005, 8), Err(Duration::from_millis(500)));
+        assert_eq!(limiter.check_at(now, 1004, 8), Ok(()));
+        // half a second later, we got a new token.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check_at(later, 1005, 8), Ok(()));
+        assert!(limiter.check_at(later, 1005, 8).is_err());
+    }
+
+    #[test]
+    fn rejected_packets_consume_nothing() {
+        let now = Instant::now();
+        let config = RateLimitConfig {
+            packets: Some(Budget::new(10, 2)),
+            bytes: Some(Budget::new(100, 100)),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new_at(&config, now);
+        assert_eq!(limiter.check_at(now, 1, 80), Ok(()));
+        // not enough bytes left, so the packet budget should stay the same.
+        assert!(limiter.check_at(now, 1, 80).is_err());
+        assert_eq!(limiter.check_at(now, 1, 20), Ok(()));
+        assert!(limiter.check_at(now, 1, 0).is_err());
+    }
+
+    #[test]
+    fn budgets_have_to_refill() {
+        let mut config = RateLimitConfig {
+            packets: Some(Budget::new(10, 2)),
+            per_packet: HashMap::from([(1005, Budget::new(2, 2))]),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+        config.per_packet.insert(1004, Budget::new(0, 5));
+        assert!(config.validate().is_err());
+        config.per_packet.remove(&1004);
+        config.bytes = Some(Budget::new(0, 1024));
+        assert!(config.validate().is_err());
+    }
+}
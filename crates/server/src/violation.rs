@@ -0,0 +1,99 @@
+//! Protocol violation accounting.
+//!
+//! Every [`Violation`] a client commits (see
+//! [`tq_network::ActorHandle::report`]) gives its connection some strikes,
+//! once it has more than [`ViolationConfig::max_strikes`], the
+//! [`ViolationAction`] is taken. Every violation is passed to
+//! [`TQServer::on_violation`](crate::TQServer::on_violation) to get recorded,
+//! whatever the strikes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tq_network::{Violation, ViolationKind};
+
+/// What to do with a client that has too many strikes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViolationAction {
+    /// Disconnect the client.
+    #[default]
+    Disconnect,
+    /// Disconnect the client and refuse any connection from its IP for that
+    /// long.
+    Ban(Duration),
+}
+
+/// Violation policies applied to every connection.
+///
+/// By default, violations are only recorded.
+#[derive(Debug, Clone, Default)]
+pub struct ViolationConfig {
+    /// How many strikes every kind of violation is worth, the kinds that are
+    /// not in there are worth one.
+    pub strikes: HashMap<ViolationKind, u32>,
+    /// The most strikes a connection could have before the `action` is taken,
+    /// there is no limit if `None`.
+    pub max_strikes: Option<u32>,
+    /// What to do once a connection has too many strikes.
+    pub action: ViolationAction,
+}
+
+impl ViolationConfig {
+    /// How many strikes `kind` is worth.
+    pub fn strikes_of(&self, kind: ViolationKind) -> u32 {
+        self.strikes.get(&kind).copied().unwrap_or(1)
+    }
+}
+
+/// The strikes of a single connection.
+#[derive(Debug)]
+pub(crate) struct Strikes<'a> {
+    config: &'a ViolationConfig,
+    total: u32,
+}
+
+impl<'a> Strikes<'a> {
+    pub fn new(config: &'a ViolationConfig) -> Self {
+        Self { config, total: 0 }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Counts `violation`, returns the action to take if that was one too
+    /// many.
+    pub fn add(&mut self, violation: &Violation) -> Option<ViolationAction> {
+        self.total = self.total.saturating_add(self.config.strikes_of(violation.kind()));
+        match self.config.max_strikes {
+            Some(max) if self.total > max => Some(self.config.action),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_is_taken_past_max_strikes() {
+        let config = ViolationConfig {
+            strikes: HashMap::from([(ViolationKind::MalformedPacket, 3)]),
+            max_strikes: Some(4),
+            action: ViolationAction::Ban(Duration::from_secs(60)),
+        };
+        let mut strikes = Strikes::new(&config);
+        let unknown = Violation::UnknownPacket { packet_id: 1 };
+        let malformed = Violation::MalformedPacket { packet_id: 1 };
+        assert_eq!(strikes.add(&unknown), None);
+        assert_eq!(strikes.add(&malformed), None);
+        assert_eq!(strikes.total(), 4);
+        assert_eq!(strikes.add(&unknown), Some(config.action));
+
+        let unlimited = ViolationConfig::default();
+        let mut strikes = Strikes::new(&unlimited);
+        for _ in 0..100 {
+            assert_eq!(strikes.add(&malformed), None);
+        }
+    }
+}
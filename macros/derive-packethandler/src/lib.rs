@@ -7,43 +7,71 @@ use syn::{parse_macro_input, Data, DataEnum, DeriveInput, Expr, Ident, Token};
 struct Args {
     actor_state: Expr,
     state: Expr,
+    /// Runs around every packet, the first is the outermost.
+    middleware: Vec<Expr>,
 }
 
 impl Parse for Args {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let ident1: Ident = input
-            .parse()
-            .map_err(|e| syn::Error::new(e.span(), "expected `state` or `actor_state` but got nothing"))?;
-        let _: Token!(=) = input.parse().map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
-        let ident1_value: Expr = input
-            .parse()
-            .map_err(|e| syn::Error::new(e.span(), "expected `Expr`"))?;
-        let _: Token!(,) = input.parse().map_err(|e| syn::Error::new(e.span(), "expected `,`"))?;
-        let ident2: Ident = input
-            .parse()
-            .map_err(|e| syn::Error::new(e.span(), "expected `state` or `actor_state` but got nothing"))?;
-        let _: Token!(=) = input.parse().map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
-        let ident2_value: Expr = input
-            .parse()
-            .map_err(|e| syn::Error::new(e.span(), "expected `Expr`"))?;
-        let (state, actor_state) = match (ident1, ident2) {
-            (ident1, ident2) if ident1 == "state" && ident2 == "actor_state" => (ident1_value, ident2_value),
-            (ident1, ident2) if ident1 == "actor_state" && ident2 == "state" => (ident2_value, ident1_value),
-            (v1, v2) => {
-                return Err(syn::Error::new(
-                    input.span(),
-                    format!("expected `state` or `actor_state` but got {v1} and {v2}",),
-                ))
-            },
+        let mut state = None;
+        let mut actor_state = None;
+        let mut middleware = Vec::new();
+        while !input.is_empty() {
+            let ident: Ident = input.parse().map_err(|e| {
+                syn::Error::new(
+                    e.span(),
+                    "expected `state`, `actor_state` or `middleware` but got nothing",
+                )
+            })?;
+            let _: Token!(=) = input.parse().map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+            let value: Expr = input
+                .parse()
+                .map_err(|e| syn::Error::new(e.span(), "expected `Expr`"))?;
+            match ident.to_string().as_str() {
+                "state" => state = Some(value),
+                "actor_state" => actor_state = Some(value),
+                "middleware" => match value {
+                    Expr::Array(list) => middleware.extend(list.elems),
+                    v => {
+                        return Err(syn::Error::new_spanned(
+                            v,
+                            "expected a list, like `[Timing, CatchPanic]`",
+                        ))
+                    },
+                },
+                v => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        format!("expected `state`, `actor_state` or `middleware` but got {v}"),
+                    ))
+                },
+            }
+            if input.is_empty() {
+                break;
+            }
+            let _: Token!(,) = input.parse().map_err(|e| syn::Error::new(e.span(), "expected `,`"))?;
+        }
+        let (Some(state), Some(actor_state)) = (state, actor_state) else {
+            return Err(syn::Error::new(input.span(), "expected both `state` and `actor_state`"));
+        };
+        let args = Self {
+            state,
+            actor_state,
+            middleware,
         };
-
-        let args = Self { state, actor_state };
         Ok(args)
     }
 }
+
 fn derive_packet_handler(input: DeriveInput) -> syn::Result<TokenStream> {
-    let body = if let Data::Enum(e) = input.data {
-        body(e)?
+    let (body, packets) = if let Data::Enum(e) = input.data {
+        let packets: Vec<_> = e
+            .variants
+            .iter()
+            .filter(|v| v.fields.is_empty())
+            .map(|v| v.ident.clone())
+            .collect();
+        (body(e)?, packets)
     } else {
         return Err(syn::Error::new(
             input.ident.span(),
@@ -65,6 +93,7 @@ fn derive_packet_handler(input: DeriveInput) -> syn::Result<TokenStream> {
     let args: Args = attr.parse_args()?;
     let state = args.state;
     let actor_state = args.actor_state;
+    let body = with_middleware(body, &args.middleware);
     let generics = input.generics;
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     // Build the output, possibly using quasi-quotation
@@ -74,6 +103,7 @@ fn derive_packet_handler(input: DeriveInput) -> syn::Result<TokenStream> {
             type Error = crate::Error;
             type ActorState = #actor_state;
             type State = #state;
+            const PACKETS: &'static [tq_network::PacketInfo] = tq_network::packets![#(#packets),*];
             #[::tracing::instrument(skip_all, fields(actor = actor.id(), packet_id = packet.0))]
              async fn handle(
                  packet: (u16, bytes::Bytes),
@@ -82,19 +112,52 @@ fn derive_packet_handler(input: DeriveInput) -> syn::Result<TokenStream> {
                 ) -> Result<(), Self::Error> {
                     use tq_network::{PacketID, PacketProcess};
                     #body
-                    Ok(())
                 }
         }
     };
     Ok(expanded.into())
 }
 
+/// Wraps the dispatch `body` in the `middleware`, the first one is the
+/// outermost.
+fn with_middleware(body: proc_macro2::TokenStream, middleware: &[Expr]) -> proc_macro2::TokenStream {
+    if middleware.is_empty() {
+        return quote! {
+            #body
+            Ok(())
+        };
+    }
+    let mut chain = quote! {
+        async {
+            #body
+            Ok::<(), Self::Error>(())
+        }
+    };
+    for m in middleware.iter().rev() {
+        chain = quote! {
+            async {
+                let next = tq_network::middleware::Next::new(Box::pin(#chain));
+                <#m as tq_network::middleware::Middleware<Self>>::call(&packet, state, actor, next).await
+            }
+        };
+    }
+    quote! { #chain.await }
+}
+
 fn body(e: DataEnum) -> syn::Result<proc_macro2::TokenStream> {
     let vars = e.variants.into_iter().filter(|v| v.fields.is_empty());
     let match_stms = vars.into_iter().map(|v| {
         let ident = v.ident;
         quote! {
             #ident::PACKET_ID => {
+                let phase = actor.phase();
+                if !<#ident as PacketProcess>::PHASES.contains(phase) {
+                    // Dropped, the connection stays open unless the strikes
+                    // of the violation say otherwise.
+                    tracing::warn!(id = %packet.0, %phase, "Got Packet out of phase");
+                    actor.report(tq_network::Violation::OutOfPhase { packet_id: packet.0, phase });
+                    return Ok(());
+                }
                 let maybe_msg = <#ident as tq_network::PacketDecode>::decode(&packet.1);
                 match maybe_msg {
                     Ok(msg) => {
@@ -103,6 +166,7 @@ fn body(e: DataEnum) -> syn::Result<proc_macro2::TokenStream> {
                     },
                     Err(e) => {
                         tracing::error!(id = %packet.0, error = ?e, "Failed to decode packet");
+                        actor.report(tq_network::Violation::MalformedPacket { packet_id: packet.0 });
                         return Ok(());
                     }
                 }
@@ -115,12 +179,26 @@ fn body(e: DataEnum) -> syn::Result<proc_macro2::TokenStream> {
             #(#match_stms)*
             _ => {
                 tracing::warn!(id = %packet.0, "Got Unknown Packet");
+                actor.report(tq_network::Violation::UnknownPacket { packet_id: packet.0 });
             }
         }
     };
     Ok(tokens)
 }
 
+/// Derives `tq_network::PacketHandler` for an enum of packets, decoding and
+/// processing every packet by its id, if the actor is in one of the
+/// `tq_network::PacketProcess::PHASES` of that packet.
+///
+/// The packets out of phase, unknown or malformed are dropped and reported as
+/// a `tq_network::Violation`, they never close the connection by themselves.
+///
+/// It also sets `tq_network::PacketHandler::PACKETS` to the packets of the
+/// enum, and fails to compile if any two of them have the same id.
+///
+/// `#[handle(state = .., actor_state = .., middleware = [..])]` sets the
+/// state types, and the optional `tq_network::middleware::Middleware` to run
+/// around every packet.
 #[proc_macro_derive(PacketHandler, attributes(handle))]
 pub fn derive(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
@@ -1,7 +1,7 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{quote, ToTokens};
 use syn::parse::{Parse, ParseStream};
-use syn::{parse_macro_input, DeriveInput, Ident, Lit, LitInt, Token};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Ident, Lit, LitInt, Token, Type};
 
 struct Args {
     id: LitInt,
@@ -29,6 +29,85 @@ impl Parse for Args {
     }
 }
 
+/// How many bytes `ty` takes on the wire, `None` if it depends on the value or
+/// it is not known.
+fn wire_size(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Array(array) => {
+            let Expr::Lit(len) = &array.len else {
+                return None;
+            };
+            let Lit::Int(len) = &len.lit else {
+                return None;
+            };
+            let len: usize = len.base10_parse().ok()?;
+            wire_size(&array.elem).map(|size| size * len)
+        },
+        Type::Path(path) => {
+            let ident = &path.path.segments.last()?.ident;
+            let size = match ident.to_string().as_str() {
+                "u8" | "i8" | "bool" => 1,
+                "u16" | "i16" => 2,
+                "u32" | "i32" | "f32" => 4,
+                "u64" | "i64" | "f64" => 8,
+                "String10" => 10,
+                "String16" | "TQPassword" | "TQMaskedPassword" => 16,
+                _ => return None,
+            };
+            Some(size)
+        },
+        _ => None,
+    }
+}
+
+/// Whether the field is left out of the wire by `#[serde(skip)]`.
+fn is_skipped(field: &syn::Field) -> bool {
+    field.attrs.iter().filter(|a| a.path().is_ident("serde")).any(|a| {
+        let mut skip = false;
+        let _ = a.parse_nested_meta(|meta| {
+            skip |= meta.path.is_ident("skip");
+            if meta.input.peek(Token![=]) {
+                let _: Expr = meta.value()?.parse()?;
+            }
+            Ok(())
+        });
+        skip
+    })
+}
+
+/// The `tq_network::FieldInfo` of every field that is on the wire.
+fn fields_info(data: &Data) -> Vec<proc_macro2::TokenStream> {
+    let fields = match data {
+        Data::Struct(s) => &s.fields,
+        _ => return Vec::new(),
+    };
+    let fields = match fields {
+        Fields::Named(f) => f.named.iter().collect(),
+        Fields::Unnamed(f) => f.unnamed.iter().collect(),
+        Fields::Unit => Vec::new(),
+    };
+    fields
+        .into_iter()
+        .enumerate()
+        .filter(|(_, f)| !is_skipped(f))
+        .map(|(i, f)| {
+            let name = f.ident.as_ref().map(|i| i.to_string()).unwrap_or_else(|| i.to_string());
+            let ty = f.ty.to_token_stream().to_string().replace(' ', "");
+            let size = match wire_size(&f.ty) {
+                Some(size) => quote!(Some(#size)),
+                None => quote!(None),
+            };
+            quote! {
+                tq_network::FieldInfo {
+                    name: #name,
+                    ty: #ty,
+                    size: #size,
+                }
+            }
+        })
+        .collect()
+}
+
 fn derive_packet_id(input: DeriveInput) -> syn::Result<TokenStream> {
     // Used in the quasi-quotation below as `#name`.
     let name = input.ident;
@@ -45,16 +124,25 @@ fn derive_packet_id(input: DeriveInput) -> syn::Result<TokenStream> {
         })?;
     let args: Args = attr.parse_args()?;
     let id = args.id;
+    let packet_name = name.to_string();
+    let fields = fields_info(&input.data);
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     // Build the output, possibly using quasi-quotation
     let expanded = quote! {
         impl #impl_generics tq_network::PacketID for #name #ty_generics #where_clause {
             const PACKET_ID: u16 = #id;
+            const PACKET_INFO: tq_network::PacketInfo = tq_network::PacketInfo {
+                name: #packet_name,
+                id: #id,
+                fields: &[#(#fields),*],
+            };
         }
     };
     Ok(expanded.into())
 }
 
+/// Derives `tq_network::PacketID` from `#[packet(id = ..)]`, along with the
+/// `tq_network::PacketInfo` of the packet, its fields and their wire sizes.
 #[proc_macro_derive(PacketID, attributes(packet))]
 pub fn derive(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS violations (
+    violation_id INTEGER PRIMARY KEY,
+    ip_address TEXT NOT NULL CHECK (length(ip_address) <= 45),
+    -- Unknown until the client logs in.
+    account_id INTEGER DEFAULT NULL,
+    kind TEXT NOT NULL CHECK (length(kind) <= 32),
+    packet_id INTEGER NOT NULL CHECK (
+        packet_id >= 0
+        AND packet_id <= 65535
+    ),
+    details TEXT NOT NULL,
+    strikes INTEGER NOT NULL CHECK (strikes >= 0),
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+CREATE INDEX IF NOT EXISTS violations_ip_address ON violations(ip_address);
@@ -0,0 +1,6 @@
+-- The port the game server listens on for the other servers, see `tq-bus`.
+ALTER TABLE realms
+ADD COLUMN bus_port INTEGER NOT NULL DEFAULT 5817 CHECK (
+        bus_port >= 0
+        AND bus_port <= 65535
+    );
@@ -8,6 +8,7 @@ include!(concat!(env!("OUT_DIR"), "/wasm.rs"));
 use msg_connect_ex::{AccountCredentials, RejectionCode};
 use serde::{Deserialize, Serialize};
 use tq_bindings::{host, Resource};
+use tq_db::realm::RealmStatus;
 use tq_network::{ActorHandle, ErrorPacket, IntoErrorPacket, PacketEncode, PacketID};
 
 /// Defines account parameters to be transferred from the account server to the
@@ -31,18 +32,26 @@ impl MsgTransfer {
                 return Err(RejectionCode::ServerLocked.packet().error_packet().into());
             },
         };
-        // Try to connect to that realm first.
-        if let Err(e) = host::auth::server_bus::check(&realm) {
-            tracing::error!(
+        // Only hand the client over to a realm that takes it.
+        let status = host::auth::server_bus::check(&realm);
+        let rejection = match status {
+            RealmStatus::Online => None,
+            RealmStatus::Offline => Some(RejectionCode::ServerDown),
+            RealmStatus::Full => Some(RejectionCode::ServerBusy),
+            RealmStatus::Maintenance => Some(RejectionCode::TryAgainLater),
+            RealmStatus::Locked => Some(RejectionCode::ServerLocked),
+        };
+        if let Some(code) = rejection {
+            tracing::warn!(
                 ip = realm.game_ip_address,
                 port = realm.game_port,
                 realm_id = realm.realm_id,
-                error = ?e,
-                "Failed to connect to realm"
+                status = status.name(),
+                "Realm is unavailable"
             );
-            host::network::actor::send(actor, RejectionCode::ServerDown.packet())?;
+            host::network::actor::send(actor, code.packet())?;
             host::network::actor::shutdown(actor);
-            return Err(e.into());
+            return Err(Error::RealmUnavailable);
         }
 
         let res = host::auth::server_bus::transfer(actor, &realm);
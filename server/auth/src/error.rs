@@ -1,3 +1,4 @@
+use crate::guest::GuestFailure;
 use bytes::Bytes;
 use tq_network::{ErrorPacket, PacketEncode};
 
@@ -5,16 +6,20 @@ use tq_network::{ErrorPacket, PacketEncode};
 pub enum Error {
     Wasmtime(wasmtime::Error),
     Network(tq_network::Error),
-    #[cfg(feature = "server")]
     Server(tq_server::Error),
     IO(std::io::Error),
     DotEnv(dotenvy::Error),
     Env(std::env::VarError),
+    Config(tq_config::Error),
+    Bus(tq_bus::Error),
+    Client(tq_client::Error),
     Sqlx(sqlx::Error),
     Db(tq_db::Error),
     State(&'static str),
     Other(String),
     Msg(u16, Bytes),
+    /// The packet module got killed, see [`crate::guest::GuestLimits`].
+    Guest(&'static str, GuestFailure),
     ActorNotFound,
     InvalidPacket,
 }
@@ -37,6 +42,24 @@ impl From<std::env::VarError> for Error {
     }
 }
 
+impl From<tq_config::Error> for Error {
+    fn from(v: tq_config::Error) -> Self {
+        Self::Config(v)
+    }
+}
+
+impl From<tq_bus::Error> for Error {
+    fn from(v: tq_bus::Error) -> Self {
+        Self::Bus(v)
+    }
+}
+
+impl From<tq_client::Error> for Error {
+    fn from(v: tq_client::Error) -> Self {
+        Self::Client(v)
+    }
+}
+
 impl From<dotenvy::Error> for Error {
     fn from(v: dotenvy::Error) -> Self {
         Self::DotEnv(v)
@@ -49,7 +72,6 @@ impl From<std::io::Error> for Error {
     }
 }
 
-#[cfg(feature = "server")]
 impl From<tq_server::Error> for Error {
     fn from(v: tq_server::Error) -> Self {
         Self::Server(v)
@@ -75,11 +97,13 @@ impl core::fmt::Display for Error {
         match self {
             Self::Wasmtime(e) => write!(f, "Wasmtime error: {}", e),
             Self::Network(e) => write!(f, "Network error: {}", e),
-            #[cfg(feature = "server")]
             Self::Server(e) => write!(f, "Server error: {}", e),
             Self::IO(e) => write!(f, "IO error: {}", e),
             Self::DotEnv(e) => write!(f, "DotEnv error: {}", e),
             Self::Env(e) => write!(f, "Env error: {}", e),
+            Self::Config(e) => write!(f, "Config error: {}", e),
+            Self::Bus(e) => write!(f, "Bus error: {}", e),
+            Self::Client(e) => write!(f, "Client error: {}", e),
             Self::Sqlx(e) => write!(f, "Sqlx error: {}", e),
             Self::Db(e) => write!(f, "Db error: {}", e),
             Self::State(e) => write!(f, "State error: {}", e),
@@ -87,6 +111,7 @@ impl core::fmt::Display for Error {
             Self::Msg(id, bytes) => {
                 write!(f, "Error packet: id = {}, body = {:?}", id, bytes)
             },
+            Self::Guest(module, reason) => write!(f, "Packet module {} killed: {}", module, reason),
             Self::ActorNotFound => write!(f, "Actor Not Found"),
             Self::InvalidPacket => write!(f, "Invalid Packet"),
         }
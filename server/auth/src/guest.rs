@@ -0,0 +1,120 @@
+//! What the packet modules get to use while handling a packet, see
+//! [`GuestLimits`].
+
+use std::time::Duration;
+use wasmtime::{ResourceLimiter, Trap};
+
+/// How much fuel the guests burn before giving the thread back to the
+/// other tasks.
+pub const YIELD_INTERVAL: u64 = 100_000;
+
+/// The budget of a packet module for a single packet, it gets killed once it
+/// is over any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestLimits {
+    /// Roughly how many WASM instructions it could run.
+    pub fuel: u64,
+    /// How large its memory could grow, in bytes.
+    pub memory: usize,
+    /// How long it could take, waiting on the host included.
+    pub timeout: Duration,
+}
+
+impl Default for GuestLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 50_000_000,
+            memory: 32 << 20,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Why a guest got killed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestFailure {
+    OutOfFuel,
+    OutOfMemory,
+    Timeout,
+    /// It trapped, or one of the host functions it called failed.
+    Trap,
+}
+
+impl GuestFailure {
+    /// Tells why the guest failed with `error`, using the `limiter` of its
+    /// store.
+    pub fn of(error: &wasmtime::Error, limiter: &GuestLimiter) -> Self {
+        if limiter.exceeded() {
+            return Self::OutOfMemory;
+        }
+        match error.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => Self::OutOfFuel,
+            _ => Self::Trap,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::OutOfFuel => "out_of_fuel",
+            Self::OutOfMemory => "out_of_memory",
+            Self::Timeout => "timeout",
+            Self::Trap => "trap",
+        }
+    }
+}
+
+impl core::fmt::Display for GuestFailure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Caps the memory of a guest, and remembers if it tried to go over.
+#[derive(Debug, Clone)]
+pub struct GuestLimiter {
+    memory: usize,
+    exceeded: bool,
+}
+
+impl GuestLimiter {
+    pub fn new(memory: usize) -> Self {
+        Self {
+            memory,
+            exceeded: false,
+        }
+    }
+
+    /// The guest asked for more memory than it could have.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+}
+
+impl Default for GuestLimiter {
+    fn default() -> Self {
+        Self::new(GuestLimits::default().memory)
+    }
+}
+
+impl ResourceLimiter for GuestLimiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
+        if desired > self.memory {
+            self.exceeded = true;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: u32, _desired: u32, _maximum: Option<u32>) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Guests killed for going over their limits, by the `reason`.
+pub fn guests_killed(module: &str, reason: GuestFailure) -> tq_metrics::Counter {
+    tq_metrics::counter(
+        "coemu_wasm_guests_killed_total",
+        "WASM packet module instances killed while handling a packet.",
+        &[("module", module), ("reason", reason.name())],
+    )
+}
@@ -1,24 +1,39 @@
 //! Auth Server
 
 pub mod error;
+pub mod guest;
 pub mod linker;
+pub mod registry;
+pub mod reload;
 pub mod state;
 
+mod server;
+pub use server::AuthServer;
+
+use arc_swap::ArcSwap;
 use bytes::Bytes;
+use guest::{GuestFailure, GuestLimiter, GuestLimits};
 pub use state::State;
-use tq_network::{Actor, PacketHandler, PacketID};
-use wasmtime::{Engine, ExternRef, Linker, Module};
+use std::time::Instant;
+use tq_network::{Actor, PacketHandler, PacketID, Violation};
+use wasmtime::{Engine, ExternRef, InstancePre, Linker};
 
 pub struct Runtime {
     pub state: State,
     pub engine: Engine,
     pub linker: Linker<State>,
-    pub packets: Packets,
+    /// Swapped by the [`reload::Reloader`].
+    pub packets: ArcSwap<Packets>,
+    /// The budget of every packet module for a single packet, the engine
+    /// must consume fuel.
+    pub limits: GuestLimits,
 }
 
+/// The packet modules, ready to be instantiated with the linker imports.
+#[derive(Clone)]
 pub struct Packets {
-    pub msg_connect: Module,
-    pub msg_account: Module,
+    pub msg_connect: InstancePre<State>,
+    pub msg_account: InstancePre<State>,
 }
 
 #[async_trait::async_trait]
@@ -27,93 +42,101 @@ impl PacketHandler for Runtime {
     type Error = crate::error::Error;
     type State = Runtime;
 
+    const PACKETS: &'static [tq_network::PacketInfo] =
+        tq_network::packets![msg_connect::MsgConnect, msg_account::MsgAccount];
+
     async fn handle(
         packet: (u16, Bytes),
         runtime: &Self::State,
         actor: &Actor<Self::ActorState>,
     ) -> Result<(), Self::Error> {
-        const PROCESS_PACKET: &str = "process_packet";
-        let packet_len = packet.1.len();
-
-        let mut store = wasmtime::Store::new(&runtime.engine, runtime.state.clone());
-        let actor = wasmtime::ExternRef::new(actor.handle());
-        match packet.0 {
-            msg_connect::MsgConnect::PACKET_ID => {
-                let msg_connect = runtime
-                    .linker
-                    .instantiate_async(&mut store, &runtime.packets.msg_connect)
-                    .await?;
-                let alloc_packet = msg_connect.get_typed_func::<u32, i32>(&mut store, linker::ALLOC)?;
-                let ptr = alloc_packet.call_async(&mut store, packet_len as u32).await?;
-                let memory = msg_connect
-                    .get_memory(&mut store, linker::MEMORY)
-                    .expect("Failed to get memory");
-                memory
-                    .write(&mut store, ptr as usize, &packet.1)
-                    .expect("Failed to write packet to memory");
-                let process =
-                    msg_connect.get_typed_func::<(i32, i32, Option<ExternRef>), i32>(&mut store, PROCESS_PACKET)?;
-                let ret = process
-                    .call_async(&mut store, (ptr, packet_len as i32, Some(actor)))
-                    .await?;
-                match ret {
-                    0 => Ok(()),
-                    0xdec0de => {
-                        tracing::error!("Failed to decode packet: {:#?}", packet);
-                        Err(crate::error::Error::InvalidPacket)
-                    },
-                    0x00f => {
-                        tracing::error!("Failed to handle packet: {:#?}", packet);
-                        Err(crate::error::Error::InvalidPacket)
-                    },
-                    code => {
-                        tracing::error!("Unknown error: {:#?}", packet);
-                        Err(crate::error::Error::Other(format!("Unknown error: {}", code)))
-                    },
-                }
-            },
-            msg_account::MsgAccount::PACKET_ID => {
-                let msg_connect = runtime
-                    .linker
-                    .instantiate_async(&mut store, &runtime.packets.msg_account)
-                    .await?;
-                let alloc_packet = msg_connect.get_typed_func::<u32, i32>(&mut store, linker::ALLOC)?;
-                let ptr = alloc_packet.call_async(&mut store, packet_len as u32).await?;
-                let memory = msg_connect
-                    .get_memory(&mut store, linker::MEMORY)
-                    .expect("Failed to get memory");
-                memory
-                    .write(&mut store, ptr as usize, &packet.1)
-                    .expect("Failed to write packet to memory");
-                let process =
-                    msg_connect.get_typed_func::<(i32, i32, Option<ExternRef>), i32>(&mut store, PROCESS_PACKET)?;
-                let ret = process
-                    .call_async(&mut store, (ptr, packet_len as i32, Some(actor)))
-                    .await?;
-                match ret {
-                    0 => Ok(()),
-                    0xdec0de => {
-                        tracing::error!("Failed to decode packet: {:#?}", packet);
-                        Err(crate::error::Error::InvalidPacket)
-                    },
-                    0x00f => {
-                        tracing::error!("Failed to handle packet: {:#?}", packet);
-                        Err(crate::error::Error::InvalidPacket)
-                    },
-                    code => {
-                        tracing::error!("Unknown error: {:#?}", packet);
-                        Err(crate::error::Error::Other(format!("Unknown error: {}", code)))
-                    },
-                }
-            },
+        // Keeps the modules alive until the packet is handled, even if they
+        // get reloaded meanwhile.
+        let packets = runtime.packets.load_full();
+        let (module, pre) = match packet.0 {
+            msg_connect::MsgConnect::PACKET_ID => ("msg_connect", &packets.msg_connect),
+            msg_account::MsgAccount::PACKET_ID => ("msg_account", &packets.msg_account),
             _ => {
                 tracing::warn!("Unknown packet: {:#?}", packet);
-                Ok(())
+                actor.report(Violation::UnknownPacket { packet_id: packet.0 });
+                return Ok(());
             },
-        }
+        };
+        let handle = actor.handle();
+        let mut state = runtime.state.clone();
+        state.limiter = GuestLimiter::new(runtime.limits.memory);
+        let mut store = wasmtime::Store::new(&runtime.engine, state);
+        store.limiter(|state| &mut state.limiter);
+        store.set_fuel(runtime.limits.fuel)?;
+        store.fuel_async_yield_interval(Some(guest::YIELD_INTERVAL))?;
+        // Dropping the store on timeout kills the guest, wherever it is.
+        let call = call_guest(module, pre, &mut store, &packet.1, handle.clone());
+        let failure = match tokio::time::timeout(runtime.limits.timeout, call).await {
+            Ok(Ok(ret)) => return guest_result(ret, &packet, &handle),
+            Ok(Err(e)) => (GuestFailure::of(&e, &store.data().limiter), e.to_string()),
+            Err(_) => (GuestFailure::Timeout, String::from("timed out")),
+        };
+        let (reason, error) = failure;
+        guest::guests_killed(module, reason).inc();
+        tracing::error!(module, %reason, %error, "Killed packet module");
+        Err(crate::error::Error::Guest(module, reason))
+    }
+}
+
+/// Instantiates the packet module, and calls it with the `packet` body.
+async fn call_guest(
+    module: &'static str,
+    pre: &InstancePre<State>,
+    store: &mut wasmtime::Store<State>,
+    packet: &Bytes,
+    actor: tq_network::ActorHandle,
+) -> wasmtime::Result<i32> {
+    const PROCESS_PACKET: &str = "process_packet";
+    let started = Instant::now();
+    let instance = pre.instantiate_async(&mut *store).await?;
+    instantiate_latency(module).observe_duration(started.elapsed());
+    let alloc_packet = instance.get_typed_func::<u32, i32>(&mut *store, linker::ALLOC)?;
+    let ptr = alloc_packet.call_async(&mut *store, packet.len() as u32).await?;
+    let memory = instance
+        .get_memory(&mut *store, linker::MEMORY)
+        .expect("Failed to get memory");
+    memory.write(&mut *store, ptr as usize, packet)?;
+    let process = instance.get_typed_func::<(i32, i32, Option<ExternRef>), i32>(&mut *store, PROCESS_PACKET)?;
+    process
+        .call_async(&mut *store, (ptr, packet.len() as i32, Some(ExternRef::new(actor))))
+        .await
+}
+
+/// Maps what the packet module returned.
+fn guest_result(ret: i32, packet: &(u16, Bytes), actor: &tq_network::ActorHandle) -> Result<(), crate::error::Error> {
+    match ret {
+        0 => Ok(()),
+        0xdec0de => {
+            tracing::error!("Failed to decode packet: {:#?}", packet);
+            actor.report(Violation::MalformedPacket { packet_id: packet.0 });
+            Err(crate::error::Error::InvalidPacket)
+        },
+        0x00f => {
+            tracing::error!("Failed to handle packet: {:#?}", packet);
+            Err(crate::error::Error::InvalidPacket)
+        },
+        code => {
+            tracing::error!("Unknown error: {:#?}", packet);
+            Err(crate::error::Error::Other(format!("Unknown error: {}", code)))
+        },
     }
 }
 
+/// Time spent instantiating the packet `module` for every packet.
+fn instantiate_latency(module: &str) -> tq_metrics::Histogram {
+    tq_metrics::histogram(
+        "coemu_wasm_instantiate_seconds",
+        "Time spent instantiating the WASM packet modules.",
+        tq_metrics::LATENCY_BUCKETS,
+        &[("module", module)],
+    )
+}
+
 /// Add the runtime to the linker.
 pub fn add_to_linker(linker: &mut Linker<crate::State>) -> Result<(), error::Error> {
     linker::log::trace_event(linker)?;
@@ -133,18 +156,40 @@ pub fn add_to_linker(linker: &mut Linker<crate::State>) -> Result<(), error::Err
 
 #[cfg(test)]
 mod tests {
+    use async_trait::async_trait;
     use msg_account::MsgAccount;
     use msg_connect::MsgConnect;
-    use tq_network::{Message, PacketEncode};
-    use wasmtime::Config;
+    use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+    use std::io;
+    use tq_bus::messages::{Ping, Pong};
+    use tq_db::realm::Realm;
+    use tq_network::{CQCipher, PacketDecode, PacketEncode};
+    use tq_server::harness::Harness;
+    use wasmtime::{Config, Module};
 
     use super::*;
+    use crate::error::Error;
+    use crate::state::{RealmConnector, RealmStream};
+
+    async fn create_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to open the database");
+        // Run database migrations
+        sqlx::migrate!("../../migrations")
+            .run(&pool)
+            .await
+            .expect("Failed to migrate database");
+        pool
+    }
 
-    async fn create_runtime() -> Runtime {
+    fn create_runtime(state: State) -> Runtime {
         let mut config = Config::new();
         config
             .async_support(true)
             .wasm_reference_types(true)
+            .consume_fuel(true)
             .wasm_backtrace(true)
             .wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable)
             .native_unwind_info(true)
@@ -155,25 +200,82 @@ mod tests {
         add_to_linker(&mut linker).unwrap();
         let msg_connect = Module::from_file(&engine, msg_connect::WASM_BINARY.unwrap()).unwrap();
         let msg_account = Module::from_file(&engine, msg_account::WASM_BINARY.unwrap()).unwrap();
-
-        std::env::set_var("DATABASE_URL", "sqlite::memory:");
-        let state = State::init().await.unwrap();
-
-        // Run database migrations
-        sqlx::migrate!("../../migrations")
-            .run(state.pool())
-            .await
-            .expect("Failed to migrate database");
-        let packets = Packets {
-            msg_connect,
-            msg_account,
-        };
+        let packets = Packets::new(&linker, &msg_connect, &msg_account).unwrap();
 
         Runtime {
             state,
             linker,
             engine,
-            packets,
+            packets: ArcSwap::from_pointee(packets),
+            limits: GuestLimits::default(),
+        }
+    }
+
+    fn harness(state: State) -> Harness<AuthServer> {
+        let runtime = create_runtime(state);
+        Harness::new(Box::leak(Box::new(runtime)))
+    }
+
+    fn secret() -> tq_bus::Secret {
+        tq_bus::Secret::new("a very secret secret")
+    }
+
+    /// Reaches the bus of every realm through an in-memory game server.
+    struct InMemoryRealm(&'static game::State);
+
+    #[async_trait]
+    impl RealmConnector for InMemoryRealm {
+        async fn connect(&self, _realm: &Realm) -> io::Result<Box<dyn RealmStream>> {
+            let (stream, server) = tokio::io::duplex(1024);
+            let handler = std::sync::Arc::new(game::bus::BusHandler::new(self.0));
+            tokio::spawn(async move { tq_bus::serve_stream(server, &secret(), handler).await });
+            Ok(Box::new(stream))
+        }
+    }
+
+    /// Never answers on the bus of realm 1, answers pings on the others.
+    struct StuckRealm;
+
+    #[async_trait]
+    impl RealmConnector for StuckRealm {
+        async fn connect(&self, realm: &Realm) -> io::Result<Box<dyn RealmStream>> {
+            let (stream, server) = tokio::io::duplex(1024);
+            match realm.realm_id {
+                1 => tokio::spawn(async move {
+                    let _server = server;
+                    std::future::pending::<()>().await
+                }),
+                _ => {
+                    let handler = std::sync::Arc::new(Pinged);
+                    tokio::spawn(async move {
+                        let _ = tq_bus::serve_stream(server, &secret(), handler).await;
+                    })
+                },
+            };
+            Ok(Box::new(stream))
+        }
+    }
+
+    /// Answers pings, like any realm would.
+    struct Pinged;
+
+    #[async_trait]
+    impl tq_bus::Handler for Pinged {
+        type Error = tq_network::Error;
+
+        async fn handle(&self, (_, body): (u16, bytes::Bytes)) -> Result<(u16, bytes::Bytes), Self::Error> {
+            let ping = Ping::decode(&body)?;
+            Pong { nonce: ping.nonce }.encode()
+        }
+    }
+
+    fn realm(realm_id: i32) -> Realm {
+        Realm {
+            realm_id,
+            name: format!("Realm{realm_id}"),
+            game_ip_address: String::from("127.0.0.1"),
+            game_port: 5816,
+            bus_port: 5817,
         }
     }
 
@@ -208,41 +310,242 @@ mod tests {
     #[tokio::test]
     async fn msg_connect() {
         let _guard = setup_logger(3);
-        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
-        let runtime = create_runtime().await;
+        let harness = harness(State::with_pool(create_pool().await, secret()));
+        let mut client = harness.connect(CQCipher::new());
         let msg = MsgConnect {
             id: 1,
             file_contents: 0,
             file_name: String::from("test").into(),
         };
-        let actor = Actor::<()>::new(tx);
-
-        let encoded = <MsgConnect as PacketEncode>::encode(&msg).unwrap();
-        Runtime::handle(encoded.clone(), &runtime, &actor).await.unwrap();
-        let msg = rx.recv().await.unwrap();
-        assert_eq!(msg, Message::Shutdown);
+        client.send(msg).await.unwrap();
+        client.expect_closed().await;
     }
 
     #[tokio::test]
     async fn msg_account() {
         let _guard = setup_logger(3);
-        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
-        let runtime = create_runtime().await;
+        let harness = harness(State::with_pool(create_pool().await, secret()));
+        let mut client = harness.connect(CQCipher::new());
         let msg = MsgAccount {
             username: String::from("test").into(),
             password: String::from("test").into(),
             realm: String::from("coemu").into(),
             ..Default::default()
         };
-        let actor = Actor::<()>::new(tx);
-
-        let encoded = msg.encode().unwrap();
-        Runtime::handle(encoded.clone(), &runtime, &actor).await.unwrap();
+        client.send(msg).await.unwrap();
         let code = msg_connect_ex::RejectionCode::InvalidPassword;
         let expected_msg = msg_connect_ex::MsgConnectEx::from_code(code);
 
         let encoded = expected_msg.encode().unwrap();
-        let msg = rx.recv().await.unwrap();
-        assert_eq!(msg, Message::from(encoded));
+        assert_eq!(client.recv().await.unwrap(), Some(encoded));
+    }
+
+    #[tokio::test]
+    async fn login_handoff() {
+        let _guard = setup_logger(3);
+        let pool = create_pool().await;
+        let game_state = game::State::with_pool(pool.clone(), concat!(env!("CARGO_MANIFEST_DIR"), "/../../data"))
+            .await
+            .unwrap();
+        let game_state = Box::leak(Box::new(game_state));
+        let game = Harness::<game::GameServer>::new(game_state);
+        let state = State::with_pool(pool, secret()).with_connector(InMemoryRealm(game_state));
+        let registry = state.registry().clone();
+        let auth = harness(state);
+        // Compiling the packet modules can take longer than the heartbeats
+        // last, so the realm only reports in right before the login.
+        registry.update(&game::bus::heartbeat(game_state, 1));
+
+        let mut client = auth.connect(CQCipher::new());
+        let msg = MsgAccount {
+            username: String::from("test1").into(),
+            password: String::from("123456").into(),
+            realm: String::from("CoEmu").into(),
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        let (id, body) = client.recv().await.unwrap().expect("Connection closed");
+        assert_eq!(id, msg_connect_ex::MsgConnectEx::PACKET_ID);
+        let down = msg_connect_ex::RejectionCode::ServerDown.packet().encode().unwrap();
+        assert_ne!((id, body.clone()), down, "The realm is down");
+        let token = u64::from_le_bytes(body[..8].try_into().unwrap());
+
+        let mut client = game.connect(CQCipher::new());
+        let msg = game::packets::MsgConnect {
+            token,
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        client.generate_keys(token);
+        // The account has no character yet.
+        let expected = game::packets::MsgTalk::login_new_role().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_stuck_realm_holds_no_other() {
+        let pool = SqlitePoolOptions::new().connect_lazy("sqlite::memory:").unwrap();
+        let state = State::with_pool(pool, secret()).with_connector(StuckRealm);
+        let stuck = tokio::spawn({
+            let state = state.clone();
+            async move { state.bus(&realm(1)).await }
+        });
+        tokio::task::yield_now().await;
+        let bus = state.bus(&realm(2)).await.unwrap();
+        let pong = bus.request(&Ping { nonce: 7 }).await.unwrap();
+        assert_eq!(pong.nonce, 7);
+        let res = stuck.await.unwrap();
+        assert!(matches!(res, Err(Error::Bus(tq_bus::Error::TimedOut))), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn throttled_clients_still_get_shut_down() {
+        use std::time::Duration;
+        use tq_server::rate_limit::{Budget, RateLimitAction};
+
+        let _guard = setup_logger(3);
+        let runtime = create_runtime(State::with_pool(create_pool().await, secret()));
+        let mut config = tq_server::Config::default();
+        config.rate_limit.packets = Some(Budget::new(1, 1));
+        config.rate_limit.action = RateLimitAction::Throttle;
+        let auth = Harness::<AuthServer>::with_config(Box::leak(Box::new(runtime)), config);
+        let mut client = auth.connect(CQCipher::new());
+        // The second one waits a whole second for the budget to refill.
+        for _ in 0..3 {
+            client.send((1, bytes::Bytes::new())).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let shutdown = tokio::time::timeout(Duration::from_millis(500), auth.shutdown()).await;
+        assert!(matches!(shutdown, Ok(Ok(()))), "{shutdown:?}");
+    }
+
+    #[tokio::test]
+    async fn realm_status_rejections() {
+        use msg_connect_ex::RejectionCode;
+        use tq_bus::messages::RealmHeartbeat;
+        use tq_db::realm::RealmStatus;
+
+        let _guard = setup_logger(3);
+        let state = State::with_pool(create_pool().await, secret());
+        let registry = state.registry().clone();
+        let auth = harness(state);
+        let cases = [
+            (None, RejectionCode::ServerDown),
+            (Some((RealmStatus::Online, 10)), RejectionCode::ServerBusy),
+            (Some((RealmStatus::Maintenance, 0)), RejectionCode::TryAgainLater),
+            (Some((RealmStatus::Locked, 0)), RejectionCode::ServerLocked),
+        ];
+        for (status, code) in cases {
+            if let Some((status, population)) = status {
+                registry.update(&RealmHeartbeat {
+                    realm_id: 1,
+                    status: status as u8,
+                    population,
+                    capacity: 10,
+                });
+            }
+            let mut client = auth.connect(CQCipher::new());
+            let msg = MsgAccount {
+                username: String::from("test1").into(),
+                password: String::from("123456").into(),
+                realm: String::from("CoEmu").into(),
+                ..Default::default()
+            };
+            client.send(msg).await.unwrap();
+            let expected = code.packet().encode().unwrap();
+            assert_eq!(client.recv().await.unwrap(), Some(expected), "{code:?}");
+            client.expect_closed().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn changed_modules_get_reloaded() {
+        use std::fs::{self, File};
+        use std::sync::Arc;
+        use std::time::{Duration, SystemTime};
+
+        let _guard = setup_logger(3);
+        let runtime = create_runtime(State::with_pool(create_pool().await, secret()));
+        let dir = std::env::temp_dir().join(format!("coemu-modules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let msg_connect = dir.join(reload::MODULES[0]);
+        fs::copy(msg_connect::WASM_BINARY.unwrap(), &msg_connect).unwrap();
+        fs::copy(msg_account::WASM_BINARY.unwrap(), dir.join(reload::MODULES[1])).unwrap();
+        let touch = |secs| {
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            File::options()
+                .write(true)
+                .open(&msg_connect)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        let mut reloader = reload::Reloader::new(&dir);
+        let loaded = runtime.packets.load_full();
+        assert_eq!(reloader.reload(&runtime).await, 0);
+
+        // A broken module does not replace the working one.
+        fs::write(&msg_connect, b"not wasm").unwrap();
+        touch(1);
+        assert_eq!(reloader.reload(&runtime).await, 0);
+        assert!(Arc::ptr_eq(&loaded, &runtime.packets.load_full()));
+
+        fs::copy(msg_connect::WASM_BINARY.unwrap(), &msg_connect).unwrap();
+        touch(2);
+        assert_eq!(reloader.reload(&runtime).await, 1);
+        assert!(!Arc::ptr_eq(&loaded, &runtime.packets.load_full()));
+        // Nothing changed since.
+        assert_eq!(reloader.reload(&runtime).await, 0);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The packets are handled by the new module.
+        let harness = Harness::<AuthServer>::new(Box::leak(Box::new(runtime)));
+        let mut client = harness.connect(CQCipher::new());
+        let msg = MsgConnect {
+            id: 1,
+            file_contents: 0,
+            file_name: String::from("test").into(),
+        };
+        client.send(msg).await.unwrap();
+        client.expect_closed().await;
+    }
+
+    #[tokio::test]
+    async fn guests_over_their_limits_get_killed() {
+        let _guard = setup_logger(3);
+        let pool = create_pool().await;
+        let cases = [
+            (
+                GuestFailure::OutOfFuel,
+                GuestLimits {
+                    fuel: 1,
+                    ..Default::default()
+                },
+            ),
+            (
+                GuestFailure::OutOfMemory,
+                GuestLimits {
+                    memory: 0,
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (reason, limits) in cases {
+            let killed = guest::guests_killed("msg_connect", reason).get();
+            let mut runtime = create_runtime(State::with_pool(pool.clone(), secret()));
+            runtime.limits = limits;
+            let harness = Harness::<AuthServer>::new(Box::leak(Box::new(runtime)));
+            let mut client = harness.connect(CQCipher::new());
+            let msg = MsgConnect {
+                id: 1,
+                file_contents: 0,
+                file_name: String::from("test").into(),
+            };
+            client.send(msg).await.unwrap();
+            // The connection ends with the guest.
+            client.expect_closed().await;
+            assert_eq!(guest::guests_killed("msg_connect", reason).get(), killed + 1);
+        }
     }
 }
@@ -235,15 +235,13 @@ pub mod db {
 }
 
 pub mod server_bus {
-    use msg_transfer::MsgTransfer;
-    use tokio::net::TcpStream;
-    use tokio_stream::StreamExt;
-    use tq_network::{CQCipher, PacketDecode, PacketEncode, PacketID, TQCodec};
     use tracing::Instrument;
     use wasmtime::{ExternRef, Linker};
 
     use crate::linker::MODULE;
 
+    /// The status of the realm, see [`tq_db::realm::RealmStatus`], from its
+    /// last heartbeat.
     pub fn check(linker: &mut Linker<crate::State>) -> Result<(), crate::error::Error> {
         const NAME: &str = "auth_server_bus_check";
         linker.func_wrap1_async::<u64, i32>(MODULE, NAME, |mut caller, realm| {
@@ -254,24 +252,9 @@ pub mod server_bus {
                 let realm = unsafe {
                     rkyv::from_bytes_unchecked::<tq_db::realm::Realm>(realm_slice).expect("failed to deserialize realm")
                 };
-                let ip = realm.game_ip_address.as_str();
-                let port = realm.game_port;
-                let stream = TcpStream::connect(format!("{ip}:{port}"))
-                    .instrument(tracing::info_span!("realm_connect", %ip, %port, realm_id = realm.realm_id))
-                    .await;
-                match stream {
-                    Ok(_) => 0,
-                    Err(e) => {
-                        tracing::error!(
-                            %ip,
-                            %port,
-                            realm_id = realm.realm_id,
-                            error = ?e,
-                            "Failed to connect to realm"
-                        );
-                        -1
-                    },
-                }
+                let status = caller.data().registry().status(realm.realm_id);
+                tracing::debug!(realm_id = realm.realm_id, status = status.name(), "Realm check");
+                status as i32
             }) as _
         })?;
         Ok(())
@@ -293,51 +276,26 @@ pub mod server_bus {
                     rkyv::from_bytes_unchecked::<tq_db::realm::Realm>(realm_slice).expect("failed to deserialize realm")
                 };
                 let ip = realm.game_ip_address.as_str();
-                let port = realm.game_port;
-                let stream = TcpStream::connect(format!("{ip}:{port}"))
-                    .instrument(tracing::info_span!("realm_connect", %ip, %port, realm_id = realm.realm_id))
-                    .await;
-                let tcp_stream = match stream {
-                    Ok(s) => s,
+                let port = realm.bus_port;
+                let state = caller.data().clone();
+                // The token is only good for the client that logged in here.
+                let client_ip = actor.addr().map(|addr| addr.ip());
+                let res = async {
+                    let bus = state.bus(&realm).await?;
+                    let token =
+                        tq_client::transfer::request_token(&bus, actor.id() as _, realm.realm_id as _, client_ip)
+                            .await?;
+                    Ok::<_, crate::error::Error>(token)
+                }
+                .instrument(tracing::info_span!("realm_transfer", %ip, %port, realm_id = realm.realm_id))
+                .await;
+                match res {
+                    Ok(token) => token as i64,
                     Err(e) => {
-                        tracing::error!(
-                            %ip,
-                            %port,
-                            realm_id = realm.realm_id,
-                            error = ?e,
-                            "Failed to connect to realm"
-                        );
-                        return -1;
-                    },
-                };
-                let cipher = CQCipher::new();
-                let (mut encoder, mut decoder) = TQCodec::new(tcp_stream, cipher).split();
-                let transfer = MsgTransfer {
-                    account_id: actor.id() as _,
-                    realm_id: realm.realm_id as _,
-                    ..Default::default()
-                };
-
-                let transfer = transfer.encode().expect("failed to encode transfer");
-                encoder.send(transfer).await.expect("failed to send transfer");
-                let res = decoder.next().await;
-                let res = match res {
-                    Some(Ok((MsgTransfer::PACKET_ID, bytes))) => {
-                        MsgTransfer::decode(&bytes).expect("failed to decode transfer")
-                    },
-                    Some(Ok((id, _))) => {
-                        tracing::error!(packet_id = ?id, "Unexpected packet id");
-                        return -1;
-                    },
-                    Some(Err(e)) => {
-                        tracing::error!(error = ?e, "Failed to decode transfer");
-                        return -1;
-                    },
-                    None => {
-                        return -1;
+                        tracing::error!(error = %e, "Failed to transfer account");
+                        -1
                     },
-                };
-                res.token as i64
+                }
             }) as _
         })?;
         Ok(())
@@ -4,32 +4,32 @@
 //! correct with the database. If the combination is correct, the client
 //! will be transferred to the message server of their choice.
 
-use bytes::Bytes;
-use msg_connect::MsgConnect;
-use std::env;
-use tq_network::{Actor, ActorHandle, PacketDecode, PacketHandler, PacketID, TQCipher};
-#[cfg(feature = "server")]
+use arc_swap::ArcSwap;
+use std::time::Duration;
+use tq_server::admission::AdmissionConfig;
+use tq_server::idle::IdleConfig;
+use tq_server::rate_limit::{Budget, RateLimitAction, RateLimitConfig};
+use tq_server::violation::ViolationConfig;
 use tq_server::TQServer;
-use wasmtime::{Config, Engine, ExternRef, Linker, Module, Store};
+use wasmtime::{Config, Engine, Linker};
 
 use auth::error::Error;
-use auth::{Runtime, State};
-
-struct AuthServer;
-
-impl TQServer for AuthServer {
-    type ActorState = ();
-    type Cipher = TQCipher;
-    type PacketHandler = Runtime;
-}
+use auth::guest::GuestLimits;
+use auth::registry::BusHandler;
+use auth::reload::Reloader;
+use auth::{AuthServer, Runtime, State};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    dotenvy::dotenv()?;
-    let log_verbosity = env::var("LOG_VERBOSITY")
-        .map(|s| s.parse::<i32>().unwrap_or(2))
-        .unwrap_or(2);
-    setup_logger(log_verbosity)?;
+    let args = tq_config::Args::from_env();
+    // Everything could be in the configuration file, so `.env` is optional.
+    dotenvy::dotenv().ok();
+    let config = tq_config::Config::load(args.config.as_deref())?;
+    if args.check_config {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+    setup_logger(config.log.verbosity)?;
     println!(
         r#"
  _____         _____                  
@@ -44,37 +44,98 @@ Copyright 2020-2023 Shady Khalifa (@shekohex)
      All Rights Reserved.
  "#
     );
-    let mut config = Config::new();
-    config.async_support(true).wasm_reference_types(true);
+    let mut wasm_config = Config::new();
+    // The fuel is the CPU budget of the packet modules.
+    wasm_config
+        .async_support(true)
+        .wasm_reference_types(true)
+        .consume_fuel(true);
 
-    let engine = Engine::new(&config)?;
+    let engine = Engine::new(&wasm_config)?;
     let mut linker = Linker::new(&engine);
     auth::add_to_linker(&mut linker)?;
     tracing::info!("Loading Packet and handlers..");
 
-    let msg_connect = Module::from_file(&engine, "./target/wasm32-unknown-unknown/wasm/msg_connect.s.wasm")?;
+    let modules = &config.auth.modules_dir;
+    let packets = auth::Packets::load(&linker, modules)?;
     tracing::info!("Initializing State ..");
-    let state = State::init().await?;
-    let packets = auth::Packets { msg_connect };
-
+    let state = State::init(&config).await?;
+    let bus_port = config.auth.bus_port;
+    let bus_listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{bus_port}")).await?;
+    tracing::info!("Auth Bus will be available on {bus_port}");
+    let (stop_bus, bus_stopped) = tokio::sync::oneshot::channel::<()>();
+    let bus = tokio::spawn(tq_bus::serve(
+        bus_listener,
+        config.bus.secret()?,
+        BusHandler::new(state.registry().clone()),
+        async move {
+            let _ = bus_stopped.await;
+        },
+    ));
     let static_runtime = {
         let runtime = Runtime {
             state,
             linker,
             engine,
-            packets,
+            packets: ArcSwap::from_pointee(packets),
+            limits: GuestLimits {
+                fuel: config.auth.guest.fuel,
+                memory: config.auth.guest.memory_mb << 20,
+                timeout: Duration::from_secs(config.auth.guest.timeout_secs),
+            },
         };
         Box::leak(Box::new(runtime)) as *mut _
     };
     // SAFETY: We are the only owner of this Box, and we are deref
     // it. This happens only once, so no one else can access.
     let runtime: &'static _ = unsafe { &*static_runtime };
+    // Change the login logic without a restart.
+    let reloader = match config.auth.reload_secs {
+        0 => None,
+        secs => Some(Reloader::new(modules).spawn(runtime, Duration::from_secs(secs))),
+    };
 
     tracing::info!("Starting Auth Server");
+    if let Some(addr) = config.auth.metrics_addr {
+        tq_metrics::http::spawn(addr, tq_metrics::registry()).await?;
+    }
     tracing::info!("Initializing server...");
-    let auth_port = env::var("AUTH_PORT")?;
+    let auth_port = config.auth.port;
     tracing::info!("Auth Server will be available on {auth_port}");
-    AuthServer::run(format!("0.0.0.0:{}", auth_port), runtime).await?;
+    // Login is only a couple of packets, anything more than that is suspicious.
+    let mut server_config = tq_server::Config {
+        admission: AdmissionConfig {
+            max_connections: Some(1024),
+            max_connections_per_ip: Some(5),
+            ..Default::default()
+        },
+        idle: IdleConfig {
+            login: Some(Duration::from_secs(15)),
+            ..Default::default()
+        },
+        rate_limit: RateLimitConfig {
+            packets: Some(Budget::new(2, 5)),
+            bytes: Some(Budget::new(1024, 2048)),
+            action: RateLimitAction::Disconnect,
+            ..Default::default()
+        },
+        // A real client never gets any of its login packets wrong.
+        violations: ViolationConfig {
+            max_strikes: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    config.auth.server.apply(&mut server_config)?;
+    AuthServer::run(format!("0.0.0.0:{}", auth_port), server_config, runtime).await?;
+    // The bus handler holds on to the state, so wait for it to stop.
+    let _ = stop_bus.send(());
+    let _ = bus.await;
+    // It swaps the modules of the runtime, aborting only asks it to stop.
+    if let Some(reloader) = reloader {
+        reloader.abort();
+        let _ = reloader.await;
+    }
     unsafe {
         // SAFETY: We are the only owner of this Box, and we are dropping
         // it. This happens at the end of the program, so no one
@@ -85,7 +146,7 @@ Copyright 2020-2023 Shady Khalifa (@shekohex)
     Ok(())
 }
 
-fn setup_logger(verbosity: i32) -> Result<(), Error> {
+fn setup_logger(verbosity: u8) -> Result<(), Error> {
     use tracing::Level;
     let log_level = match verbosity {
         0 => Level::ERROR,
@@ -102,6 +163,7 @@ fn setup_logger(verbosity: i32) -> Result<(), Error> {
         .add_directive(format!("tq_codec={}", log_level).parse().unwrap())
         .add_directive(format!("tq_network={}", log_level).parse().unwrap())
         .add_directive(format!("tq_server={}", log_level).parse().unwrap())
+        .add_directive(format!("tq_bus={}", log_level).parse().unwrap())
         .add_directive(format!("auth={}", log_level).parse().unwrap())
         .add_directive(format!("auth_server={}", log_level).parse().unwrap());
     let logger = tracing_subscriber::fmt()
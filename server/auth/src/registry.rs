@@ -0,0 +1,149 @@
+//! The health of the realms, from the heartbeats the game servers send on
+//! the bus of the account server, see [`RealmRegistry`].
+
+use crate::error::Error;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tq_bus::messages::{Ping, Pong, RealmHeartbeat, RealmHeartbeatAck};
+use tq_db::realm::RealmStatus;
+use tq_network::{PacketDecode, PacketEncode, PacketID};
+
+/// The last heartbeat of a realm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealmHealth {
+    pub status: RealmStatus,
+    pub population: u32,
+    pub capacity: u32,
+    pub last_seen: Instant,
+}
+
+impl RealmHealth {
+    /// The status of the realm, [`RealmStatus::Offline`] once its heartbeats
+    /// stop for [`RealmHeartbeat::TIMEOUT`].
+    pub fn status_at(&self, now: Instant) -> RealmStatus {
+        if now.saturating_duration_since(self.last_seen) > RealmHeartbeat::TIMEOUT {
+            return RealmStatus::Offline;
+        }
+        match self.status {
+            RealmStatus::Online if self.population >= self.capacity => RealmStatus::Full,
+            status => status,
+        }
+    }
+}
+
+/// The realms that sent their heartbeats, by their id.
+#[derive(Debug, Default)]
+pub struct RealmRegistry {
+    realms: RwLock<HashMap<i32, RealmHealth>>,
+}
+
+impl RealmRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the heartbeat of a realm, returns `true` if it is the first
+    /// one since the realm went offline.
+    pub fn update(&self, heartbeat: &RealmHeartbeat) -> bool {
+        let now = Instant::now();
+        let health = RealmHealth {
+            status: RealmStatus::from_u8(heartbeat.status).unwrap_or_default(),
+            population: heartbeat.population,
+            capacity: heartbeat.capacity,
+            last_seen: now,
+        };
+        let mut realms = self.realms.write().expect("realm registry lock");
+        let previous = realms.insert(heartbeat.realm_id as i32, health);
+        previous.is_none_or(|h| h.status_at(now) == RealmStatus::Offline)
+    }
+
+    /// The last heartbeat of the realm, if any.
+    pub fn health(&self, realm_id: i32) -> Option<RealmHealth> {
+        self.realms.read().expect("realm registry lock").get(&realm_id).copied()
+    }
+
+    /// The status of the realm, [`RealmStatus::Offline`] if it never sent
+    /// a heartbeat.
+    pub fn status(&self, realm_id: i32) -> RealmStatus {
+        self.health(realm_id)
+            .map(|h| h.status_at(Instant::now()))
+            .unwrap_or_default()
+    }
+}
+
+/// Answers the requests of the game servers.
+#[derive(Debug, Clone)]
+pub struct BusHandler {
+    registry: Arc<RealmRegistry>,
+}
+
+impl BusHandler {
+    pub fn new(registry: Arc<RealmRegistry>) -> Self {
+        Self { registry }
+    }
+}
+
+#[async_trait]
+impl tq_bus::Handler for BusHandler {
+    type Error = Error;
+
+    async fn handle(&self, (id, body): (u16, Bytes)) -> Result<(u16, Bytes), Self::Error> {
+        match id {
+            Ping::PACKET_ID => {
+                let ping = Ping::decode(&body)?;
+                Ok(Pong { nonce: ping.nonce }.encode()?)
+            },
+            RealmHeartbeat::PACKET_ID => {
+                let heartbeat = RealmHeartbeat::decode(&body)?;
+                if self.registry.update(&heartbeat) {
+                    tracing::info!(
+                        realm_id = heartbeat.realm_id,
+                        status = heartbeat.status,
+                        population = heartbeat.population,
+                        capacity = heartbeat.capacity,
+                        "Realm is up"
+                    );
+                }
+                Ok(RealmHeartbeatAck {
+                    realm_id: heartbeat.realm_id,
+                }
+                .encode()?)
+            },
+            _ => Err(Error::Other(format!("Unknown bus request #{id}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(status: RealmStatus, population: u32) -> RealmHeartbeat {
+        RealmHeartbeat {
+            realm_id: 1,
+            status: status as u8,
+            population,
+            capacity: 10,
+        }
+    }
+
+    #[test]
+    fn realms_report_their_status() {
+        let registry = RealmRegistry::new();
+        assert_eq!(registry.status(1), RealmStatus::Offline);
+        assert!(registry.update(&heartbeat(RealmStatus::Online, 0)));
+        assert!(!registry.update(&heartbeat(RealmStatus::Online, 5)));
+        assert_eq!(registry.status(1), RealmStatus::Online);
+        registry.update(&heartbeat(RealmStatus::Online, 10));
+        assert_eq!(registry.status(1), RealmStatus::Full);
+        registry.update(&heartbeat(RealmStatus::Maintenance, 10));
+        assert_eq!(registry.status(1), RealmStatus::Maintenance);
+
+        let health = registry.health(1).unwrap();
+        let later = health.last_seen + RealmHeartbeat::TIMEOUT * 2;
+        assert_eq!(health.status_at(later), RealmStatus::Offline);
+    }
+}
@@ -0,0 +1,130 @@
+//! Hot reload of the packet modules, see [`Reloader`].
+
+use crate::error::Error;
+use crate::{Packets, Runtime, State};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use wasmtime::{Engine, InstancePre, Linker, Module};
+
+/// The file names of the packet modules, in the modules directory.
+pub const MODULES: [&str; 2] = ["msg_connect.s.wasm", "msg_account.s.wasm"];
+
+impl Packets {
+    /// Compiles every packet module found in `dir`, and checks their imports
+    /// against the `linker`.
+    pub fn load(linker: &Linker<State>, dir: &Path) -> Result<Self, Error> {
+        let engine = linker.engine();
+        let msg_connect = Module::from_file(engine, dir.join(MODULES[0]))?;
+        let msg_account = Module::from_file(engine, dir.join(MODULES[1]))?;
+        Self::new(linker, &msg_connect, &msg_account)
+    }
+
+    pub fn new(linker: &Linker<State>, msg_connect: &Module, msg_account: &Module) -> Result<Self, Error> {
+        Ok(Self {
+            msg_connect: linker.instantiate_pre(msg_connect)?,
+            msg_account: linker.instantiate_pre(msg_account)?,
+        })
+    }
+
+    /// A copy of these modules, with `name` replaced by `module`.
+    fn with_module(&self, name: &str, module: InstancePre<State>) -> Self {
+        let mut packets = self.clone();
+        match name {
+            "msg_connect.s.wasm" => packets.msg_connect = module,
+            "msg_account.s.wasm" => packets.msg_account = module,
+            _ => unreachable!("unknown packet module {name}"),
+        }
+        packets
+    }
+}
+
+/// Watches the modules directory, and swaps in the modules that changed.
+///
+/// A module gets compiled again once its file modification time changes, on
+/// a blocking thread. The packets already being handled finish on the old
+/// module, and the old module stays if the new one does not compile or
+/// link.
+#[derive(Debug)]
+pub struct Reloader {
+    dir: PathBuf,
+    modified: [Option<SystemTime>; MODULES.len()],
+}
+
+impl Reloader {
+    /// Watches `dir`, the modules as they are now are the loaded ones.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let modified = MODULES.map(|name| modified(&dir.join(name)));
+        Self { dir, modified }
+    }
+
+    /// Compiles the modules that changed since the last time and swaps them
+    /// in, returns how many got swapped.
+    pub async fn reload(&mut self, runtime: &Runtime) -> usize {
+        let mut swapped = 0;
+        for (i, name) in MODULES.into_iter().enumerate() {
+            let path = self.dir.join(name);
+            let modified = modified(&path);
+            if modified.is_none() || modified == self.modified[i] {
+                continue;
+            }
+            // Do not try again until the file changes again, even if it did
+            // not compile.
+            self.modified[i] = modified;
+            let compiled = compile(&runtime.engine, path.clone()).await;
+            // A module that does not link would fail every packet.
+            match compiled.and_then(|module| Ok(runtime.linker.instantiate_pre(&module)?)) {
+                Ok(module) => {
+                    runtime.packets.rcu(|packets| packets.with_module(name, module.clone()));
+                    reloads(name, "ok").inc();
+                    tracing::info!(module = name, path = %path.display(), "Reloaded packet module");
+                    swapped += 1;
+                },
+                Err(e) => {
+                    reloads(name, "failed").inc();
+                    tracing::error!(
+                        module = name,
+                        path = %path.display(),
+                        error = %e,
+                        "Failed to reload packet module, keeping the old one"
+                    );
+                },
+            }
+        }
+        swapped
+    }
+
+    /// Checks for changed modules every `interval`, forever.
+    pub fn spawn(mut self, runtime: &'static Runtime, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                self.reload(runtime).await;
+            }
+        })
+    }
+}
+
+async fn compile(engine: &Engine, path: PathBuf) -> Result<Module, Error> {
+    let engine = engine.clone();
+    tokio::task::spawn_blocking(move || Module::from_file(&engine, path))
+        .await
+        .map_err(|e| Error::Other(e.to_string()))?
+        .map_err(Error::from)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Packet module reloads, by their `result`.
+fn reloads(module: &str, result: &str) -> tq_metrics::Counter {
+    tq_metrics::counter(
+        "coemu_wasm_reloads_total",
+        "Reloads of the WASM packet modules.",
+        &[("module", module), ("result", result)],
+    )
+}
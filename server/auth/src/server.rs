@@ -0,0 +1,57 @@
+use crate::Runtime;
+use async_trait::async_trait;
+use msg_connect_ex::RejectionCode;
+use std::net::SocketAddr;
+use tq_network::{Actor, PacketHandler, TQCipher, Violation};
+use tq_server::admission::RejectReason;
+use tq_server::TQServer;
+
+pub struct AuthServer;
+
+#[async_trait]
+impl TQServer for AuthServer {
+    type ActorState = ();
+    type Cipher = TQCipher;
+    type PacketHandler = Runtime;
+
+    /// Tell the client why they got rejected, so they get a proper error
+    /// dialog instead of a dropped connection.
+    #[tracing::instrument(skip(_state, actor))]
+    async fn on_rejected(
+        _state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+        reason: RejectReason,
+    ) -> Result<(), tq_server::Error> {
+        let code = match reason {
+            RejectReason::ServerFull => RejectionCode::ServerBusy,
+            RejectReason::TooManyConnections(_) => RejectionCode::TryAgainLater,
+            RejectReason::Banned(_) => RejectionCode::AccountBanned,
+        };
+        actor.send(code.packet()).await?;
+        Ok(())
+    }
+
+    /// Keep every violation in the database, for later review.
+    #[tracing::instrument(skip(runtime, _actor))]
+    async fn on_violation(
+        runtime: &<Self::PacketHandler as PacketHandler>::State,
+        _actor: &Actor<Self::ActorState>,
+        addr: SocketAddr,
+        violation: Violation,
+        strikes: u32,
+    ) -> Result<(), tq_server::Error> {
+        let record = tq_db::violation::Violation {
+            ip_address: addr.ip().to_string(),
+            kind: violation.kind().name().to_owned(),
+            packet_id: violation.packet_id() as i32,
+            details: violation.to_string(),
+            strikes: strikes as i32,
+            ..Default::default()
+        };
+        record
+            .save(runtime.state.pool())
+            .await
+            .map_err(|e| tq_server::Error::Internal(e.into()))?;
+        Ok(())
+    }
+}
@@ -1,29 +1,137 @@
 use crate::error::Error;
+use crate::guest::GuestLimiter;
+use crate::registry::RealmRegistry;
+use async_trait::async_trait;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tq_bus::Secret;
+use tq_db::realm::Realm;
 
-#[derive(Debug, Clone)]
+/// A connection to a game server, see [`RealmConnector`].
+pub trait RealmStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> RealmStream for T {}
+
+/// Opens connections to the bus of the game servers (realms), see
+/// [`State::bus`].
+#[async_trait]
+pub trait RealmConnector: Send + Sync {
+    async fn connect(&self, realm: &Realm) -> io::Result<Box<dyn RealmStream>>;
+}
+
+/// Connects to the realm bus over TCP, using its address in the database.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnector;
+
+#[async_trait]
+impl RealmConnector for TcpConnector {
+    async fn connect(&self, realm: &Realm) -> io::Result<Box<dyn RealmStream>> {
+        let ip = realm.game_ip_address.as_str();
+        let port = realm.bus_port;
+        let stream = TcpStream::connect(format!("{ip}:{port}")).await?;
+        stream.set_nodelay(true)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// The bus connection to a single realm, once connected.
+type BusSlot = Arc<Mutex<Option<tq_bus::Client>>>;
+
+#[derive(Clone)]
 pub struct State {
     pool: SqlitePool,
+    realms: Arc<dyn RealmConnector>,
+    secret: Secret,
+    /// The bus connections to the realms, by their id. Each realm has its
+    /// own lock, so a realm that is slow to connect to holds no other.
+    buses: Arc<std::sync::Mutex<HashMap<i32, BusSlot>>>,
+    registry: Arc<RealmRegistry>,
+    /// Caps the memory of the packet module this state is given to.
+    pub(crate) limiter: GuestLimiter,
+}
+
+impl core::fmt::Debug for State {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("State")
+            .field("pool", &self.pool)
+            .finish_non_exhaustive()
+    }
 }
 
 impl State {
+    /// How long to wait for a realm bus to connect and finish the handshake.
+    pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
     /// Init The State.
     /// Should only get called once.
-    pub async fn init() -> Result<Self, Error> {
-        let data_dir = dotenvy::var("DATA_LOCATION")?;
-        let default_db_location = format!("sqlite://{data_dir}/coemu.db?mode=rwc");
-        let db_url = dotenvy::var("DATABASE_URL").unwrap_or(default_db_location);
+    pub async fn init(config: &tq_config::Config) -> Result<Self, Error> {
         let pool = SqlitePoolOptions::new()
-            .max_connections(42)
-            .min_connections(4)
-            .connect(&db_url)
+            .max_connections(config.database.max_connections)
+            .min_connections(config.database.min_connections)
+            .connect(&config.database_url())
             .await?;
-        let state = Self { pool };
-        Ok(state)
+        Ok(Self::with_pool(pool, config.bus.secret()?))
+    }
+
+    pub fn with_pool(pool: SqlitePool, secret: Secret) -> Self {
+        Self {
+            pool,
+            realms: Arc::new(TcpConnector),
+            secret,
+            buses: Default::default(),
+            registry: Default::default(),
+            limiter: Default::default(),
+        }
+    }
+
+    /// Reach the realms using this connector instead of TCP.
+    pub fn with_connector(mut self, connector: impl RealmConnector + 'static) -> Self {
+        self.realms = Arc::new(connector);
+        self
     }
 
     /// Get access to the database pool
     pub fn pool(&self) -> &SqlitePool {
         &self.pool
     }
+
+    /// Get access to the realms connector
+    pub fn realms(&self) -> Arc<dyn RealmConnector> {
+        self.realms.clone()
+    }
+
+    /// The health of the realms, from their heartbeats.
+    pub fn registry(&self) -> &Arc<RealmRegistry> {
+        &self.registry
+    }
+
+    /// The bus connection to `realm`, connects to it the first time and
+    /// again once the connection is gone, giving up after
+    /// [`State::CONNECT_TIMEOUT`].
+    pub async fn bus(&self, realm: &Realm) -> Result<tq_bus::Client, Error> {
+        let slot = {
+            let mut buses = self.buses.lock().expect("not poisoned");
+            buses.entry(realm.realm_id).or_default().clone()
+        };
+        let mut slot = slot.lock().await;
+        if let Some(bus) = slot.as_ref().filter(|bus| !bus.is_closed()) {
+            return Ok(bus.clone());
+        }
+        let connect = async {
+            let stream = self.realms.connect(realm).await?;
+            Ok::<_, Error>(tq_bus::Client::connect(stream, &self.secret).await?)
+        };
+        let bus = tokio::time::timeout(Self::CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| Error::Bus(tq_bus::Error::TimedOut))??;
+        tracing::info!(realm_id = realm.realm_id, realm = %realm.name, "Connected to realm bus");
+        *slot = Some(bus.clone());
+        Ok(bus)
+    }
 }
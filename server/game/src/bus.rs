@@ -0,0 +1,104 @@
+//! What the game server answers on the inter-server bus, and the heartbeats
+//! it sends to the account server, see [`tq_bus`].
+
+use crate::{state, Error, State};
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::net::TcpStream;
+use tq_bus::messages::{LoginToken, LoginTokenRequest, Ping, Pong, RealmHeartbeat};
+use tq_bus::Secret;
+use tq_network::{PacketDecode, PacketEncode, PacketID};
+
+/// Answers the requests of the account server.
+#[derive(Debug, Clone, Copy)]
+pub struct BusHandler {
+    state: &'static State,
+}
+
+impl BusHandler {
+    pub fn new(state: &'static State) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl tq_bus::Handler for BusHandler {
+    type Error = Error;
+
+    async fn handle(&self, (id, body): (u16, Bytes)) -> Result<(u16, Bytes), Self::Error> {
+        match id {
+            Ping::PACKET_ID => {
+                let ping = Ping::decode(&body)?;
+                Ok(Pong { nonce: ping.nonce }.encode()?)
+            },
+            LoginTokenRequest::PACKET_ID => {
+                let req = LoginTokenRequest::decode(&body)?;
+                let client_ip = match req.client_ip.as_str() {
+                    "" => None,
+                    ip => Some(
+                        ip.parse()
+                            .map_err(|_| Error::Other(format!("Invalid client ip {ip}")))?,
+                    ),
+                };
+                let info = state::LoginToken {
+                    account_id: req.account_id,
+                    realm_id: req.realm_id,
+                };
+                let generated = self.state.tokens().generate_login_token(info, client_ip);
+                tracing::debug!(
+                    account_id = req.account_id,
+                    realm_id = req.realm_id,
+                    ?client_ip,
+                    "Generated login token"
+                );
+                Ok(LoginToken { token: generated.token }.encode()?)
+            },
+            _ => Err(Error::Other(format!("Unknown bus request #{id}"))),
+        }
+    }
+}
+
+/// The heartbeat of the realm, with its status and population.
+pub fn heartbeat(state: &State, realm_id: u32) -> RealmHeartbeat {
+    RealmHeartbeat {
+        realm_id,
+        status: state.realm_status() as u8,
+        population: state.population(),
+        capacity: state.capacity(),
+    }
+}
+
+/// Sends the heartbeats of the realm to the account server bus at `addr`,
+/// every [`RealmHeartbeat::INTERVAL`], connecting again whenever the
+/// connection is gone. Never returns.
+pub async fn send_heartbeats(state: &'static State, realm_id: u32, addr: String, secret: Secret) {
+    let mut interval = tokio::time::interval(RealmHeartbeat::INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut bus: Option<tq_bus::Client> = None;
+    loop {
+        interval.tick().await;
+        let client = match bus.take().filter(|bus| !bus.is_closed()) {
+            Some(client) => client,
+            None => match connect(&addr, &secret).await {
+                Ok(client) => {
+                    tracing::info!(%addr, "Connected to the account server bus");
+                    client
+                },
+                Err(e) => {
+                    tracing::warn!(%addr, error = %e, "Failed to reach the account server bus");
+                    continue;
+                },
+            },
+        };
+        match client.request(&heartbeat(state, realm_id)).await {
+            Ok(_) => bus = Some(client),
+            Err(e) => tracing::warn!(%addr, error = %e, "Failed to send the heartbeat"),
+        }
+    }
+}
+
+async fn connect(addr: &str, secret: &Secret) -> Result<tq_bus::Client, Error> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    Ok(tq_bus::Client::connect(stream, secret).await?)
+}
@@ -20,6 +20,10 @@ pub enum Error {
     #[error(transparent)]
     Env(#[from] std::env::VarError),
     #[error(transparent)]
+    Config(#[from] tq_config::Error),
+    #[error(transparent)]
+    Bus(#[from] tq_bus::Error),
+    #[error(transparent)]
     Sqlx(#[from] sqlx::Error),
     #[error(transparent)]
     Db(#[from] tq_db::Error),
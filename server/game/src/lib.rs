@@ -16,3 +16,8 @@ pub mod error;
 pub use error::Error;
 
 pub mod packets;
+
+pub mod bus;
+
+pub mod server;
+pub use server::{GameServer, Handler};
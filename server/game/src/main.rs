@@ -5,65 +5,28 @@
 //! are processed on this server. Entity intelligence is processed by this
 //! server as well.
 
-use async_trait::async_trait;
-use std::env;
-use tq_network::{Actor, ActorState as _, PacketHandler, TQCipher};
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tq_server::TQServer;
 
+use game::bus::BusHandler;
 use game::packets::*;
-use game::{ActorState, Error, State};
-
-struct GameServer;
-
-#[async_trait]
-impl TQServer for GameServer {
-    type ActorState = ActorState;
-    type Cipher = TQCipher;
-    type PacketHandler = Handler;
-
-    /// Get Called right before ending the connection with that client.
-    /// good chance to clean up anything related to that actor.
-    #[tracing::instrument(skip(state, actor))]
-    async fn on_disconnected(
-        state: &<Self::PacketHandler as PacketHandler>::State,
-        actor: Actor<Self::ActorState>,
-    ) -> Result<(), tq_server::Error> {
-        if let Ok(entity) = actor.try_entity() {
-            let me = entity.as_character().ok_or(Error::CharacterNotFound)?;
-            let mymap_id = me.entity().map_id();
-            me.save(state).await?;
-            me.try_screen()?.remove_from_observers().await?;
-            ActorState::dispose(&actor, actor.handle()).await?;
-            state.remove_entity(me.id());
-            let mymap = state.try_map(mymap_id)?;
-            mymap.remove_entity(&entity)?;
-        }
-        let _ = actor.shutdown().await;
-        Ok(())
-    }
-}
+use game::{Error, GameServer, State};
 
-#[derive(Copy, Clone, PacketHandler)]
-#[handle(state = State, actor_state = ActorState)]
-pub enum Handler {
-    MsgConnect,
-    MsgRegister,
-    MsgTalk,
-    MsgAction,
-    MsgItem,
-    MsgWalk,
-    MsgTransfer,
-    MsgNpc,
-    MsgTaskDialog,
-}
+/// How long we wait for the state to save everything before giving up.
+const CLEAN_UP_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    dotenvy::dotenv()?;
-    let log_verbosity = env::var("LOG_VERBOSITY")
-        .map(|s| s.parse::<i32>().unwrap_or(2))
-        .unwrap_or(2);
-    setup_logger(log_verbosity)?;
+    let args = tq_config::Args::from_env();
+    // Everything could be in the configuration file, so `.env` is optional.
+    dotenvy::dotenv().ok();
+    let config = tq_config::Config::load(args.config.as_deref())?;
+    if args.check_config {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+    setup_logger(config.log.verbosity)?;
     println!(
         r#"
  _____         _____                  
@@ -84,33 +47,145 @@ Copyright 2020 Shady Khalifa (@shekohex)
     tracing::info!("Initializing State ..");
 
     let static_state = {
-        let state = State::init().await?;
+        let state = State::init(&config).await?;
         Box::leak(Box::new(state)) as *mut State
     };
 
     // SAFETY: We are the only owner of this Box, and we are deref
     // it. This happens only once, so no one else can access.
     let state = unsafe { &*static_state };
-    let realm = tq_db::realm::Realm::by_name(state.pool(), "CoEmu")
+    let sweeper = state.spawn_token_sweeper();
+    if let Some(addr) = config.game.metrics_addr {
+        state.register_metrics();
+        tq_metrics::http::spawn(addr, tq_metrics::registry()).await?;
+    }
+    let realm = tq_db::realm::Realm::by_name(state.pool(), &config.game.realm)
         .await?
         .ok_or(Error::RealmNotFound)?;
     let game_port = realm.game_port;
     tracing::info!("Game Server will be available on {}", game_port);
 
-    GameServer::run(format!("0.0.0.0:{}", game_port), state).await?;
+    // The account server asks for the login tokens over the bus, never on the
+    // game port.
+    let bus_secret = config.bus.secret()?;
+    let bus_listener = TcpListener::bind(format!("0.0.0.0:{}", realm.bus_port)).await?;
+    tracing::info!("Bus will be available on {}", realm.bus_port);
+    let heartbeats = tokio::spawn(game::bus::send_heartbeats(
+        state,
+        realm.realm_id as u32,
+        config.game.auth_bus.clone(),
+        bus_secret.clone(),
+    ));
+    let (stop_bus, bus_stopped) = tokio::sync::oneshot::channel::<()>();
+    let bus = tokio::spawn(tq_bus::serve(
+        bus_listener,
+        bus_secret,
+        BusHandler::new(state),
+        async move {
+            let _ = bus_stopped.await;
+        },
+    ));
+
+    GameServer::run(format!("0.0.0.0:{}", game_port), server_config(&config)?, state).await?;
+    // The bus handler borrows the state, so it has to be gone before the
+    // state is dropped.
+    let _ = stop_bus.send(());
+    let _ = bus.await;
+    // Aborting only asks, the tasks are not gone until awaited.
+    heartbeats.abort();
+    let _ = heartbeats.await;
+    sweeper.abort();
+    let _ = sweeper.await;
     unsafe {
         // SAFETY: We are the only owner of this Box, and we are dropping
         // it. This happens at the end of the program, so no one
         // else can access.
         let state = Box::from_raw(static_state);
-        state.clean_up().await?;
+        match tokio::time::timeout(CLEAN_UP_TIMEOUT, state.clean_up()).await {
+            Ok(res) => res?,
+            Err(_) => tracing::warn!(timeout = ?CLEAN_UP_TIMEOUT, "Timed out while cleaning up the state"),
+        }
         // State dropped here.
     };
     tracing::info!("Shutdown.");
     Ok(())
 }
 
-fn setup_logger(verbosity: i32) -> Result<(), Error> {
+/// Connection limits for the game server, the `[game.server]` settings
+/// override them.
+fn server_config(config: &tq_config::Config) -> Result<tq_server::Config, Error> {
+    use tq_network::{OutboundPolicy, PacketID, SendPolicy};
+    use tq_server::admission::AdmissionConfig;
+    use tq_server::idle::IdleConfig;
+    use tq_server::rate_limit::{Budget, RateLimitAction, RateLimitConfig};
+    use tq_server::violation::ViolationConfig;
+
+    let admission = AdmissionConfig {
+        max_connections: Some(2048),
+        max_connections_per_ip: Some(10),
+        ..Default::default()
+    };
+
+    // The client pings the server every few seconds while in game, so a
+    // minute of silence means the connection is dead.
+    let idle = IdleConfig {
+        login: Some(Duration::from_secs(30)),
+        in_game: Some(Duration::from_secs(60)),
+        keepalive: Some(Duration::from_secs(20)),
+    };
+    let rate_limit = RateLimitConfig {
+        packets: Some(Budget::new(60, 120)),
+        bytes: Some(Budget::new(16 * 1024, 32 * 1024)),
+        per_packet: [
+            (MsgWalk::PACKET_ID, Budget::new(12, 24)),
+            (MsgTalk::PACKET_ID, Budget::new(2, 6)),
+        ]
+        .into_iter()
+        .collect(),
+        action: RateLimitAction::Drop,
+    };
+    // A client that can't keep up with 1024 packets is too far behind, but
+    // we don't disconnect anyone for missing a few movement updates.
+    let outbound = tq_server::OutboundConfig {
+        queue_size: 1024,
+        policy: OutboundPolicy {
+            default: SendPolicy::Disconnect,
+            per_packet: [
+                (MsgWalk::PACKET_ID, SendPolicy::Drop),
+                (MsgWeather::PACKET_ID, SendPolicy::Drop),
+            ]
+            .into_iter()
+            .collect(),
+        },
+        // Screen updates usually come in bursts, wait a bit to send them
+        // together.
+        coalesce: tq_server::CoalesceConfig {
+            linger: Duration::from_millis(2),
+            ..Default::default()
+        },
+    };
+    // The client could get a packet out of phase now and then (like a walk
+    // racing a teleport), but not many of them.
+    let violations = ViolationConfig {
+        max_strikes: Some(10),
+        ..Default::default()
+    };
+    // Capturing is off unless asked for, see the `$record` command.
+    let capture = config.game.capture.to_server_config();
+    let mut server_config = tq_server::Config {
+        admission,
+        idle,
+        rate_limit,
+        violations,
+        capture,
+        outbound,
+        ..Default::default()
+    };
+    config.game.server.apply(&mut server_config)?;
+    Ok(server_config)
+}
+
+fn setup_logger(verbosity: u8) -> Result<(), Error> {
     use tracing::Level;
     use tracing_subscriber::prelude::*;
 
@@ -130,6 +205,7 @@ fn setup_logger(verbosity: i32) -> Result<(), Error> {
         .add_directive(format!("tq_codec={}", log_level).parse().unwrap())
         .add_directive(format!("tq_network={}", log_level).parse().unwrap())
         .add_directive(format!("tq_server={}", log_level).parse().unwrap())
+        .add_directive(format!("tq_bus={}", log_level).parse().unwrap())
         .add_directive(format!("game={}", log_level).parse().unwrap())
         .add_directive(format!("game_server={}", log_level).parse().unwrap());
 
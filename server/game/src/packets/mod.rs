@@ -13,9 +13,6 @@ pub use msg_action::{ActionType, MsgAction};
 mod msg_item;
 pub use msg_item::MsgItem;
 
-mod msg_transfer;
-pub use msg_transfer::MsgTransfer;
-
 mod msg_register;
 pub use msg_register::{BaseClass, BodyType, MsgRegister};
 
@@ -41,7 +38,26 @@ mod msg_npc_info;
 pub use msg_npc_info::MsgNpcInfo;
 
 mod msg_npc;
-pub use msg_npc::MsgNpc;
+pub use msg_npc::{MsgNpc, NpcActionKind};
 
 mod msg_task_dialog;
 pub use msg_task_dialog::MsgTaskDialog;
+
+/// Every packet of the game server, whether it sends or handles it.
+pub const PACKETS: &[tq_network::PacketInfo] = tq_network::packets![
+    MsgConnect,
+    MsgTalk,
+    MsgUserInfo,
+    MsgAction,
+    MsgItem,
+    MsgRegister,
+    MsgWalk,
+    MsgPlayer,
+    MsgItemInfo,
+    MsgData,
+    MsgWeather,
+    MsgMapInfo,
+    MsgNpcInfo,
+    MsgNpc,
+    MsgTaskDialog,
+];
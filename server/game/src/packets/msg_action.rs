@@ -8,7 +8,7 @@ use async_trait::async_trait;
 use num_enum::{FromPrimitive, IntoPrimitive};
 use primitives::Location;
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases, Violation};
 use utils::LoHi;
 
 #[derive(Copy, Clone, Debug, Default, FromPrimitive, IntoPrimitive)]
@@ -131,7 +131,7 @@ impl MsgAction {
                 if !mymap.weather().is_unknwon() {
                     actor.send(MsgWeather::new(mymap.weather())).await?;
                 }
-                let screen = actor.screen();
+                let screen = actor.try_screen()?;
                 screen.load_surroundings(state).await?;
             },
             Err(_) => {
@@ -181,7 +181,7 @@ impl MsgAction {
     #[tracing::instrument(skip_all)]
     async fn handle_leave_booth(&self, state: &State, actor: &Actor<ActorState>) -> Result<(), Error> {
         // Remove Player from Booth.
-        let myscreen = actor.screen();
+        let myscreen = actor.try_screen()?;
         myscreen.clear()?;
         myscreen.load_surroundings(state).await?;
         Ok(())
@@ -228,7 +228,7 @@ impl MsgAction {
                 me.set_elevation(tile.elevation);
                 mymap.update_region_for(entity.clone());
                 actor.send(self.clone()).await?;
-                let myscreen = actor.screen();
+                let myscreen = actor.try_screen()?;
                 myscreen.send_movement(state, self.clone()).await?;
             },
             Some(_) | None => {
@@ -261,7 +261,7 @@ impl MsgAction {
         loc.direction = self.details as u8;
         me.entity().set_location(loc);
         actor.send(self.clone()).await?;
-        let myscreen = actor.screen();
+        let myscreen = actor.try_screen()?;
         myscreen.send_message(self.clone()).await?;
         Ok(())
     }
@@ -332,7 +332,7 @@ impl MsgAction {
             KillMode::Arrestment => "In arrestment mode, you can only attack monsters and black name players.",
         };
         actor.send(self.clone()).await?;
-        let msg = super::MsgTalk::from_system(actor.entity().id(), TalkChannel::System, notice);
+        let msg = super::MsgTalk::from_system(actor.try_entity()?.id(), TalkChannel::System, notice);
         actor.send(msg).await?;
         Ok(())
     }
@@ -344,6 +344,8 @@ impl PacketProcess for MsgAction {
     type Error = Error;
     type State = State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         let ty = self.action_type.into();
         match ty {
@@ -384,6 +386,13 @@ impl PacketProcess for MsgAction {
             ActionType::ChangeFacing => self.handle_change_facing(actor).await,
             ActionType::QueryEntity => self.handle_query_entity(state, actor).await,
             ActionType::ChangeMap => self.handle_change_map(state, actor).await,
+            ActionType::Unknown => {
+                actor.report(Violation::InvalidValue {
+                    packet_id: Self::PACKET_ID,
+                    field: "action_type",
+                });
+                Ok(())
+            },
             _ => {
                 let p = MsgTalk::from_system(
                     self.character_id,
@@ -1,10 +1,11 @@
 use super::{MsgTalk, MsgUserInfo};
 use crate::entities::Character;
 use crate::packets::MsgData;
+use crate::state::CreationToken;
 use crate::systems::Screen;
 use crate::{ActorState, Error, State};
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, IntoErrorPacket, PacketID, PacketProcess};
+use tq_network::{Actor, IntoErrorPacket, PacketID, PacketProcess, Phase, Phases, Violation};
 use tq_serde::String10;
 
 /// Message containing a connection request to the game server. Contains the
@@ -26,10 +27,17 @@ impl PacketProcess for MsgConnect {
     type Error = Error;
     type State = State;
 
+    const PHASES: Phases = Phases::HANDSHAKE;
+
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
-        let info = state
-            .remove_login_token(self.token)
-            .map_err(|_| MsgTalk::login_invalid().error_packet())?;
+        let addr = actor.addr().map(|addr| addr.ip());
+        let info = state.tokens().redeem_login_token(self.token, addr).map_err(|e| {
+            actor.report(Violation::InvalidToken {
+                packet_id: Self::PACKET_ID,
+                reason: e.reason(),
+            });
+            MsgTalk::login_invalid().error_packet()
+        })?;
         actor.generate_keys(self.token).await?;
         actor.set_id(info.account_id as usize);
         let maybe_character = tq_db::character::Character::from_account(state.pool(), info.account_id).await?;
@@ -43,14 +51,21 @@ impl PacketProcess for MsgConnect {
                 let mymap = state
                     .try_map(mymap_id)
                     .map_err(|_| MsgTalk::login_invalid().error_packet())?;
-                mymap.insert_entity(actor.entity()).await?;
-                state.insert_entity(actor.entity());
+                let entity = actor.try_entity()?;
+                mymap.insert_entity(entity.clone()).await?;
+                state.insert_entity(entity);
+                actor.set_phase(Phase::InWorld);
                 actor.send(MsgTalk::login_ok()).await?;
                 actor.send(msg).await?;
                 actor.send(MsgData::now()).await?;
             },
             None => {
-                state.store_creation_token(self.token as u32, info.account_id, info.realm_id)?;
+                let creation = CreationToken {
+                    account_id: info.account_id,
+                    realm_id: info.realm_id,
+                };
+                state.tokens().store_creation_token(self.token as u32, creation, addr);
+                actor.set_phase(Phase::CharacterCreation);
                 actor.send(MsgTalk::login_new_role()).await?;
             },
         };
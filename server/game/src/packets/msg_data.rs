@@ -5,7 +5,7 @@ use crate::{ActorState, Error};
 use chrono::{Datelike, NaiveDateTime, Timelike};
 use num_enum::{FromPrimitive, IntoPrimitive};
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases};
 
 /// Enumeration type for defining data actions that may used by the client.
 #[derive(Debug, FromPrimitive, IntoPrimitive)]
@@ -57,6 +57,8 @@ impl PacketProcess for MsgData {
     type Error = Error;
     type State = State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, _state: &Self::State, _actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -4,7 +4,7 @@ use crate::ActorState;
 use async_trait::async_trait;
 use num_enum::{FromPrimitive, IntoPrimitive};
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases, Violation};
 
 /// Enumeration type for defining item actions that may be requested by the
 /// user, or given to by the server. Allows for action handling as a packet
@@ -66,6 +66,8 @@ impl PacketProcess for MsgItem {
     type Error = crate::Error;
     type State = State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, _state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         let action = self.action_type.into();
         match action {
@@ -89,6 +91,12 @@ impl PacketProcess for MsgItem {
                 // later, but I'm going to leave it here for now.
                 actor.send(msg).await?;
             },
+            ItemActionType::Unknown => {
+                actor.report(Violation::InvalidValue {
+                    packet_id: Self::PACKET_ID,
+                    field: "action_type",
+                });
+            },
             _ => {
                 actor.send(self.clone()).await?;
                 let p = MsgTalk::from_system(
@@ -1,6 +1,6 @@
 use num_enum::{FromPrimitive, IntoPrimitive};
-use serde::Deserialize;
-use tq_network::{Actor, PacketID, PacketProcess};
+use serde::{Deserialize, Serialize};
+use tq_network::{Actor, PacketID, PacketProcess, Phases};
 
 use crate::entities::NpcKind;
 use crate::packets::{MsgAction, MsgTalk, MsgTaskDialog};
@@ -20,7 +20,7 @@ pub enum NpcActionKind {
 
 /// This packet is used to interact with a NPC and contains multiple
 /// DialogAction types that are used to determine the type of interaction.
-#[derive(Debug, Deserialize, Clone, PacketID)]
+#[derive(Debug, Serialize, Deserialize, Clone, PacketID)]
 #[packet(id = 2031)]
 pub struct MsgNpc {
     npc_id: u32,
@@ -29,12 +29,25 @@ pub struct MsgNpc {
     kind: u16,
 }
 
+impl MsgNpc {
+    pub fn new(npc_id: u32, action: NpcActionKind) -> Self {
+        Self {
+            npc_id,
+            data: 0,
+            action: action.into(),
+            kind: 0,
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl PacketProcess for MsgNpc {
     type ActorState = crate::ActorState;
     type Error = crate::Error;
     type State = crate::State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         tracing::debug!(
             npc_id = self.npc_id,
@@ -43,7 +56,7 @@ impl PacketProcess for MsgNpc {
             kind = ?NpcKind::from(self.kind as u8),
             "MsgNpc received"
         );
-        let me = actor.entity();
+        let me = actor.try_entity()?;
         let mycharacter = me.as_character().ok_or(crate::Error::CharacterNotFound)?;
         let mymap = state.try_map(me.basic().map_id())?;
         let npc = match mymap.npc(self.npc_id) {
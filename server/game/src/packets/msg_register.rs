@@ -6,7 +6,7 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
-use tq_network::{Actor, IntoErrorPacket, PacketID, PacketProcess};
+use tq_network::{Actor, IntoErrorPacket, PacketID, PacketProcess, Phase, Phases, Violation};
 use tq_serde::{String16, TQMaskedPassword};
 
 #[derive(Debug, Default, Serialize, Deserialize, PacketID)]
@@ -114,18 +114,34 @@ impl PacketProcess for MsgRegister {
     type Error = Error;
     type State = State;
 
+    const PHASES: Phases = Phases::CHARACTER_CREATION;
+
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
-        let info = state
-            .remove_creation_token(self.token)
-            .map_err(|_| MsgTalk::register_invalid().error_packet())?;
+        // Validate Data.
+        let invalid = |field| {
+            actor.report(Violation::InvalidValue {
+                packet_id: Self::PACKET_ID,
+                field,
+            });
+            MsgTalk::register_invalid().error_packet()
+        };
+        BodyType::try_from(self.mesh).map_err(|_| invalid("mesh"))?;
+        BaseClass::try_from(self.class).map_err(|_| invalid("class"))?;
 
         if tq_db::character::Character::name_taken(state.pool(), &self.character_name).await? {
             return Err(MsgTalk::register_name_taken().error_packet().into());
         }
 
-        // Validate Data.
-        BodyType::try_from(self.mesh).map_err(|_| MsgTalk::register_invalid().error_packet())?;
-        BaseClass::try_from(self.class).map_err(|_| MsgTalk::register_invalid().error_packet())?;
+        // Only redeemed once everything else checks out, the client tries
+        // again with the same token.
+        let addr = actor.addr().map(|addr| addr.ip());
+        let info = state.tokens().redeem_creation_token(self.token, addr).map_err(|e| {
+            actor.report(Violation::InvalidToken {
+                packet_id: Self::PACKET_ID,
+                reason: e.reason(),
+            });
+            MsgTalk::register_invalid().error_packet()
+        })?;
 
         let character_id = self
             .build_character(info.account_id, info.realm_id)?
@@ -136,13 +152,15 @@ impl PacketProcess for MsgRegister {
         let me = Character::new(actor.handle(), character);
         let screen = Screen::new(actor.handle());
         actor.update(me, screen);
-        state.insert_entity(actor.entity());
+        let entity = actor.try_entity()?;
+        state.insert_entity(entity.clone());
         // Set player map.
         state
             .try_map(map_id as _)
             .map_err(|_| MsgTalk::register_invalid().error_packet())?
-            .insert_entity(actor.entity())
+            .insert_entity(entity)
             .await?;
+        actor.set_phase(Phase::InWorld);
 
         tracing::info!(
             "Account #{} Created Character #{} with Name {}",
@@ -5,7 +5,7 @@ use crate::ActorState;
 use async_trait::async_trait;
 use num_enum::{FromPrimitive, IntoPrimitive};
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases};
 
 /// Enumeration for defining the channel text is printed to. Can also print to
 /// separate states of the client such as character registration, and can be
@@ -133,6 +133,8 @@ impl PacketProcess for MsgTalk {
     type Error = crate::Error;
     type State = State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         if self.message.starts_with('$') {
             // Command Message.
@@ -142,8 +144,9 @@ impl PacketProcess for MsgTalk {
         }
         // For now, we just broadcast the message to all players in our region.
         // TODO: Implement this properly.
-        let map_id = actor.entity().basic().map_id();
-        let loc = actor.entity().basic().location();
+        let entity = actor.try_entity()?;
+        let map_id = entity.basic().map_id();
+        let loc = entity.basic().location();
         let mymap = state.try_map(map_id)?;
         let myregion = mymap.region(loc.x, loc.y).ok_or(crate::Error::MapRegionNotFound)?;
         myregion.broadcast(self.clone()).await?;
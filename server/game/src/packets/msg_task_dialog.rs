@@ -1,6 +1,6 @@
 use num_enum::{FromPrimitive, IntoPrimitive};
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases};
 use tq_serde::StringList;
 
 use crate::constants;
@@ -181,6 +181,8 @@ impl PacketProcess for MsgTaskDialog {
     type Error = crate::Error;
     type State = crate::State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     async fn process(&self, _state: &Self::State, _actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         tracing::debug!(msg = ?self, "MsgTaskDialog received");
         Ok(())
@@ -6,7 +6,7 @@ use async_trait::async_trait;
 use num_enum::FromPrimitive;
 use primitives::Location;
 use serde::{Deserialize, Serialize};
-use tq_network::{Actor, PacketID, PacketProcess};
+use tq_network::{Actor, PacketID, PacketProcess, Phases};
 
 use super::{MsgTalk, TalkChannel};
 
@@ -33,19 +33,39 @@ pub struct MsgWalk {
     movement_type: u8,
 }
 
+impl MsgWalk {
+    pub fn new(character_id: u32, direction: u8, movement_type: MovementType) -> Self {
+        Self {
+            character_id,
+            direction,
+            movement_type: movement_type as u8,
+        }
+    }
+
+    pub fn character_id(&self) -> u32 {
+        self.character_id
+    }
+
+    pub fn direction(&self) -> u8 {
+        self.direction
+    }
+}
+
 #[async_trait]
 impl PacketProcess for MsgWalk {
     type ActorState = ActorState;
     type Error = Error;
     type State = State;
 
+    const PHASES: Phases = Phases::IN_WORLD;
+
     /// processes a character movement for the actor. It checks if
     /// the movement is valid, then distributes it to observing players. if
     /// the movement is invalid, the packet will not be sent back and the actor
     /// will be teleported back to the character's original position.
     async fn process(&self, state: &Self::State, actor: &Actor<Self::ActorState>) -> Result<(), Self::Error> {
         let direction = (self.direction % 8) as usize;
-        let entity = actor.entity();
+        let entity = actor.try_entity()?;
         let me = entity.as_character().ok_or(Error::CharacterNotFound)?;
         let current_location = me.entity().location();
         let offset = ((WALK_XCOORDS[direction] as u16), (WALK_YCOORDS[direction] as u16));
@@ -59,8 +79,8 @@ impl PacketProcess for MsgWalk {
                 me.entity().set_location(Location::new(x, y, direction as _));
                 me.set_elevation(tile.elevation);
                 actor.send(self.clone()).await?;
-                map.update_region_for(actor.entity());
-                let myscreen = actor.screen();
+                map.update_region_for(entity.clone());
+                let myscreen = actor.try_screen()?;
                 myscreen.send_movement(state, self.clone()).await?;
             },
             Some(_) | None => {
@@ -0,0 +1,357 @@
+//! The game server, see [`TQServer`].
+
+use crate::packets::*;
+use crate::{ActorState, Error, State};
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tq_db::realm::RealmStatus;
+use tq_network::middleware::{CatchPanic, Timing};
+use tq_network::{Actor, ActorState as _, PacketEncode, PacketHandler, Phase, ProtocolCipher, Violation};
+use tq_server::admission::RejectReason;
+use tq_server::TQServer;
+
+pub struct GameServer;
+
+#[async_trait]
+impl TQServer for GameServer {
+    type ActorState = ActorState;
+    type Cipher = ProtocolCipher;
+    type PacketHandler = Handler;
+
+    /// Get Called right before ending the connection with that client.
+    /// good chance to clean up anything related to that actor.
+    #[tracing::instrument(skip(state, actor))]
+    async fn on_disconnected(
+        state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: Actor<Self::ActorState>,
+    ) -> Result<(), tq_server::Error> {
+        if let Ok(entity) = actor.try_entity() {
+            let me = entity.as_character().ok_or(Error::CharacterNotFound)?;
+            let mymap_id = me.entity().map_id();
+            me.save(state).await?;
+            me.try_screen()?.remove_from_observers().await?;
+            ActorState::dispose(&actor, actor.handle()).await?;
+            state.remove_entity(me.id());
+            let mymap = state.try_map(mymap_id)?;
+            mymap.remove_entity(&entity)?;
+        }
+        let _ = actor.shutdown().await;
+        Ok(())
+    }
+
+    fn is_logged_in(actor: &Actor<Self::ActorState>) -> bool {
+        actor.phase() == Phase::InWorld
+    }
+
+    /// Keep the client clock in sync, and make sure the connection is still
+    /// there, only after logging in, since the client does not expect it
+    /// before that.
+    #[tracing::instrument(skip(_state, actor))]
+    async fn on_keepalive(
+        _state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+    ) -> Result<(), tq_server::Error> {
+        if Self::is_logged_in(actor) {
+            actor.send(MsgData::now()).await?;
+        }
+        Ok(())
+    }
+
+    /// Tell the client why they can't log in, the client shows messages on
+    /// the login channel as a dialog.
+    #[tracing::instrument(skip(_state, actor))]
+    async fn on_rejected(
+        _state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+        reason: RejectReason,
+    ) -> Result<(), tq_server::Error> {
+        let message = match reason {
+            RejectReason::ServerFull => "Server is full, please try again later.",
+            RejectReason::TooManyConnections(_) => "Too many connections from your address.",
+            RejectReason::Banned(_) => "Your address is banned for a while, please try again later.",
+        };
+        let msg = MsgTalk::from_system(0, TalkChannel::Login, message);
+        actor.send(msg).await?;
+        Ok(())
+    }
+
+    /// Keep every violation in the database, for the GMs to review.
+    #[tracing::instrument(skip(state, actor), fields(actor = actor.id()))]
+    async fn on_violation(
+        state: &<Self::PacketHandler as PacketHandler>::State,
+        actor: &Actor<Self::ActorState>,
+        addr: SocketAddr,
+        violation: Violation,
+        strikes: u32,
+    ) -> Result<(), tq_server::Error> {
+        // The actor id is the account id once it logs in.
+        let account_id = match actor.phase() {
+            Phase::Handshake => None,
+            _ => Some(actor.id() as i32),
+        };
+        let record = tq_db::violation::Violation {
+            ip_address: addr.ip().to_string(),
+            account_id,
+            kind: violation.kind().name().to_owned(),
+            packet_id: violation.packet_id() as i32,
+            details: violation.to_string(),
+            strikes: strikes as i32,
+            ..Default::default()
+        };
+        record.save(state.pool()).await.map_err(Error::from)?;
+        Ok(())
+    }
+
+    /// Get Called once the server starts shutting down, we use it to warn
+    /// everyone in the game before kicking them out.
+    #[tracing::instrument(skip(state))]
+    async fn on_shutdown(state: &<Self::PacketHandler as PacketHandler>::State) -> Result<(), tq_server::Error> {
+        // The next heartbeat tells the account server to stop sending players.
+        state.set_realm_status(RealmStatus::Maintenance);
+        for remaining in (1..=SHUTDOWN_COUNTDOWN).rev() {
+            let msg = MsgTalk::from_system(
+                0,
+                TalkChannel::Center,
+                format!("Server is going down for maintenance in {remaining} second(s), please log out."),
+            );
+            let packet = msg.encode()?;
+            for entity in state.entities() {
+                if let Some(owner) = entity.owner() {
+                    let _ = owner.send(packet.clone()).await;
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Ok(())
+    }
+}
+
+/// How many seconds the players get warned before the server goes down.
+const SHUTDOWN_COUNTDOWN: u64 = 5;
+
+#[derive(Copy, Clone, PacketHandler)]
+#[handle(state = State, actor_state = ActorState, middleware = [CatchPanic, Timing])]
+pub enum Handler {
+    MsgConnect,
+    MsgRegister,
+    MsgTalk,
+    MsgAction,
+    MsgItem,
+    MsgWalk,
+    MsgNpc,
+    MsgTaskDialog,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tq_network::{CQCipher, PacketID};
+    use tq_server::harness::Harness;
+    use tq_server::violation::ViolationAction;
+
+    async fn state() -> &'static State {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("../../migrations").run(&pool).await.unwrap();
+        let state = State::with_pool(pool, concat!(env!("CARGO_MANIFEST_DIR"), "/../../data"))
+            .await
+            .unwrap();
+        Box::leak(Box::new(state))
+    }
+
+    /// Gets a login token for the client at `client_ip` over the bus, like the
+    /// account server does.
+    async fn login_token(state: &'static State, account_id: u32, realm_id: u32, client_ip: &str) -> u64 {
+        let (stream, server) = tokio::io::duplex(1024);
+        let secret = tq_bus::Secret::new("a very secret secret");
+        let handler = std::sync::Arc::new(crate::bus::BusHandler::new(state));
+        let server_secret = secret.clone();
+        tokio::spawn(async move { tq_bus::serve_stream(server, &server_secret, handler).await });
+        let bus = tq_bus::Client::connect(stream, &secret).await.unwrap();
+        let req = tq_bus::messages::LoginTokenRequest {
+            account_id,
+            realm_id,
+            client_ip: client_ip.to_owned(),
+        };
+        bus.request(&req).await.unwrap().token
+    }
+
+    #[tokio::test]
+    async fn heartbeats_report_the_realm_status() {
+        let state = state().await;
+        let heartbeat = crate::bus::heartbeat(state, 1);
+        assert_eq!(heartbeat.status, RealmStatus::Online as u8);
+        assert_eq!(heartbeat.population, 0);
+        state.set_capacity(0);
+        assert_eq!(crate::bus::heartbeat(state, 1).status, RealmStatus::Full as u8);
+        state.set_realm_status(RealmStatus::Maintenance);
+        assert_eq!(state.realm_status(), RealmStatus::Maintenance);
+    }
+
+    #[tokio::test]
+    async fn login_with_transferred_token() {
+        let state = state().await;
+        let harness = Harness::<GameServer>::new(state);
+        let token = login_token(state, 1, 1, "127.0.0.1").await;
+
+        let mut client = harness.connect(CQCipher::new());
+        let msg = MsgConnect {
+            token,
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        client.generate_keys(token);
+        // The account has no character yet.
+        let expected = MsgTalk::login_new_role().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+
+        // The token is good for one login only.
+        let mut client = harness.connect(CQCipher::new());
+        let msg = MsgConnect {
+            token,
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        let expected = MsgTalk::login_invalid().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn taken_names_keep_the_creation_token() {
+        let state = state().await;
+        let harness = Harness::<GameServer>::new(state);
+        MsgRegister::build_character_with(String::from("Taken"), BodyType::AgileMale, BaseClass::Trojan, 2, 1)
+            .unwrap()
+            .save(state.pool())
+            .await
+            .unwrap();
+        let token = login_token(state, 1, 1, "127.0.0.1").await;
+
+        let mut client = harness.connect(CQCipher::new());
+        let msg = MsgConnect {
+            token,
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        client.generate_keys(token);
+        let expected = MsgTalk::login_new_role().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+
+        let register = |name: &str| MsgRegister {
+            character_name: name.to_owned().into(),
+            mesh: BodyType::AgileMale.into(),
+            class: BaseClass::Trojan.into(),
+            token: token as u32,
+            ..Default::default()
+        };
+        client.send(register("Taken")).await.unwrap();
+        let expected = MsgTalk::register_name_taken().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+        client.send(register("Free")).await.unwrap();
+        // Without the client's maps the character can not enter the world,
+        // it is created before that either way.
+        let _ = client.recv().await;
+        let character = tq_db::character::Character::from_account(state.pool(), 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(character.name, "Free");
+        assert_eq!(state.tokens().pending(), (0, 0));
+
+        // Picking a taken name is no violation.
+        let violations = tq_db::violation::Violation::by_ip(state.pool(), "127.0.0.1", 10)
+            .await
+            .unwrap();
+        assert!(violations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn packets_out_of_phase_are_dropped() {
+        let state = state().await;
+        let harness = Harness::<GameServer>::new(state);
+        let token = login_token(state, 1, 1, "127.0.0.1").await;
+
+        let mut client = harness.connect(CQCipher::new());
+        // Not logged in yet, it is never decoded.
+        client.send((MsgWalk::PACKET_ID, Bytes::new())).await.unwrap();
+        let msg = MsgConnect {
+            token,
+            ..Default::default()
+        };
+        client.send(msg).await.unwrap();
+        client.generate_keys(token);
+        let expected = MsgTalk::login_new_role().encode().unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn tokens_of_another_address_are_refused() {
+        let state = state().await;
+        let harness = Harness::<GameServer>::new(state);
+        let token = login_token(state, 1, 1, "10.0.0.1").await;
+
+        let mut client = harness.connect(CQCipher::new());
+        let expected = MsgTalk::login_invalid().encode().unwrap();
+        for _ in 0..2 {
+            let msg = MsgConnect {
+                token,
+                ..Default::default()
+            };
+            client.send(msg).await.unwrap();
+            assert_eq!(client.recv().await.unwrap(), Some(expected.clone()));
+        }
+
+        // The first one is recorded by now, the packets are handled in order,
+        // and the token is gone after it.
+        let violations = tq_db::violation::Violation::by_ip(state.pool(), "127.0.0.1", 10)
+            .await
+            .unwrap();
+        let first = violations.last().unwrap();
+        assert_eq!(first.kind, "invalid_token");
+        assert_eq!(first.details, "Invalid Token in Packet 1052: token of another address");
+    }
+
+    #[tokio::test]
+    async fn violations_are_recorded_until_banned() {
+        let state = state().await;
+        let mut config = tq_server::Config::default();
+        config.violations.max_strikes = Some(1);
+        config.violations.action = ViolationAction::Ban(Duration::from_secs(60));
+        let harness = Harness::<GameServer>::with_config(state, config);
+        let mut client = harness.connect(CQCipher::new());
+        // Out of phase, it is never decoded.
+        client.send((MsgWalk::PACKET_ID, Bytes::new())).await.unwrap();
+        client.send((1, Bytes::new())).await.unwrap();
+        client.expect_closed().await;
+
+        let violations = tq_db::violation::Violation::by_ip(state.pool(), "127.0.0.1", 10)
+            .await
+            .unwrap();
+        let kinds: Vec<_> = violations.iter().map(|v| (v.kind.as_str(), v.strikes)).collect();
+        assert_eq!(kinds, [("unknown_packet", 2), ("out_of_phase", 1)]);
+
+        let mut client = harness.connect(CQCipher::new());
+        let expected = MsgTalk::from_system(
+            0,
+            TalkChannel::Login,
+            "Your address is banned for a while, please try again later.",
+        );
+        assert_eq!(client.recv().await.unwrap(), Some(expected.encode().unwrap()));
+        client.expect_closed().await;
+    }
+
+    #[test]
+    fn handled_packets_are_registered() {
+        let info = Handler::packet(MsgConnect::PACKET_ID).unwrap();
+        assert_eq!(info.name, "MsgConnect");
+        // token, build_version, language and file_contents.
+        assert_eq!(info.size(), Some(8 + 2 + 10 + 4));
+        assert_eq!(info.field_at(12).map(|f| f.ty), Some("String10"));
+        assert!(Handler::packet(MsgUserInfo::PACKET_ID).is_none());
+        // Login tokens are only handed out over the bus.
+        assert!(Handler::packet(4001).is_none());
+        assert!(crate::packets::PACKETS.iter().any(|p| p.id == MsgUserInfo::PACKET_ID));
+    }
+}
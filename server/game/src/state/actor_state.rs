@@ -39,10 +39,6 @@ impl ActorState {
         self.screen.store(Some(screen));
     }
 
-    pub fn entity(&self) -> Arc<GameEntity> {
-        self.entity.load().clone().expect("state is not empty")
-    }
-
     pub fn entity_weak(&self) -> Weak<GameEntity> {
         let e = self.entity.load().clone();
         match e {
@@ -63,10 +59,6 @@ impl ActorState {
         }
     }
 
-    pub fn screen(&self) -> Arc<Screen> {
-        self.screen.load().clone().expect("state is not empty")
-    }
-
     pub fn screen_weak(&self) -> Weak<Screen> {
         let screen = self.screen.load().clone();
         match screen {
@@ -1,26 +1,32 @@
 use crate::entities::GameEntity;
 use crate::world::Map;
 use crate::Error;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::RwLock;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::time::Duration;
+use tq_db::realm::RealmStatus;
 use tracing::debug;
 
 mod actor_state;
+mod tokens;
 
 pub use actor_state::ActorState;
+pub use tokens::{CreationToken, GeneratedLoginToken, LoginToken, TokenConfig, TokenRejection, TokenStore};
 
 type Maps = HashMap<u32, Map>;
 type Entites = RwLock<HashMap<u32, Arc<GameEntity>>>;
-type LoginTokens = Mutex<HashMap<u64, LoginToken>>;
-type CreationTokens = Mutex<HashMap<u32, CreationToken>>;
 
 #[derive(Debug)]
 pub struct State {
-    login_tokens: LoginTokens,
-    creation_tokens: CreationTokens,
+    tokens: TokenStore,
+    /// What the realm reports to the account server, see
+    /// [`State::realm_status`].
+    status: AtomicU8,
+    capacity: AtomicU32,
     entities: Entites,
     maps: Maps,
     pool: SqlitePool,
@@ -29,19 +35,24 @@ pub struct State {
 impl State {
     /// Init The State.
     /// Should only get called once.
-    pub async fn init() -> Result<Self, Error> {
-        let data_dir = dotenvy::var("DATA_LOCATION")?;
-        let default_db_location = format!("sqlite://{data_dir}/coemu.db?mode=rwc");
-        let db_url = dotenvy::var("DATABASE_URL").unwrap_or(default_db_location);
+    pub async fn init(config: &tq_config::Config) -> Result<Self, Error> {
         let pool = SqlitePoolOptions::new()
-            .max_connections(42)
-            .min_connections(4)
-            .connect(&db_url)
+            .max_connections(config.database.max_connections)
+            .min_connections(config.database.min_connections)
+            .connect(&config.database_url())
             .await?;
-        Self::with_pool(pool).await
+        let tokens = TokenConfig {
+            login_ttl: Duration::from_secs(config.game.tokens.login_ttl_secs),
+            creation_ttl: Duration::from_secs(config.game.tokens.creation_ttl_secs),
+            bind_ip: config.game.tokens.bind_ip,
+        };
+        let state = Self::with_pool(pool, &config.data_dir).await?.with_tokens(tokens);
+        state.set_capacity(config.game.capacity);
+        Ok(state)
     }
 
-    pub async fn with_pool(pool: SqlitePool) -> Result<Self, Error> {
+    /// The maps get loaded from `data_dir` once needed.
+    pub async fn with_pool(pool: SqlitePool, data_dir: impl AsRef<Path>) -> Result<Self, Error> {
         debug!("Loading Maps from Database");
         let db_maps = tq_db::map::Map::load_all(&pool).await?;
         let mut maps = HashMap::with_capacity(db_maps.len());
@@ -51,13 +62,14 @@ impl State {
             tracing::trace!(%map.id, portals = %portals.len(), "Loaded Portals");
             let npcs = tq_db::npc::Npc::by_map(&pool, map.id).await?;
             tracing::trace!(%map.id, npcs = %npcs.len(), "Loaded Npcs");
-            let map = Map::new(map, portals, npcs);
+            let map = Map::new(data_dir.as_ref(), map, portals, npcs);
             maps.insert(map.id(), map);
         }
 
         let state = Self {
-            login_tokens: Default::default(),
-            creation_tokens: Default::default(),
+            tokens: Default::default(),
+            status: AtomicU8::new(RealmStatus::Online as u8),
+            capacity: AtomicU32::new(1000),
             entities: Default::default(),
             maps,
             pool,
@@ -65,6 +77,12 @@ impl State {
         Ok(state)
     }
 
+    /// Hands out the tokens with `config` instead of the default one.
+    pub fn with_tokens(mut self, config: TokenConfig) -> Self {
+        self.tokens = TokenStore::new(config);
+        self
+    }
+
     /// Get access to the database pool
     pub fn pool(&self) -> &SqlitePool {
         &self.pool
@@ -78,6 +96,45 @@ impl State {
         self.maps.get(&map_id).ok_or(Error::MapNotFound)
     }
 
+    /// Reports the loaded maps and their entities, computed every time the
+    /// metrics get scraped.
+    pub fn register_metrics(&'static self) {
+        let registry = tq_metrics::registry();
+        registry.gauge_fn("coemu_maps_loaded", "Maps loaded in memory.", move || {
+            let loaded = self.maps.values().filter(|m| m.loaded()).count();
+            vec![(Vec::new(), loaded as f64)]
+        });
+        registry.gauge_fn("coemu_map_entities", "Entities on every loaded map.", move || {
+            self.maps
+                .values()
+                .filter(|m| m.loaded())
+                .map(|m| (vec![("map_id", m.id().to_string())], m.entity_count() as f64))
+                .collect()
+        });
+        registry.gauge_fn("coemu_pending_tokens", "Tokens waiting to be redeemed.", move || {
+            let (login, creation) = self.tokens.pending();
+            vec![
+                (vec![("kind", String::from("login"))], login as f64),
+                (vec![("kind", String::from("creation"))], creation as f64),
+            ]
+        });
+    }
+
+    /// Drops the expired tokens every [`tokens::SWEEP_INTERVAL`], until the
+    /// returned task gets aborted.
+    pub fn spawn_token_sweeper(&'static self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokens::SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let swept = self.tokens.sweep();
+                if swept > 0 {
+                    debug!(swept, "Dropped expired tokens");
+                }
+            }
+        })
+    }
+
     pub fn insert_entity(&self, entity: Arc<GameEntity>) {
         let mut entities = self.entities.write();
         entities.insert(entity.id(), entity);
@@ -102,42 +159,38 @@ impl State {
         values.cloned().collect()
     }
 
-    /// Generate a new Login Token.
-    ///
-    /// The token will be stored internally, and can be later removed by calling
-    /// [`TokenStore::remove_login_token`].
-    pub fn generate_login_token(&self, account_id: u32, realm_id: u32) -> Result<GeneratedLoginToken, crate::Error> {
-        let token = rand::random();
-        self.login_tokens
-            .lock()
-            .insert(token, LoginToken { account_id, realm_id });
-        Ok(GeneratedLoginToken { token })
-    }
-
-    /// Remove a Login Token.
-    pub fn remove_login_token(&self, token: u64) -> Result<LoginToken, crate::Error> {
-        self.login_tokens
-            .lock()
-            .remove(&token)
-            .ok_or(crate::Error::LoginTokenNotFound)
-    }
-
-    /// Store a new CreationToken.
-    /// The token will be stored internally, and can be later removed by calling
-    /// [`TokenStore::remove_creation_token`].
-    pub fn store_creation_token(&self, token: u32, account_id: u32, realm_id: u32) -> Result<(), crate::Error> {
-        self.creation_tokens
-            .lock()
-            .insert(token, CreationToken { account_id, realm_id });
-        Ok(())
+    /// The login and character creation tokens.
+    pub fn tokens(&self) -> &TokenStore {
+        &self.tokens
+    }
+
+    /// The status of the realm, [`RealmStatus::Full`] once the population
+    /// reaches the capacity unless it is set to something else than online.
+    pub fn realm_status(&self) -> RealmStatus {
+        let status = RealmStatus::from_u8(self.status.load(Ordering::Relaxed)).unwrap_or_default();
+        match status {
+            RealmStatus::Online if self.population() >= self.capacity() => RealmStatus::Full,
+            status => status,
+        }
     }
 
-    /// Remove a CreationToken.
-    pub fn remove_creation_token(&self, token: u32) -> Result<CreationToken, crate::Error> {
-        self.creation_tokens
-            .lock()
-            .remove(&token)
-            .ok_or(crate::Error::CreationTokenNotFound)
+    /// Sets the status of the realm, like [`RealmStatus::Maintenance`].
+    pub fn set_realm_status(&self, status: RealmStatus) {
+        self.status.store(status as u8, Ordering::Relaxed);
+    }
+
+    /// The players in game.
+    pub fn population(&self) -> u32 {
+        self.entities.read().len() as u32
+    }
+
+    /// How many players the realm takes.
+    pub fn capacity(&self) -> u32 {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    pub fn set_capacity(&self, capacity: u32) {
+        self.capacity.store(capacity, Ordering::Relaxed);
     }
 
     fn drain_entities(&self) -> Vec<Arc<GameEntity>> {
@@ -164,63 +217,3 @@ impl State {
         Ok(())
     }
 }
-
-#[derive(Clone, Debug)]
-pub struct LoginToken {
-    pub account_id: u32,
-    pub realm_id: u32,
-}
-
-#[derive(Clone, Debug)]
-pub struct CreationToken {
-    pub account_id: u32,
-    pub realm_id: u32,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct GeneratedLoginToken {
-    pub token: u64,
-}
-
-/// Listens for the server shutdown signal.
-///
-/// Shutdown is signalled using a `broadcast::Receiver`. Only a single value is
-/// ever sent. Once a value has been sent via the broadcast channel, the server
-/// should shutdown.
-///
-/// The `Shutdown` struct listens for the signal and tracks that the signal has
-/// been received. Callers may query for whether the shutdown signal has been
-/// received or not.
-#[derive(Debug)]
-pub struct Shutdown {
-    /// `true` if the shutdown signal has been received
-    shutdown: bool,
-
-    /// The receive half of the channel used to listen for shutdown.
-    notify: broadcast::Receiver<()>,
-}
-
-impl Shutdown {
-    /// Create a new `Shutdown` backed by the given `broadcast::Receiver`.
-    pub fn new(notify: broadcast::Receiver<()>) -> Shutdown {
-        Shutdown {
-            shutdown: false,
-            notify,
-        }
-    }
-
-    /// Receive the shutdown notice, waiting if necessary.
-    pub async fn recv(&mut self) {
-        // If the shutdown signal has already been received, then return
-        // immediately.
-        if self.shutdown {
-            return;
-        }
-
-        // Cannot receive a "lag error" as only one value is ever sent.
-        let _ = self.notify.recv().await;
-
-        // Remember that the signal has been received.
-        self.shutdown = true;
-    }
-}
@@ -0,0 +1,260 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How often the expired tokens get dropped, see [`TokenStore::sweep`].
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the tokens last, and who could redeem them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenConfig {
+    /// How long the client has to present its login token, after the
+    /// account server asked for it.
+    pub login_ttl: Duration,
+    /// How long the client has to create its character, after logging in.
+    pub creation_ttl: Duration,
+    /// Only accept the tokens from the address they were given to.
+    pub bind_ip: bool,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            login_ttl: Duration::from_secs(60),
+            creation_ttl: Duration::from_secs(10 * 60),
+            bind_ip: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginToken {
+    pub account_id: u32,
+    pub realm_id: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct CreationToken {
+    pub account_id: u32,
+    pub realm_id: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GeneratedLoginToken {
+    pub token: u64,
+}
+
+/// Why a token got refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenRejection {
+    /// It was never handed out, or it got used already.
+    Unknown,
+    Expired,
+    /// It was handed out to another address.
+    WrongAddress,
+}
+
+impl TokenRejection {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown or used token",
+            Self::Expired => "expired token",
+            Self::WrongAddress => "token of another address",
+        }
+    }
+}
+
+impl core::fmt::Display for TokenRejection {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.reason())
+    }
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    token: T,
+    /// Anyone could redeem it if `None`.
+    client_ip: Option<IpAddr>,
+    expires_at: Instant,
+}
+
+/// The login tokens handed out to the account server, and the character
+/// creation tokens of the clients that logged in without a character.
+///
+/// Every token is good for a single try, it is gone once presented, even if
+/// it gets refused.
+#[derive(Debug, Default)]
+pub struct TokenStore {
+    config: TokenConfig,
+    login: Mutex<HashMap<u64, Entry<LoginToken>>>,
+    creation: Mutex<HashMap<u32, Entry<CreationToken>>>,
+}
+
+impl TokenStore {
+    pub fn new(config: TokenConfig) -> Self {
+        Self {
+            config,
+            login: Default::default(),
+            creation: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> &TokenConfig {
+        &self.config
+    }
+
+    /// Generates a new login token for the client at `client_ip`, see
+    /// [`TokenStore::redeem_login_token`].
+    pub fn generate_login_token(&self, info: LoginToken, client_ip: Option<IpAddr>) -> GeneratedLoginToken {
+        let mut tokens = self.login.lock();
+        let token = loop {
+            let token = rand::random();
+            if !tokens.contains_key(&token) {
+                break token;
+            }
+        };
+        tokens.insert(token, self.entry(info, client_ip, self.config.login_ttl));
+        GeneratedLoginToken { token }
+    }
+
+    /// Takes the login token out, if it is still good for the client at
+    /// `addr`.
+    pub fn redeem_login_token(&self, token: u64, addr: Option<IpAddr>) -> Result<LoginToken, TokenRejection> {
+        let entry = self.login.lock().remove(&token);
+        self.check(entry, addr)
+    }
+
+    /// Stores the character creation token of the client at `client_ip`.
+    pub fn store_creation_token(&self, token: u32, info: CreationToken, client_ip: Option<IpAddr>) {
+        let entry = self.entry(info, client_ip, self.config.creation_ttl);
+        self.creation.lock().insert(token, entry);
+    }
+
+    /// Takes the character creation token out, if it is still good for the
+    /// client at `addr`.
+    pub fn redeem_creation_token(&self, token: u32, addr: Option<IpAddr>) -> Result<CreationToken, TokenRejection> {
+        let entry = self.creation.lock().remove(&token);
+        self.check(entry, addr)
+    }
+
+    /// Drops the expired tokens, returns how many got dropped.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        sweep(&self.login, now) + sweep(&self.creation, now)
+    }
+
+    /// How many login and creation tokens are waiting.
+    pub fn pending(&self) -> (usize, usize) {
+        (self.login.lock().len(), self.creation.lock().len())
+    }
+
+    fn entry<T>(&self, token: T, client_ip: Option<IpAddr>, ttl: Duration) -> Entry<T> {
+        Entry {
+            token,
+            client_ip: client_ip.filter(|_| self.config.bind_ip),
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    fn check<T>(&self, entry: Option<Entry<T>>, addr: Option<IpAddr>) -> Result<T, TokenRejection> {
+        let entry = entry.ok_or(TokenRejection::Unknown)?;
+        if Instant::now() >= entry.expires_at {
+            return Err(TokenRejection::Expired);
+        }
+        match entry.client_ip {
+            Some(ip) if Some(ip) != addr => Err(TokenRejection::WrongAddress),
+            _ => Ok(entry.token),
+        }
+    }
+}
+
+fn sweep<K: Eq + Hash, T>(tokens: &Mutex<HashMap<K, Entry<T>>>, now: Instant) -> usize {
+    let mut tokens = tokens.lock();
+    let before = tokens.len();
+    tokens.retain(|_, entry| entry.expires_at > now);
+    before - tokens.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    fn info() -> LoginToken {
+        LoginToken {
+            account_id: 1,
+            realm_id: 1,
+        }
+    }
+
+    #[test]
+    fn tokens_are_single_use_and_bound() {
+        let store = TokenStore::default();
+        let generated = store.generate_login_token(info(), Some(CLIENT));
+        assert_eq!(
+            store.redeem_login_token(generated.token, Some(OTHER)).unwrap_err(),
+            TokenRejection::WrongAddress
+        );
+        // Refused, but gone anyway.
+        assert_eq!(
+            store.redeem_login_token(generated.token, Some(CLIENT)).unwrap_err(),
+            TokenRejection::Unknown
+        );
+
+        let generated = store.generate_login_token(info(), Some(CLIENT));
+        assert_eq!(
+            store
+                .redeem_login_token(generated.token, Some(CLIENT))
+                .unwrap()
+                .account_id,
+            1
+        );
+        assert_eq!(
+            store.redeem_login_token(generated.token, Some(CLIENT)).unwrap_err(),
+            TokenRejection::Unknown
+        );
+
+        // Unbound tokens are good for anyone.
+        let generated = store.generate_login_token(info(), None);
+        assert!(store.redeem_login_token(generated.token, Some(OTHER)).is_ok());
+
+        let store = TokenStore::new(TokenConfig {
+            bind_ip: false,
+            ..Default::default()
+        });
+        let generated = store.generate_login_token(info(), Some(CLIENT));
+        assert!(store.redeem_login_token(generated.token, Some(OTHER)).is_ok());
+    }
+
+    #[test]
+    fn expired_tokens_are_refused_and_swept() {
+        let store = TokenStore::new(TokenConfig {
+            login_ttl: Duration::ZERO,
+            creation_ttl: Duration::ZERO,
+            bind_ip: true,
+        });
+        let generated = store.generate_login_token(info(), Some(CLIENT));
+        assert_eq!(
+            store.redeem_login_token(generated.token, Some(CLIENT)).unwrap_err(),
+            TokenRejection::Expired
+        );
+
+        store.generate_login_token(info(), Some(CLIENT));
+        let creation = CreationToken {
+            account_id: 1,
+            realm_id: 1,
+        };
+        store.store_creation_token(42, creation, Some(CLIENT));
+        assert_eq!(store.pending(), (1, 1));
+        assert_eq!(store.sweep(), 2);
+        assert_eq!(store.pending(), (0, 0));
+        assert_eq!(
+            store.redeem_creation_token(42, Some(CLIENT)).unwrap_err(),
+            TokenRejection::Unknown
+        );
+    }
+}
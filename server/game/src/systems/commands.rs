@@ -2,10 +2,11 @@ use crate::packets::{MsgTalk, TalkChannel};
 use crate::world::Maps;
 use crate::{ActorState, Error};
 use argh::FromArgs;
+use tq_db::realm::RealmStatus;
 use tq_network::Actor;
 
 pub async fn parse_and_execute(state: &crate::State, actor: &Actor<ActorState>, args: &[&str]) -> Result<(), Error> {
-    let entity = actor.entity();
+    let entity = actor.try_entity()?;
     let me = entity.as_character().ok_or(Error::CharacterNotFound)?;
     let c = match Command::from_args(&["commands"], args) {
         Ok(cmd) => cmd,
@@ -28,8 +29,8 @@ pub async fn parse_and_execute(state: &crate::State, actor: &Actor<ActorState>,
             let old_map = state.try_map(me.entity().map_id())?;
             let map = state.try_map(info.map_id)?;
             me.teleport(state, info.map_id, (info.x, info.y)).await?;
-            map.insert_entity(actor.entity()).await?;
-            old_map.remove_entity(&actor.entity())?;
+            map.insert_entity(entity.clone()).await?;
+            old_map.remove_entity(&entity)?;
             if info.all {
                 // TODO: teleport all
             }
@@ -57,6 +58,36 @@ pub async fn parse_and_execute(state: &crate::State, actor: &Actor<ActorState>,
             map.change_weather(weather.kind.into()).await?;
             Ok(())
         },
+        SubCommands::Record(record) => {
+            let target = match record.id {
+                Some(id) => state
+                    .with_entity(id, |e| e.owner())
+                    .flatten()
+                    .ok_or(Error::CharacterNotFound)?,
+                None => actor.handle(),
+            };
+            target.set_capture(!record.stop).await?;
+            let status = if record.stop { "Stopped" } else { "Started" };
+            actor
+                .send(MsgTalk::from_system(
+                    me.id(),
+                    TalkChannel::System,
+                    format!("{status} recording packets of #{}", record.id.unwrap_or(me.id())),
+                ))
+                .await?;
+            Ok(())
+        },
+        SubCommands::Realm(realm) => {
+            state.set_realm_status(realm.status);
+            actor
+                .send(MsgTalk::from_system(
+                    me.id(),
+                    TalkChannel::System,
+                    format!("Realm is {}", state.realm_status().name()),
+                ))
+                .await?;
+            Ok(())
+        },
     }
 }
 
@@ -75,6 +106,8 @@ enum SubCommands {
     Teleport(TeleportCmd),
     JumpBack(JumpBackCmd),
     Weather(WeatherCmd),
+    Record(RecordCmd),
+    Realm(RealmCmd),
 }
 
 /// Disconnect From Server
@@ -119,3 +152,24 @@ struct WeatherCmd {
     #[argh(positional)]
     kind: u32,
 }
+
+/// Record the packets of a character into the server capture file
+#[derive(Debug, Clone, PartialEq, FromArgs)]
+#[argh(subcommand, name = "record")]
+struct RecordCmd {
+    /// stop recording instead
+    #[argh(switch)]
+    stop: bool,
+    /// the character id to record, defaults to yourself
+    #[argh(option)]
+    id: Option<u32>,
+}
+
+/// Set the status the realm reports to the account server
+#[derive(Debug, Clone, PartialEq, FromArgs)]
+#[argh(subcommand, name = "realm")]
+struct RealmCmd {
+    /// online, maintenance or locked
+    #[argh(positional)]
+    status: RealmStatus,
+}
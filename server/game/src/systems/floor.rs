@@ -4,7 +4,6 @@ use io::{AsyncReadExt, AsyncWriteExt};
 use num_enum::FromPrimitive;
 use parking_lot::RwLock;
 use primitives::{Point, Size};
-use std::env;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use tokio::fs::File;
@@ -26,15 +25,18 @@ pub struct Floor {
     loaded: AtomicBool,
     /// The path to the map file.
     path: PathBuf,
+    /// Where the `Maps` and `GameMaps` directories are.
+    data_dir: PathBuf,
 }
 
 impl Floor {
-    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+    pub fn new<D: Into<PathBuf>, P: Into<PathBuf>>(data_dir: D, path: P) -> Self {
         Self {
             coordinates: Default::default(),
             boundaries: Default::default(),
             loaded: Default::default(),
             path: path.into(),
+            data_dir: data_dir.into(),
         }
     }
 
@@ -68,7 +70,7 @@ impl Floor {
         if self.loaded() {
             return Ok(());
         }
-        let data_path = PathBuf::from(env::var("DATA_LOCATION")?);
+        let data_path = &self.data_dir;
         let map_path = data_path.join("Maps").join(&self.path);
         trace!("Starting to load map from {}", map_path.display());
         if let Ok(true) = map_path.try_exists() {
@@ -190,7 +192,7 @@ impl Floor {
                     let scene_file_name = std::str::from_utf8(buf)?;
                     // replace backslashes with forward slashes
                     let scene_file_name = scene_file_name.replace("map\\", "").replace('\\', "/");
-                    let data_path = PathBuf::from(env::var("DATA_LOCATION")?);
+                    let data_path = &self.data_dir;
                     let scene_path = data_path.join("GameMaps").join(scene_file_name).canonicalize()?;
                     trace!("Loading scene file {}", scene_path.display());
                     let px = buffer.get_i32_le();
@@ -278,7 +280,7 @@ impl Floor {
                     buffer.put_u16_le(tile.elevation);
                 }
             }
-            let data_path = PathBuf::from(env::var("DATA_LOCATION")?);
+            let data_path = &self.data_dir;
             let map_path = data_path.join("Maps").join(&self.path);
             let f = File::create(map_path).await?;
             let mut writer = io::BufWriter::with_capacity(boundaries.area() as usize, f);
@@ -279,17 +279,16 @@ impl Screen {
     #[tracing::instrument(skip(self, packet), fields(me = self.owner.id(), packet_id = P::PACKET_ID))]
     pub async fn send_message<P>(&self, packet: P) -> Result<(), P::Error>
     where
-        P: PacketEncode + PacketID + Clone,
+        P: PacketEncode + PacketID,
     {
+        // Encode once, every observer shares the same bytes.
+        let packet = packet.encode()?;
         let futures = FuturesUnordered::new();
         self.with_entities(|c| {
             let iter = c.values().filter_map(|v| v.upgrade().and_then(|o| o.owner()));
             for o in iter {
                 let packet = packet.clone();
-                let fut = async move {
-                    o.send(packet).await?;
-                    Result::<_, P::Error>::Ok(())
-                };
+                let fut = async move { o.send(packet).await };
                 futures.push(fut);
             }
         });
@@ -323,8 +322,11 @@ impl Screen {
     #[tracing::instrument(skip(self, state, packet), fields(me = self.owner.id(), packet_id = P::PACKET_ID))]
     pub async fn send_movement<P>(&self, state: &crate::State, packet: P) -> Result<(), Error>
     where
-        P: PacketEncode + PacketID + Clone + Send + Sync + 'static,
+        P: PacketEncode + PacketID,
+        Error: From<P::Error>,
     {
+        // Encode once, every observer shares the same bytes.
+        let packet = packet.encode()?;
         let entity = self.character.load().upgrade().ok_or(Error::CharacterNotFound)?;
         let me = entity.as_character().ok_or(Error::CharacterNotFound)?;
         let mymap = state.try_map(me.entity().map_id())?;
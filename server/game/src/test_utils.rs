@@ -20,7 +20,6 @@ where
     let root_dir = std::str::from_utf8(&root_dir)?.trim();
     let root_dir = std::path::Path::new(root_dir);
     let data_dir = root_dir.join("data");
-    std::env::set_var("DATA_LOCATION", data_dir);
 
     let env_filter = tracing_subscriber::EnvFilter::from_default_env()
         .add_directive(format!("tq_db={}", log_level).parse().unwrap())
@@ -46,7 +45,7 @@ where
         .run(&pool)
         .await
         .expect("Failed to migrate database");
-    let state = crate::State::with_pool(pool).await?;
+    let state = crate::State::with_pool(pool, data_dir).await?;
     let actors = [make_test_actor(&state, 1).await?, make_test_actor(&state, 2).await?];
     f(state, actors).await
 }
@@ -69,6 +68,7 @@ pub async fn make_test_actor(state: &crate::State, id: usize) -> Result<Actor<Ac
     let character = Character::new(actor.handle(), inner_character);
     let screen = Screen::new(actor.handle());
     actor.update(character, screen);
-    state.insert_entity(actor.entity());
+    state.insert_entity(actor.try_entity()?);
+    actor.set_phase(tq_network::Phase::InWorld);
     Ok(actor)
 }
@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use core::fmt;
 use futures::stream::FuturesUnordered;
 use futures::{StreamExt, TryFutureExt};
@@ -5,6 +6,7 @@ use num_enum::{FromPrimitive, IntoPrimitive};
 use parking_lot::RwLock;
 use primitives::{Location, Point, Size};
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::{Arc, Weak};
 use tq_math::SCREEN_DISTANCE;
 use tq_network::{PacketEncode, PacketID};
@@ -42,7 +44,13 @@ pub struct Map {
 }
 
 impl Map {
-    pub fn new(inner: tq_db::map::Map, portals: Vec<tq_db::portal::Portal>, npcs: Vec<tq_db::npc::Npc>) -> Self {
+    /// A new map, its floor gets loaded from `data_dir` once needed.
+    pub fn new(
+        data_dir: &Path,
+        inner: tq_db::map::Map,
+        portals: Vec<tq_db::portal::Portal>,
+        npcs: Vec<tq_db::npc::Npc>,
+    ) -> Self {
         let portals = portals.into_iter().map(Portal::new).collect();
         let npcs = npcs
             .into_iter()
@@ -50,7 +58,7 @@ impl Map {
             .map(|v| (v.id as u32, Arc::new(GameEntity::from(Npc::from(v)))))
             .collect();
         Self {
-            floor: Floor::new(inner.path.clone()),
+            floor: Floor::new(data_dir, inner.path.clone()),
             revive_point: Point::new(inner.revive_point_x as u32, inner.revive_point_y as u32),
             regions: RwLock::new(Vec::new()),
             npcs,
@@ -208,6 +216,12 @@ impl Map {
         Ok(())
     }
 
+    /// Number of entities on this map, NPCs included, zero if it is not
+    /// loaded.
+    pub fn entity_count(&self) -> usize {
+        self.with_regions(|r| r.iter().map(|r| r.with_entities(|e| e.len())).sum())
+    }
+
     /// This method checks if the map is loaded in memory.
     pub fn loaded(&self) -> bool {
         self.floor.loaded() && !self.regions.read().is_empty()
@@ -315,27 +329,21 @@ impl Map {
     #[tracing::instrument(skip(self, packet), fields(map_id = self.id(), packet_id = P::PACKET_ID))]
     pub async fn broadcast<P>(&self, packet: P) -> Result<(), P::Error>
     where
-        P: PacketEncode + PacketID + Clone,
+        P: PacketEncode + PacketID,
     {
+        // Encode once, every region shares the same bytes.
+        let packet = packet.encode()?;
         let futs = FuturesUnordered::new();
         self.with_regions(|regions| {
             let regions = regions.iter().filter(|r| !r.is_empty()).cloned();
             for region in regions {
                 let p = packet.clone();
-                let f = async move { region.broadcast(p).await };
+                let f = async move { region.broadcast_encoded(p).await };
                 futs.push(f);
             }
         });
         // await all futures to complete.
-        futs.for_each_concurrent(None, |res| async {
-            match res {
-                Ok(_) => {},
-                Err(e) => {
-                    tracing::error!(error = ?e, "Failed to broadcast packet");
-                },
-            }
-        })
-        .await;
+        futs.for_each_concurrent(None, |_| async {}).await;
         Ok(())
     }
 
@@ -437,8 +445,18 @@ impl MapRegion {
     #[tracing::instrument(skip(self, packet), fields(region_id = self.id(), packet_id = P::PACKET_ID))]
     pub async fn broadcast<P>(&self, packet: P) -> Result<(), P::Error>
     where
-        P: PacketEncode + PacketID + Clone,
+        P: PacketEncode + PacketID,
     {
+        let packet = packet.encode()?;
+        self.broadcast_encoded(packet).await;
+        Ok(())
+    }
+
+    /// Same as [`MapRegion::broadcast`], but with an already encoded packet,
+    /// so the bytes get shared between all the characters instead of
+    /// encoding the packet for every one of them.
+    #[tracing::instrument(skip(self, packet), fields(region_id = self.id(), packet_id = packet.0))]
+    pub async fn broadcast_encoded(&self, packet: (u16, Bytes)) {
         let futs = FuturesUnordered::new();
         self.with_entities(|entities| {
             for character in entities.values() {
@@ -451,8 +469,12 @@ impl MapRegion {
             }
         });
         // await all futures to complete.
-        futs.for_each_concurrent(None, |_| async {}).await;
-        Ok(())
+        futs.for_each_concurrent(None, |res| async {
+            if let Err(e) = res {
+                tracing::error!(error = ?e, "Failed to broadcast packet");
+            }
+        })
+        .await;
     }
 }
 
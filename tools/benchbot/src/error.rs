@@ -1,9 +1,9 @@
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
-    Auth(#[from] auth::Error),
+    Client(#[from] tq_client::Error),
     #[error(transparent)]
-    Network(#[from] tq_network::Error),
+    Bus(#[from] tq_bus::Error),
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[error(transparent)]
@@ -11,17 +11,11 @@ pub enum Error {
     #[error(transparent)]
     Env(#[from] std::env::VarError),
     #[error(transparent)]
+    Config(#[from] tq_config::Error),
+    #[error(transparent)]
     Sqlx(#[from] sqlx::Error),
     #[error(transparent)]
     Db(#[from] tq_db::Error),
     #[error("Realm not found")]
     RealmNotFound,
-    #[error("Server timed out")]
-    ServerTimedOut,
-    #[error("Invalid password")]
-    InvalidPassword,
-    #[error("Character name already taken")]
-    CharacterNameAlreadyTaken,
-    #[error("Token not found")]
-    AccountTokenNotFound,
 }
@@ -2,35 +2,34 @@ mod error;
 mod state;
 
 use core::time::Duration;
-use std::env;
 
 use error::Error;
 use futures::stream::FuturesUnordered;
-use game::packets::{self, ActionType, TalkChannel, TalkStyle};
+use game::packets::{self, ActionType};
 use game::utils::LoHi;
-use game::{constants, utils};
 use rand::{Rng, SeedableRng};
 use tokio::net::TcpStream;
-use tokio_stream::StreamExt;
-use tq_codec::{TQCodec, TQEncoder};
-use tq_crypto::{CQCipher, Cipher};
+use tq_client::session::{Event, GameClient, Login};
+use tq_client::transfer;
 use tq_db::account::Account;
 use tq_db::realm::Realm;
-use tq_network::{PacketDecode, PacketEncode, PacketID};
 
 const NUM_OF_BOTS: i64 = 1200;
 const MAX_ACTION_DELAY: Duration = Duration::from_millis(300);
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    dotenvy::dotenv()?;
-    let log_verbosity = env::var("LOG_VERBOSITY")
-        .map(|s| s.parse::<i32>().unwrap_or(2))
-        .unwrap_or(2);
-    setup_logger(log_verbosity)?;
-    let state = state::State::init().await?;
+    let args = tq_config::Args::from_env();
+    dotenvy::dotenv().ok();
+    let config = tq_config::Config::load(args.config.as_deref())?;
+    if args.check_config {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+    setup_logger(config.log.verbosity)?;
+    let state = state::State::init(&config).await?;
     let accounts = create_or_get_accounts(&state).await?;
-    let maybe_realm = Realm::by_name(state.pool(), "CoEmu").await?;
+    let maybe_realm = Realm::by_name(state.pool(), &config.game.realm).await?;
     let local_ip = local_ip_address::local_ip().expect("local ip");
     // Check if there is a realm with that name
     let realm = match maybe_realm {
@@ -39,122 +38,65 @@ async fn main() -> Result<(), Error> {
             return Err(Error::RealmNotFound);
         },
     };
+    // Ask the realm for the login tokens over its bus, like the account
+    // server does.
+    let stream = TcpStream::connect(format!("{local_ip}:{}", realm.bus_port)).await?;
+    let bus = tq_bus::Client::connect(stream, &config.bus.secret()?).await?;
     let tasks = FuturesUnordered::new();
     for account in accounts {
         let realm = realm.clone();
-        let state = state.clone();
+        let bus = bus.clone();
         let task = tokio::spawn(async move {
-            // Try to connect to that realm's RPC first.
-            // let ip = realm.game_ip_address.as_str();
             let port = realm.game_port;
-            let stream = TcpStream::connect(format!("{local_ip}:{port}")).await;
-            let stream = match stream {
-                Ok(s) => s,
-                Err(e) => {
-                    return Err(e.into());
-                },
-            };
-            let cipher = CQCipher::new();
-            let (mut encoder, mut decoder) =
-                TQCodec::new(stream, cipher).split();
-            let transfer = auth::packets::MsgTransfer {
-                account_id: account.account_id as u32,
-                realm_id: realm.realm_id as u32,
-                ..Default::default()
-            };
-
-            let transfer = transfer.encode()?;
-            encoder.send(transfer).await?;
-            let res = decoder.next().await;
-            let res = match res {
-                Some(Ok((_, bytes))) => {
-                    auth::packets::MsgTransfer::decode(&bytes)?
-                },
-                Some(Err(e)) => return Err(e.into()),
-                None => {
-                    return Err(Error::ServerTimedOut);
-                },
-            };
-            // Close the connection and connect to the new realm.
-            encoder.close().await?;
+            // The bots connect from wherever, the token is good for any
+            // address.
+            let token = transfer::request_token(
+                &bus,
+                account.account_id as u32,
+                realm.realm_id as u32,
+                None,
+            )
+            .await?;
             tracing::info!(?account.name, ?realm.name, "Connected to realm");
-            let port = realm.game_port;
-            let stream =
-                TcpStream::connect(format!("127.0.0.1:{port}")).await?;
-            let cipher = CQCipher::new();
-            let (mut encoder, mut decoder) =
-                TQCodec::new(stream, cipher.clone()).split();
-            encoder
-                .send(
-                    packets::MsgConnect {
-                        token: res.token,
-                        build_version: 123,
-                        language: String::from("En").into(),
-                        file_contents: 10,
-                    }
-                    .encode()?,
-                )
-                .await?;
-            state.tokens().write().insert(account.account_id, res.token);
-            cipher.generate_keys(res.token);
-            while let Some(packet) = decoder.next().await {
-                let (id, bytes) = packet?;
-                match id {
-                    packets::MsgTalk::PACKET_ID => {
-                        handle_msg_talk(
-                            &state,
-                            &account,
-                            &mut encoder,
-                            packets::MsgTalk::decode(&bytes)?,
+            let (mut client, login) =
+                GameClient::connect(format!("127.0.0.1:{port}"), token).await?;
+            match login {
+                Login::Ready => {
+                    tracing::info!(?account.name, "Logged in");
+                },
+                Login::NewRole => {
+                    tracing::info!(?account.name, "Creating character");
+                    let name = format!("bot{}", account.account_id);
+                    client
+                        .register(
+                            &name,
+                            packets::BaseClass::Trojan,
+                            packets::BodyType::AgileMale,
                         )
                         .await?;
+                    tracing::info!(?account.name, "Account created");
+                },
+            }
+            while let Some(event) = client.next_event().await? {
+                match event {
+                    Event::Action(msg) => {
+                        do_random_stuff(&account, &mut client, msg).await?;
                     },
-                    packets::MsgAction::PACKET_ID => {
-                        let msg = packets::MsgAction::decode(&bytes)?;
-                        do_random_stuff(&state, &account, &mut encoder, msg)
-                            .await?;
-                    },
-                    packets::MsgUserInfo::PACKET_ID => {
-                        let msg = packets::MsgUserInfo::decode(&bytes)?;
+                    Event::UserInfo(msg) => {
                         tracing::trace!(?msg, "Received MsgUserInfo packet");
-                        let msg_leave_booth = packets::MsgAction {
-                            client_timestamp: utils::current_ts(),
-                            character_id: msg.character_id as _,
-                            data1: 0,
-                            data2: 0,
-                            details: 0,
-                            action_type: ActionType::LeaveBooth.into(),
-                        };
-                        encoder.send(msg_leave_booth.encode()?).await?;
-                        let msg_setlocation = packets::MsgAction {
-                            client_timestamp: utils::current_ts(),
-                            character_id: msg.character_id as _,
-                            data1: 0,
-                            data2: 0,
-                            details: 0,
-                            action_type: ActionType::SendLocation.into(),
-                        };
-                        encoder.send(msg_setlocation.encode()?).await?;
-                        do_random_stuff(
-                            &state,
-                            &account,
-                            &mut encoder,
-                            msg_setlocation,
-                        )
-                        .await?;
+                        client.send_location().await?;
                     },
-                    packets::MsgPlayer::PACKET_ID => {
-                        let msg = packets::MsgPlayer::decode(&bytes)?;
+                    Event::Player(msg) => {
                         tracing::trace!(%msg.character_name, "We now see other players");
                     },
-                    _ => {
-                        tracing::trace!(%id, "Received unknown packet");
+                    event => {
+                        tracing::trace!(?event, "Unhandled event");
                     },
                 }
             }
             tracing::debug!(?account.name, ?realm.name, "Disconnected");
-            encoder.close().await?;
-            Ok(())
+            client.close().await?;
+            Ok::<_, Error>(())
         });
         tasks.push(task);
     }
@@ -190,58 +132,9 @@ async fn create_or_get_accounts(
     Ok(accounts)
 }
 
-async fn handle_msg_talk(
-    state: &state::State,
-    account: &Account,
-    encoder: &mut TQEncoder<TcpStream, CQCipher>,
-    msg: packets::MsgTalk,
-) -> Result<(), Error> {
-    tracing::trace!(?msg, "Received MsgTalk packet");
-    match msg.channel.into() {
-        TalkChannel::Login if msg.message.contains("Invalid") => {
-            tracing::warn!(?account.name, ?msg, "Invalid password");
-            return Err(Error::InvalidPassword);
-        },
-        TalkChannel::Login if msg.message.eq(constants::ANSWER_OK) => {
-            tracing::info!(?account.name, "Logged in");
-        },
-        TalkChannel::Login if msg.message.eq(constants::NEW_ROLE) => {
-            // Create a new character
-            tracing::info!(?account.name, "Creating character");
-            let token = state
-                .tokens()
-                .read()
-                .get(&account.account_id)
-                .cloned()
-                .ok_or(Error::AccountTokenNotFound)?;
-            let msg_register = packets::MsgRegister {
-                character_name: format!("bot{}", account.account_id).into(),
-                class: packets::BaseClass::Trojan.into(),
-                mesh: packets::BodyType::AgileMale.into(),
-                token: token as _,
-                ..Default::default()
-            };
-            tracing::debug!(?msg_register, "Sending MsgRegister packet");
-            encoder.send(msg_register.encode()?).await?;
-        },
-        TalkChannel::Register if msg.message.contains("taken") => {
-            tracing::warn!(?account.name, ?msg, "Account already exists");
-            return Err(Error::CharacterNameAlreadyTaken);
-        },
-        TalkChannel::Register if msg.message.eq(constants::ANSWER_OK) => {
-            tracing::info!(?account.name, "Account created");
-        },
-        _ => {
-            tracing::trace!(?account.name, ?msg, "Unhandled message");
-        },
-    }
-    Ok(())
-}
-
 async fn do_random_stuff(
-    _state: &state::State,
     account: &Account,
-    encoder: &mut TQEncoder<TcpStream, CQCipher>,
+    client: &mut GameClient,
     msg: packets::MsgAction,
 ) -> Result<(), Error> {
     let (w, h) = (70, 70);
@@ -257,20 +150,7 @@ async fn do_random_stuff(
             }
             let x = rng.gen_range(35..60);
             let y = rng.gen_range(35..60);
-            let msg_cmd = packets::MsgTalk {
-                color: 0x00FF_FFFF,
-                channel: TalkChannel::Talk.into(),
-                style: TalkStyle::Normal.into(),
-                character_id: msg.character_id as _,
-                recipient_mesh: 0,
-                sender_mesh: 0,
-                list_count: 4,
-                sender_name: String::new(),
-                recipient_name: constants::ALL_USERS.to_string(),
-                suffix: String::new(),
-                message: format!("$tele {target_map_id} {x} {y}"),
-            };
-            encoder.send(msg_cmd.encode()?).await?;
+            client.say(format!("$tele {target_map_id} {x} {y}")).await?;
         },
         ActionType::Teleport => {
             // Maybe that was an invalid move, try again
@@ -287,12 +167,9 @@ async fn do_random_stuff(
                     break (x, y);
                 }
             };
-            let mut jump = msg.clone();
-            jump.action_type = ActionType::Jump.into();
-            jump.data1 = u32::constract(y, x);
             // Simulate a delay before sending the packet
             tokio::time::sleep(MAX_ACTION_DELAY).await;
-            encoder.send(jump.encode()?).await?;
+            client.jump(x, y).await?;
         },
         ActionType::Jump => {
             // that was a valid move, now jump again.
@@ -309,10 +186,8 @@ async fn do_random_stuff(
                     break (x, y);
                 }
             };
-            let mut jump = msg.clone();
-            jump.data1 = u32::constract(y, x);
             tokio::time::sleep(MAX_ACTION_DELAY).await;
-            encoder.send(jump.encode()?).await?;
+            client.jump(x, y).await?;
         },
         others => {
             tracing::debug!(
@@ -326,7 +201,7 @@ async fn do_random_stuff(
     Ok(())
 }
 
-fn setup_logger(verbosity: i32) -> Result<(), Error> {
+fn setup_logger(verbosity: u8) -> Result<(), Error> {
     use tracing::Level;
     let log_level = match verbosity {
         0 => Level::ERROR,
@@ -1,8 +1,7 @@
 use bytes::{Buf, Bytes};
 use std::collections::HashMap;
+use std::fs;
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
-use std::{env, fs};
 
 #[allow(unused)]
 #[derive(Debug, serde::Deserialize, Clone)]
@@ -51,11 +50,17 @@ struct Portal {
 }
 
 fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv()?;
-    let data_path = env::var("DATA_LOCATION")?;
-    let dat_path = PathBuf::from(&data_path).join("GameMaps").join("GameMap.dat");
-    let maps_csv = PathBuf::from(&data_path).join("Maps").join("Maps.csv");
-    let portals_csv = PathBuf::from(&data_path).join("Maps").join("Portals.csv");
+    let args = tq_config::Args::from_env();
+    dotenvy::dotenv().ok();
+    let config = tq_config::Config::load(args.config.as_deref())?;
+    if args.check_config {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+    let data_path = &config.data_dir;
+    let dat_path = data_path.join("GameMaps").join("GameMap.dat");
+    let maps_csv = data_path.join("Maps").join("Maps.csv");
+    let portals_csv = data_path.join("Maps").join("Portals.csv");
     let csv_reader = csv::ReaderBuilder::new().has_headers(true).from_path(maps_csv)?;
     let mut maps = csv_reader
         .into_deserialize::<Map>()
@@ -0,0 +1,237 @@
+//! Compares the recorded server packets with the replayed ones.
+//!
+//! Packets are matched by id and order, the n-th recorded packet of an id is
+//! compared with the n-th received packet of the same id, so unrelated
+//! packets arriving in a different order are not counted as differences.
+
+use bytes::Bytes;
+use game::packets::{MsgAction, MsgData, MsgItem, PACKETS};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use tq_codec::capture::CaptureRecord;
+use tq_network::{PacketID, PacketInfo};
+
+/// Byte range of the packet body that changes on every run, like timestamps.
+fn volatile_range(packet_id: u16) -> Option<Range<usize>> {
+    match packet_id {
+        // client timestamp, echoed back on ping.
+        MsgItem::PACKET_ID => Some(12..16),
+        MsgAction::PACKET_ID => Some(0..4),
+        // the server date and time.
+        MsgData::PACKET_ID => Some(4..28),
+        _ => None,
+    }
+}
+
+fn masked(packet_id: u16, body: &Bytes) -> Vec<u8> {
+    let mut body = body.to_vec();
+    if let Some(range) = volatile_range(packet_id) {
+        let end = range.end.min(body.len());
+        let start = range.start.min(end);
+        body[start..end].fill(0);
+    }
+    body
+}
+
+fn packet_info(packet_id: u16) -> Option<&'static PacketInfo> {
+    PACKETS.iter().find(|p| p.id == packet_id)
+}
+
+/// The packet id, with its name if it is a known one.
+struct Label(u16);
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match packet_info(self.0) {
+            Some(info) => write!(f, "{} #{}", info.name, self.0),
+            None => write!(f, "#{}", self.0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Difference {
+    /// Recorded, but never received.
+    Missing { packet_id: u16, index: usize },
+    /// Received, but never recorded.
+    Extra { packet_id: u16, index: usize },
+    /// Received with different content, `offset` is the first different byte.
+    Changed {
+        packet_id: u16,
+        index: usize,
+        offset: usize,
+        expected_len: usize,
+        received_len: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub compared: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for d in &self.differences {
+            match d {
+                Difference::Missing { packet_id, index } => {
+                    writeln!(f, "- {}[{index}] missing", Label(*packet_id))?;
+                },
+                Difference::Extra { packet_id, index } => {
+                    writeln!(f, "+ {}[{index}] unexpected", Label(*packet_id))?;
+                },
+                Difference::Changed {
+                    packet_id,
+                    index,
+                    offset,
+                    expected_len,
+                    received_len,
+                } => {
+                    write!(f, "~ {}[{index}] differs at byte {offset}", Label(*packet_id))?;
+                    if let Some(field) = packet_info(*packet_id).and_then(|p| p.field_at(*offset)) {
+                        write!(f, " (`{}`)", field.name)?;
+                    }
+                    writeln!(f, " (length {expected_len} -> {received_len})")?;
+                },
+            }
+        }
+        writeln!(
+            f,
+            "Compared {} packets, {} difference(s).",
+            self.compared,
+            self.differences.len()
+        )
+    }
+}
+
+pub fn compare(expected: &[CaptureRecord], received: &[(u16, Bytes)], ignored: &BTreeSet<u16>) -> Report {
+    let mut by_id: BTreeMap<u16, (Vec<&Bytes>, Vec<&Bytes>)> = BTreeMap::new();
+    for r in expected.iter().filter(|r| !ignored.contains(&r.packet_id)) {
+        by_id.entry(r.packet_id).or_default().0.push(&r.body);
+    }
+    for (id, body) in received.iter().filter(|(id, _)| !ignored.contains(id)) {
+        by_id.entry(*id).or_default().1.push(body);
+    }
+    let mut report = Report::default();
+    for (packet_id, (expected, received)) in by_id {
+        for index in 0..expected.len().max(received.len()) {
+            report.compared += 1;
+            let difference = match (expected.get(index), received.get(index)) {
+                (Some(_), None) => Difference::Missing { packet_id, index },
+                (None, Some(_)) => Difference::Extra { packet_id, index },
+                (Some(a), Some(b)) => {
+                    let (a, b) = (masked(packet_id, a), masked(packet_id, b));
+                    if a == b {
+                        continue;
+                    }
+                    let offset = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+                    Difference::Changed {
+                        packet_id,
+                        index,
+                        offset,
+                        expected_len: a.len(),
+                        received_len: b.len(),
+                    }
+                },
+                (None, None) => unreachable!("index is within one of them"),
+            };
+            report.differences.push(difference);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tq_codec::capture::Direction;
+
+    fn record(packet_id: u16, body: &[u8]) -> CaptureRecord {
+        CaptureRecord {
+            direction: Direction::ServerToClient,
+            session: 1,
+            actor_id: 1,
+            timestamp: Duration::ZERO,
+            packet_id,
+            body: Bytes::copy_from_slice(body),
+        }
+    }
+
+    fn packet(packet_id: u16, body: &[u8]) -> (u16, Bytes) {
+        (packet_id, Bytes::copy_from_slice(body))
+    }
+
+    #[test]
+    fn volatile_bytes_are_masked() {
+        let mut now = [7u8; 32];
+        now[4..28].fill(1);
+        let mut later = [7u8; 32];
+        later[4..28].fill(2);
+        let report = compare(
+            &[record(MsgData::PACKET_ID, &now)],
+            &[packet(MsgData::PACKET_ID, &later)],
+            &BTreeSet::new(),
+        );
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.compared, 1);
+    }
+
+    #[test]
+    fn changes_outside_the_mask_are_reported() {
+        let expected = [7u8; 32];
+        let mut received = expected;
+        received[28] = 8;
+        let report = compare(
+            &[record(MsgData::PACKET_ID, &expected)],
+            &[packet(MsgData::PACKET_ID, &received)],
+            &BTreeSet::new(),
+        );
+        assert!(matches!(
+            report.differences[..],
+            [Difference::Changed {
+                packet_id: MsgData::PACKET_ID,
+                index: 0,
+                offset: 28,
+                expected_len: 32,
+                received_len: 32,
+            }]
+        ));
+    }
+
+    #[test]
+    fn missing_and_extra_packets_are_reported() {
+        let report = compare(
+            &[record(1, b"a"), record(1, b"b")],
+            &[packet(1, b"a"), packet(2, b"c")],
+            &BTreeSet::new(),
+        );
+        assert_eq!(report.compared, 3);
+        assert!(matches!(
+            report.differences[..],
+            [
+                Difference::Missing { packet_id: 1, index: 1 },
+                Difference::Extra { packet_id: 2, index: 0 }
+            ]
+        ));
+    }
+
+    #[test]
+    fn ignored_packets_are_skipped() {
+        let report = compare(
+            &[record(1, b"a"), record(2, b"b")],
+            &[packet(1, b"a"), packet(2, b"c"), packet(3, b"d")],
+            &BTreeSet::from([2, 3]),
+        );
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.compared, 1);
+    }
+}
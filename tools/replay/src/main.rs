@@ -0,0 +1,165 @@
+//! Replays a captured game session (see [`tq_codec::capture`]) against a
+//! running game server, then compares what the server sent back with what
+//! was recorded.
+//!
+//! The recorded login token is long gone, so we ask the game server for a
+//! new one over its bus (like the account server does, see [`tq_bus`]), and
+//! use it in place of the old one in `MsgConnect` and `MsgRegister`.
+//!
+//! Replaying only makes sense against a database with the same state as the
+//! one used while recording (same account, same character).
+
+mod diff;
+
+use anyhow::{bail, Context};
+use argh::FromArgs;
+use bytes::Bytes;
+use game::packets::{MsgConnect, MsgRegister};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tq_codec::capture::{CaptureReader, CaptureRecord, Direction};
+use tq_codec::TQCodec;
+use tq_crypto::{CQCipher, Cipher};
+use tq_network::{PacketDecode, PacketEncode, PacketID};
+
+/// Replay a captured session against a game server
+#[derive(Debug, FromArgs)]
+struct Args {
+    /// the capture file
+    #[argh(positional)]
+    capture: PathBuf,
+    /// the game server address
+    #[argh(option, default = "String::from(\"127.0.0.1:5816\")")]
+    server: String,
+    /// the session to replay, defaults to the first one that logs in
+    #[argh(option)]
+    session: Option<u32>,
+    /// the game server bus address, to get a new login token
+    #[argh(option, default = "String::from(\"127.0.0.1:5817\")")]
+    bus: String,
+    /// the bus secret, `BUS_SECRET` by default
+    #[argh(option)]
+    bus_secret: Option<String>,
+    /// the realm id used to get a new login token
+    #[argh(option, default = "1")]
+    realm_id: u32,
+    /// replay speed, 2.0 replays twice as fast, 0 sends everything without
+    /// waiting
+    #[argh(option, default = "1.0")]
+    speed: f64,
+    /// how long to wait for the server responses after the last packet, in
+    /// milliseconds
+    #[argh(option, default = "2000")]
+    settle_ms: u64,
+    /// packet ids to leave out of the comparison, could be repeated
+    #[argh(option)]
+    ignore: Vec<u16>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let args: Args = argh::from_env();
+    let file = File::open(&args.capture).with_context(|| format!("opening {}", args.capture.display()))?;
+    let records = CaptureReader::new(BufReader::new(file))?.collect::<Result<Vec<_>, _>>()?;
+    let session = match args.session {
+        Some(session) => session,
+        None => records
+            .iter()
+            .find(|r| r.direction == Direction::ClientToServer && r.packet_id == MsgConnect::PACKET_ID)
+            .map(|r| r.session)
+            .context("no session logs in, try --session")?,
+    };
+    let records: Vec<_> = records.into_iter().filter(|r| r.session == session).collect();
+    // MsgConnect sets the actor id to the account id.
+    let account_id = records
+        .iter()
+        .map(|r| r.actor_id)
+        .find(|id| *id != 0)
+        .context("session never logged in")?;
+    let (sent, expected): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|r| r.direction == Direction::ClientToServer);
+    println!(
+        "Replaying session #{session} of account #{account_id}: {} packets sent, {} packets expected",
+        sent.len(),
+        expected.len()
+    );
+
+    let token = login_token(&args, account_id).await?;
+    let received = replay(&args, &sent, token).await?;
+    let ignored: BTreeSet<_> = args.ignore.into_iter().collect();
+    let report = diff::compare(&expected, &received, &ignored);
+    print!("{report}");
+    if !report.is_clean() {
+        bail!("server responses differ from the capture");
+    }
+    Ok(())
+}
+
+/// Asks the game server for a new login token, the same way the account
+/// server does.
+async fn login_token(args: &Args, account_id: u32) -> anyhow::Result<u64> {
+    let secret = match &args.bus_secret {
+        Some(secret) => secret.clone(),
+        None => std::env::var("BUS_SECRET").context("no bus secret, try --bus-secret")?,
+    };
+    let stream = TcpStream::connect(&args.bus).await?;
+    let bus = tq_bus::Client::connect(stream, &tq_bus::Secret::new(secret)).await?;
+    // We could be behind a NAT, the token is good for any address.
+    let token = tq_client::transfer::request_token(&bus, account_id, args.realm_id, None).await?;
+    Ok(token)
+}
+
+/// Sends the client packets, respecting the recorded timing, and collects
+/// everything the server sends back.
+async fn replay(args: &Args, sent: &[CaptureRecord], token: u64) -> anyhow::Result<Vec<(u16, Bytes)>> {
+    let stream = TcpStream::connect(&args.server).await?;
+    let cipher = CQCipher::new();
+    let (mut encoder, mut decoder) = TQCodec::new(stream, cipher.clone()).split();
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Some(packet) = decoder.next().await {
+            received.push(packet?);
+        }
+        std::io::Result::Ok(received)
+    });
+
+    let start = Instant::now();
+    let first = sent.first().map(|r| r.timestamp).unwrap_or_default();
+    for record in sent {
+        if args.speed > 0.0 {
+            let offset = (record.timestamp - first).div_f64(args.speed);
+            tokio::time::sleep_until(start + offset).await;
+        }
+        let body = match record.packet_id {
+            MsgConnect::PACKET_ID => {
+                let mut msg = MsgConnect::decode(&record.body)?;
+                msg.token = token;
+                msg.encode()?.1
+            },
+            MsgRegister::PACKET_ID => {
+                let mut msg = MsgRegister::decode(&record.body)?;
+                // The creation token is the lower half of the login token.
+                msg.token = token as u32;
+                msg.encode()?.1
+            },
+            _ => record.body.clone(),
+        };
+        encoder.send((record.packet_id, body)).await?;
+        if record.packet_id == MsgConnect::PACKET_ID {
+            cipher.generate_keys(token);
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(args.settle_ms)).await;
+    encoder.close().await?;
+    let received = tokio::time::timeout(Duration::from_secs(10), reader)
+        .await
+        .context("server did not close the connection")???;
+    Ok(received)
+}